@@ -22,3 +22,81 @@ Create chart name and version as used by the chart label.
       key: {{ $name }}
 {{- end }}
 {{- end -}}
+
+{{/*
+Image reference, pinned to the resolved digest when shipcat found one.
+*/}}
+{{- define "image-ref" -}}
+{{- if .imageDigest -}}
+{{ .image }}@{{ .imageDigest }}
+{{- else -}}
+{{ .image }}:{{ .version }}
+{{- end -}}
+{{- end -}}
+
+{{/*
+HPA spec in autoscaling/v2beta2 form from shipcat's autoScaling (v2beta1 style metrics).
+*/}}
+{{- define "hpa-spec" -}}
+minReplicas: {{ .minReplicas }}
+maxReplicas: {{ .maxReplicas }}
+{{- if .metrics }}
+metrics:
+{{- range $m := .metrics }}
+- type: {{ $m.type }}
+{{- if eq $m.type "Resource" }}
+  resource:
+    name: {{ $m.resource.name }}
+    target:
+{{- if $m.resource.targetAverageUtilization }}
+      type: Utilization
+      averageUtilization: {{ $m.resource.targetAverageUtilization }}
+{{- else }}
+      type: AverageValue
+      averageValue: {{ $m.resource.targetAverageValue | quote }}
+{{- end }}
+{{- else if eq $m.type "Pods" }}
+  pods:
+    metric:
+      name: {{ $m.pods.metricName }}
+    target:
+      type: AverageValue
+      averageValue: {{ $m.pods.targetAverageValue | quote }}
+{{- else if eq $m.type "Object" }}
+  object:
+    describedObject:
+{{ toYaml $m.object.target | indent 6 }}
+    metric:
+      name: {{ $m.object.metricName }}
+    target:
+{{- if $m.object.averageValue }}
+      type: AverageValue
+      averageValue: {{ $m.object.averageValue | quote }}
+{{- else }}
+      type: Value
+      value: {{ $m.object.targetValue | quote }}
+{{- end }}
+{{- else if eq $m.type "External" }}
+  external:
+    metric:
+      name: {{ $m.external.metricName }}
+{{- if $m.external.metricSelector }}
+      selector:
+{{ toYaml $m.external.metricSelector | indent 8 }}
+{{- end }}
+    target:
+{{- if $m.external.targetAverageValue }}
+      type: AverageValue
+      averageValue: {{ $m.external.targetAverageValue | quote }}
+{{- else }}
+      type: Value
+      value: {{ $m.external.targetValue | quote }}
+{{- end }}
+{{- end }}
+{{- end }}
+{{- end }}
+{{- if .behavior }}
+behavior:
+{{ toYaml .behavior | indent 2 }}
+{{- end }}
+{{- end -}}
@@ -11,6 +11,10 @@ limits:
 - name: redis-sidecar
   image: redis:4
   imagePullPolicy: IfNotPresent
+{{- if .securityContext }}
+  securityContext:
+{{ toYaml .securityContext | indent 4 }}
+{{- end }}
   resources:
 {{- if .resources }}
 {{ toYaml .resources | indent 4 }}
@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::env;
 
 use serde::Serialize;
 
@@ -6,14 +7,23 @@ use url::Url;
 use chrono::{Utc, SecondsFormat};
 
 use crate::webhooks::UpgradeState;
-use super::{Result, ResultExt, ErrorKind};
+use crate::delivery::{self, Delivery};
+use super::Result;
 use super::{AuditWebhook};
-use crate::helm::direct::UpgradeData;
+use crate::helm::direct::{UpgradeData, UpgradeMode};
+use crate::helm::helpers::DiffSummary;
+
+/// Version of the audit event schema
+///
+/// Bumped whenever fields are added to or changed in events or payloads.
+pub const AUDIT_SCHEMA_VERSION: u32 = 4;
 
 /// Payload that gets sent via audit webhook
 #[derive(Serialize, Clone)]
 pub struct AuditEvent<T>
 where T: Serialize + Clone + AuditType {
+    /// Version of the schema the event follows
+    pub schema_version: u32,
     /// Payload type
     #[serde(rename = "type")]
     pub domain_type: String,
@@ -35,6 +45,7 @@ where T: Serialize + Clone + AuditType {
     /// Timestamped payload skeleton
     pub fn new(whc: &BTreeMap<String, String>, status: &UpgradeState, payload: T) -> Self {
         AuditEvent{
+            schema_version: AUDIT_SCHEMA_VERSION,
             domain_type: AuditType::get_domain_type(&payload),
             timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
             status: status.clone(),
@@ -50,6 +61,60 @@ pub trait AuditType {
     fn get_domain_type(&self) -> String;
 }
 
+/// Who or what started an action
+#[derive(Serialize, Clone, Debug)]
+pub struct AuditInitiator {
+    /// `jenkins`, `circleci` or `user`
+    pub kind: String,
+    /// Job name and number, or user name
+    pub name: String,
+    /// Link to the job if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+impl AuditInitiator {
+    /// Infer the initiator from the environment
+    pub fn infer() -> Self {
+        if let (Ok(url), Ok(name), Ok(nr)) = (env::var("BUILD_URL"),
+                                              env::var("JOB_NAME"),
+                                              env::var("BUILD_NUMBER")) {
+            AuditInitiator { kind: "jenkins".into(), name: format!("{}#{}", name, nr), link: Some(url) }
+        } else if let (Ok(url), Ok(name), Ok(nr)) = (env::var("CIRCLE_BUILD_URL"),
+                                                     env::var("CIRCLE_JOB"),
+                                                     env::var("CIRCLE_BUILD_NUM")) {
+            AuditInitiator { kind: "circleci".into(), name: format!("{}#{}", name, nr), link: Some(url) }
+        } else {
+            let name = env::var("USER").unwrap_or_else(|_| "unknown".into());
+            AuditInitiator { kind: "user".into(), name, link: None }
+        }
+    }
+}
+
+/// Details of a rollback after a failed upgrade
+#[derive(Serialize, Clone, Debug)]
+pub struct AuditRollback {
+    /// Version that failed to roll out
+    pub failed_version: String,
+    /// Version rolled back to (if known)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restored_version: Option<String>,
+}
+
+/// Deploy policy exceptions and approvals behind an action
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct AuditOverride {
+    /// Freeze windows that were overridden
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub freezes: Vec<String>,
+    /// Emergency reason given for the override
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Approval token or ticket reference
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approval: Option<String>,
+}
+
 #[derive(Serialize, Clone)]
 pub struct AuditDeploymentPayload {
     id: String,
@@ -58,6 +123,27 @@ pub struct AuditDeploymentPayload {
     manifests_revision: String,
     service: String,
     version: String,
+    /// Version running before the upgrade
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_version: Option<String>,
+    /// How the upgrade was run
+    mode: UpgradeMode,
+    /// Changed resources, keys and line counts of the helm diff
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<DiffSummary>,
+    initiator: AuditInitiator,
+    /// Wall-clock time since the upgrade started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    /// Set for rollback events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rollback: Option<AuditRollback>,
+    /// Image digest the version was pinned to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_digest: Option<String>,
+    /// Set when the region's deploy policy was overridden or approved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy_override: Option<AuditOverride>,
 }
 
 #[derive(Serialize, Clone)]
@@ -66,15 +152,39 @@ pub struct AuditReconciliationPayload {
     region: String,
     /// Eg Git SHA
     manifests_revision: String,
+    /// Set when the region's deploy policy was overridden or approved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    policy_override: Option<AuditOverride>,
 }
 
 impl AuditDeploymentPayload {
-    pub fn new(whc: &BTreeMap<String, String>, ud: &UpgradeData) -> Self {
+    /// Payload for an upgrade event, or a rollback event if `rollback` is set
+    ///
+    /// Only a summary of the diff is sent; values can hold secrets from outside the manifest.
+    pub fn new(whc: &BTreeMap<String, String>, ud: &UpgradeData, rollback: bool) -> Self {
         let (service, region, version) = (ud.name.clone(), ud.region.clone(), ud.version.clone());
         let manifests_revision = whc["SHIPCAT_AUDIT_REVISION"].clone();
+        let duration_ms = ud.started.map(|s| {
+            let d = s.elapsed();
+            d.as_secs() * 1000 + u64::from(d.subsec_millis())
+        });
+        let rollback = if rollback {
+            Some(AuditRollback {
+                failed_version: ud.version.clone(),
+                restored_version: ud.restored_version().cloned(),
+            })
+        } else {
+            None
+        };
         Self {
             id: format!("{}-{}-{}-{}", manifests_revision, region, service, version),
-            manifests_revision, region, service, version,
+            previous_version: ud.previous_version.clone(),
+            mode: ud.mode.clone(),
+            diff: Some(DiffSummary::new(&ud.diff)).filter(|d| !d.is_empty()),
+            initiator: AuditInitiator::infer(),
+            image_digest: ud.digest.clone(),
+            policy_override: ud.policy_override.clone(),
+            manifests_revision, region, service, version, duration_ms, rollback,
         }
     }
 }
@@ -86,11 +196,12 @@ impl AuditType for AuditDeploymentPayload {
 }
 
 impl AuditReconciliationPayload {
-    pub fn new(whc: &BTreeMap<String, String>, r: &str) -> Self {
+    pub fn new(whc: &BTreeMap<String, String>, r: &str, ovr: Option<&AuditOverride>) -> Self {
         let manifests_revision = whc["SHIPCAT_AUDIT_REVISION"].clone();
         let region = r.into();
         Self {
             id: format!("{}-{}", manifests_revision, region),
+            policy_override: ovr.cloned(),
             manifests_revision, region,
         }
     }
@@ -102,28 +213,18 @@ impl AuditType for AuditReconciliationPayload {
     }
 }
 
-pub fn audit_deployment(us: &UpgradeState, ud: &UpgradeData, audcfg: &AuditWebhook, whc: BTreeMap<String, String>) -> Result<()> {
-    let ae = AuditEvent::new(&whc, &us, AuditDeploymentPayload::new(&whc, &ud));
+pub fn audit_deployment(us: &UpgradeState, ud: &UpgradeData, rollback: bool, audcfg: &AuditWebhook, whc: BTreeMap<String, String>) -> Result<()> {
+    let ae = AuditEvent::new(&whc, &us, AuditDeploymentPayload::new(&whc, &ud, rollback));
     audit(ae, &audcfg)
 }
 
-pub fn audit_reconciliation(us: &UpgradeState, region: &str, audcfg: &AuditWebhook, whc: BTreeMap<String, String>) -> Result<()> {
-    let ae = AuditEvent::new(&whc, &us, AuditReconciliationPayload::new(&whc, region));
+pub fn audit_reconciliation(us: &UpgradeState, region: &str, ovr: Option<&AuditOverride>, audcfg: &AuditWebhook, whc: BTreeMap<String, String>) -> Result<()> {
+    let ae = AuditEvent::new(&whc, &us, AuditReconciliationPayload::new(&whc, region, ovr));
     audit(ae, &audcfg)
 }
 
 fn audit<T: Serialize + Clone + AuditType>(ae: AuditEvent<T>, audcfg: &AuditWebhook) -> Result<()> {
-    let endpoint = &audcfg.url;
-    debug!("event status: {}, url: {:?}", serde_json::to_string(&ae.status)?, endpoint);
-
-    let mkerr = || ErrorKind::Url(endpoint.clone());
-    let client = reqwest::Client::new();
-
-    let _res = client.post(endpoint.clone())
-        .bearer_auth(audcfg.token.clone())
-        .json(&ae)
-        .send()
-        .chain_err(&mkerr)?;
-    // TODO: check _res.is_success if it's a requirement in future
-    Ok(())
+    debug!("event status: {}, url: {:?}", serde_json::to_string(&ae.status)?, audcfg.url);
+    let d = Delivery::bearer(&audcfg.url, &audcfg.token, &ae)?;
+    delivery::deliver(&d, audcfg.retries)
 }
@@ -0,0 +1,268 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::kube;
+use crate::webhooks::UpgradeState;
+use super::{Result, ResultExt};
+use super::{AuditLogWebhook, Region, Webhook};
+
+/// Key in the ConfigMap holding the log
+pub const LOG_KEY: &str = "audit.jsonl";
+
+/// Attempts at appending to a ConfigMap that is being written to concurrently
+const CONFLICT_RETRIES: u32 = 5;
+
+/// Size a ConfigMap log is trimmed to, well under the 1MiB object limit
+const CONFIGMAP_MAX_BYTES: usize = 512 * 1024;
+
+/// Append an audit event as a single json line
+pub fn append<T: Serialize>(event: &T, hook: &AuditLogWebhook, ns: &str) -> Result<()> {
+    let line = serde_json::to_string(event)?;
+    if let Some(path) = &hook.path {
+        append_file(Path::new(path), &line)
+    } else if let Some(name) = &hook.configMap {
+        append_configmap(name, ns, &line)
+    } else {
+        bail!("audit_log webhook needs exactly one of path or configMap")
+    }
+}
+
+fn append_file(pth: &Path, line: &str) -> Result<()> {
+    if let Some(dir) = pth.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(pth)
+        .chain_err(|| format!("could not open audit log {}", pth.display()))?;
+    writeln!(f, "{}", line)?;
+    Ok(())
+}
+
+fn append_configmap(name: &str, ns: &str, line: &str) -> Result<()> {
+    for attempt in 1..=CONFLICT_RETRIES {
+        let (mut cm, create) = match kube::get_configmap(name, ns)? {
+            Some(cm) => (cm, false),
+            None => {
+                let mut cm = Value::Null;
+                cm["apiVersion"] = "v1".into();
+                cm["kind"] = "ConfigMap".into();
+                cm["metadata"]["name"] = name.into();
+                cm["metadata"]["namespace"] = ns.into();
+                (cm, true)
+            }
+        };
+        let log = cm["data"][LOG_KEY].as_str().unwrap_or("");
+        cm["data"][LOG_KEY] = append_capped(log, line, CONFIGMAP_MAX_BYTES).into();
+        // the stale resourceVersion makes replace fail if someone else wrote in between
+        match kube::write_configmap(&cm, ns, create) {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("Failed to append to configmap {} (attempt {}): {}", name, attempt, e),
+        }
+    }
+    bail!("could not append to audit log configmap {} in {}", name, ns)
+}
+
+/// Append a line to a log, dropping the oldest lines to stay within `max` bytes
+///
+/// The new line is always kept.
+fn append_capped(log: &str, line: &str, max: usize) -> String {
+    let mut out = format!("{}{}\n", log, line);
+    if out.len() > max {
+        let excess = out.len() - max;
+        // cut at the first line boundary that frees up enough space
+        let cut = out.match_indices('\n')
+            .map(|(i, _)| i + 1)
+            .find(|&i| i >= excess)
+            .unwrap_or(out.len());
+        let cut = cut.min(log.len());
+        warn!("Trimming {} bytes of old entries from the audit log", cut);
+        out = out.split_off(cut);
+    }
+    out
+}
+
+/// Raw contents of an audit log
+///
+/// Logs that have not been written to yet are empty.
+pub fn read(hook: &AuditLogWebhook, ns: &str) -> Result<String> {
+    if let Some(path) = &hook.path {
+        let pth = Path::new(path);
+        if !pth.exists() {
+            return Ok(String::new());
+        }
+        Ok(fs::read_to_string(pth)?)
+    } else if let Some(name) = &hook.configMap {
+        let cm = kube::get_configmap(name, ns)?;
+        Ok(cm.and_then(|c| c["data"][LOG_KEY].as_str().map(String::from)).unwrap_or_default())
+    } else {
+        bail!("audit_log webhook needs exactly one of path or configMap")
+    }
+}
+
+/// The parts of an audit event that make up a timeline entry
+#[derive(Deserialize, Clone, Debug)]
+pub struct Entry {
+    #[serde(rename = "type")]
+    pub domain_type: String,
+    pub timestamp: DateTime<Utc>,
+    pub status: UpgradeState,
+    pub payload: EntryPayload,
+}
+
+/// Deployment or reconciliation payload fields shown in timelines
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct EntryPayload {
+    pub region: String,
+    pub service: Option<String>,
+    pub version: Option<String>,
+    pub previous_version: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub initiator: Option<EntryInitiator>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct EntryInitiator {
+    pub kind: String,
+    pub name: String,
+}
+
+/// Parse json lines into entries, oldest first
+///
+/// Lines that cannot be parsed are skipped with a warning.
+pub fn parse(log: &str) -> Vec<Entry> {
+    let mut entries = log.lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|l| match serde_json::from_str::<Entry>(l) {
+            Ok(e) => Some(e),
+            Err(e) => {
+                warn!("Skipping unparseable audit log line: {}", e);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|e| e.timestamp);
+    entries
+}
+
+/// Parse a lookback like `30m`, `12h`, `2d` or `1w`
+pub fn parse_since(since: &str) -> Result<Duration> {
+    let s = since.trim();
+    if s.len() < 2 {
+        bail!("invalid duration '{}' - expected eg 30m, 12h, 2d or 1w", since);
+    }
+    let (num, unit) = s.split_at(s.len() - 1);
+    let n : i64 = num.parse().chain_err(|| format!("invalid duration '{}'", since))?;
+    Ok(match unit {
+        "m" => Duration::minutes(n),
+        "h" => Duration::hours(n),
+        "d" => Duration::days(n),
+        "w" => Duration::weeks(n),
+        _ => bail!("invalid duration unit in '{}' - expected one of m, h, d, w", since),
+    })
+}
+
+/// Entries for a region in a time window
+///
+/// Pending events are left out as every upgrade starts with one.
+pub fn timeline(entries: Vec<Entry>, region: &str, after: DateTime<Utc>) -> Vec<Entry> {
+    entries.into_iter()
+        .filter(|e| e.timestamp >= after)
+        .filter(|e| e.payload.region == region)
+        .filter(|e| e.status != UpgradeState::Pending)
+        .collect()
+}
+
+fn format_entry(e: &Entry) -> String {
+    let service = e.payload.service.clone().unwrap_or_else(|| format!("<{}>", e.domain_type));
+    let version = match (&e.payload.previous_version, &e.payload.version) {
+        (Some(prev), Some(v)) if prev != v => format!("{} -> {}", prev, v),
+        (_, Some(v)) => v.clone(),
+        (_, None) => "-".into(),
+    };
+    let status = serde_json::to_string(&e.status).unwrap_or_else(|_| "unknown".into());
+    let duration = e.payload.duration_ms.map(|ms| format!("{}s", ms / 1000)).unwrap_or_else(|| "-".into());
+    let initiator = e.payload.initiator.as_ref()
+        .map(|i| format!("{}:{}", i.kind, i.name))
+        .unwrap_or_else(|| "-".into());
+    format!("{}  {:<30} {:<24} {:<17} {:>6}  {}",
+        e.timestamp.format("%Y-%m-%d %H:%M:%S"),
+        service, version, status.trim_matches('"'), duration, initiator)
+}
+
+/// Print a cross-service timeline of upgrades in a region
+pub fn history(region: &Region, since: &str) -> Result<()> {
+    let after = Utc::now() - parse_since(since)?;
+    let hooks = region.webhooks.iter().flatten().filter_map(|wh| match wh {
+        Webhook::AuditLog(h) => Some(h),
+        _ => None,
+    }).collect::<Vec<_>>();
+    if hooks.is_empty() {
+        bail!("No audit_log webhook configured in {}", region.name);
+    }
+    let mut entries = vec![];
+    for h in hooks {
+        entries.extend(parse(&read(h, &region.namespace)?));
+    }
+    entries.sort_by_key(|e| e.timestamp);
+    let events = timeline(entries, &region.name, after);
+    if events.is_empty() {
+        info!("No events in {} in the last {}", region.name, since);
+    }
+    for e in &events {
+        println!("{}", format_entry(e));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append_capped, parse, parse_since, timeline};
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn auditlog_parse_since() {
+        assert_eq!(parse_since("2d").unwrap(), Duration::days(2));
+        assert_eq!(parse_since("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_since("30m").unwrap(), Duration::minutes(30));
+        assert_eq!(parse_since("1w").unwrap(), Duration::weeks(1));
+        assert!(parse_since("2").is_err());
+        assert!(parse_since("2y").is_err());
+        assert!(parse_since("xd").is_err());
+    }
+
+    #[test]
+    fn auditlog_append_capped() {
+        assert_eq!(append_capped("", "a", 10), "a\n");
+        assert_eq!(append_capped("a\nb\n", "c", 10), "a\nb\nc\n");
+        // oldest whole lines go first
+        assert_eq!(append_capped("aaa\nbbb\n", "ccc", 10), "bbb\nccc\n");
+        assert_eq!(append_capped("aaa\nbbb\n", "cccccc", 10), "cccccc\n");
+        // an oversized entry is still recorded on its own
+        assert_eq!(append_capped("aaa\n", "cccccccccccc", 10), "cccccccccccc\n");
+    }
+
+    #[test]
+    fn auditlog_timeline() {
+        let log = r#"{"type":"deployment","timestamp":"2019-03-02T10:00:00.000Z","status":"COMPLETED","payload":{"region":"dev-uk","service":"fake-ask","version":"1.6.1","previous_version":"1.6.0"}}
+not json
+{"type":"deployment","timestamp":"2019-03-01T10:00:00.000Z","status":"PENDING","payload":{"region":"dev-uk","service":"fake-ask","version":"1.6.1"}}
+{"type":"reconciliation","timestamp":"2019-03-02T09:00:00.000Z","status":"FAILED","payload":{"region":"dev-uk"}}
+{"type":"deployment","timestamp":"2019-03-02T11:00:00.000Z","status":"COMPLETED","payload":{"region":"staging-uk","service":"fake-ask","version":"1.6.1"}}
+"#;
+        let entries = parse(log);
+        assert_eq!(entries.len(), 4);
+        let after = Utc.ymd(2019, 3, 1).and_hms(12, 0, 0);
+        let tl = timeline(entries, "dev-uk", after);
+        assert_eq!(tl.len(), 2);
+        assert_eq!(tl[0].domain_type, "reconciliation");
+        assert_eq!(tl[1].payload.previous_version, Some("1.6.0".into()));
+    }
+}
@@ -1,7 +1,11 @@
-use shipcat_definitions::{Config, Region, Team, BaseManifest, ReconciliationMode};
+use shipcat_definitions::{Config, Region, Team, BaseManifest, ReconciliationMode, HelmVersion};
 use shipcat_filebacked::{SimpleManifest};
 use super::helm::{self, UpgradeMode};
+use super::kube;
 use super::{Result};
+use crate::audit::AuditOverride;
+use crate::freeze;
+use crate::networkpolicy::CallerGraph;
 use crate::webhooks;
 
 /// Helm upgrade the region (reconcile)
@@ -23,6 +27,33 @@ pub fn helm_diff(conf: &Config, region: &Region, n_workers: usize) -> Result<()>
     mass_helm(conf, region, UpgradeMode::DiffOnly, n_workers)
 }
 
+/// Adopt the helm 2 releases of a region into helm 3
+///
+/// Converts every release the tiller knows about that helm 3 does not have yet.
+/// Needs helm 3 with the `2to3` plugin. Set `helmVersion: v3` on the region afterwards.
+pub fn helm_migrate(region: &Region, dry_run: bool) -> Result<()> {
+    let releases = kube::tiller_releases(&region.namespace)?;
+    info!("Found {} helm 2 releases in {}", releases.len(), region.namespace);
+    let mut failed = vec![];
+    for r in releases {
+        if helm::helpers::has_v3_release(&r, &region.namespace) {
+            info!("{} is already a helm 3 release", r);
+            continue;
+        }
+        if let Err(e) = helm::helpers::convert_release(&r, &region.namespace, dry_run) {
+            warn!("Failed to migrate {}: {}", r, e);
+            failed.push(r);
+        }
+    }
+    if !failed.is_empty() {
+        bail!("Failed to migrate {} releases: {}", failed.len(), failed.join(", "));
+    }
+    if region.helmVersion == HelmVersion::V2 && !dry_run {
+        info!("Set helmVersion: v3 for {} in shipcat.conf to start using the migrated releases", region.name);
+    }
+    Ok(())
+}
+
 // Find all active services in a region and helm::parallel::upgrade them
 fn mass_helm(conf: &Config, region: &Region, umode: UpgradeMode, n_workers: usize) -> Result<()> {
     let mut svcs = vec![];
@@ -46,6 +77,10 @@ use super::kube;
 fn crd_reconcile(svcs: Vec<SimpleManifest>, config: &Config, region: &Region, n_workers: usize) -> Result<()> {
     use threadpool::ThreadPool;
     use std::sync::mpsc::channel;
+    use std::sync::Arc;
+
+    // fail before any upgrade if the region is frozen
+    let ovr = freeze::check(region, &freeze::Clearance::from_env())?;
 
     // Reconcile CRDs (definition itself)
     use shipcat_definitions::gen_all_crds;
@@ -71,15 +106,18 @@ fn crd_reconcile(svcs: Vec<SimpleManifest>, config: &Config, region: &Region, n_
     info!("Starting {} parallel kube jobs using {} workers", n_jobs, n_workers);
 
     // then parallel apply the remaining ones
+    let graph = Arc::new(CallerGraph::new(config, region)?);
     let (tx, rx) = channel();
     for svc in svcs {
         let reg = region.clone();
         let conf = config.clone();
+        let graph = graph.clone();
+        let ovr = ovr.clone();
 
         let tx = tx.clone(); // tx channel reused in each thread
         pool.execute(move || {
             debug!("Running CRD reconcile for {:?}", svc);
-            let res = crd_reconcile_worker(&svc.base.name, &conf, &reg);
+            let res = crd_reconcile_worker(&svc.base.name, &conf, &reg, &graph, ovr);
             tx.send(res).expect("channel will be there waiting for the pool");
         });
     }
@@ -99,14 +137,14 @@ fn crd_reconcile(svcs: Vec<SimpleManifest>, config: &Config, region: &Region, n_
     Ok(())
 }
 
-fn crd_reconcile_worker(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
+fn crd_reconcile_worker(svc: &str, conf: &Config, reg: &Region, graph: &CallerGraph, ovr: Option<AuditOverride>) -> Result<()> {
     let mf = shipcat_filebacked::load_manifest(svc, conf, reg)?;
     if kube::apply_crd(svc, mf.clone(), &reg.namespace)? {
         // 1. CRD was configured or created - upgrade the rest:
         if reg.reconciliationMode == ReconciliationMode::CrdBorrowed {
             // tiller owned upgrade
             let umode = UpgradeMode::UpgradeInstallWait;
-            helm::parallel::reconcile_worker(mf, umode, conf.clone(), reg.clone())?;
+            helm::parallel::reconcile_worker(mf, umode, conf.clone(), reg.clone(), graph, ovr)?;
         } else if reg.reconciliationMode == ReconciliationMode::CrdOwned {
             // shipcat owned upgrade
             unimplemented!();
@@ -119,7 +157,7 @@ fn crd_reconcile_worker(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
         } else {
             // tiller owned upgrade
             let umode = UpgradeMode::UpgradeInstallWait;
-            helm::parallel::reconcile_worker(mf, umode, conf.clone(), reg.clone())?;
+            helm::parallel::reconcile_worker(mf, umode, conf.clone(), reg.clone(), graph, ovr)?;
         }
     }
     Ok(())
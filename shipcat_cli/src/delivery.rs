@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde::Serialize;
+use tera::Context;
+use url::Url;
+
+use shipcat_definitions::template;
+use super::{Result, ResultExt, ErrorKind};
+use super::{HttpWebhook, Region, Webhook};
+
+/// Header carrying the HMAC-SHA256 of the body
+pub const SIGNATURE_HEADER: &str = "X-Shipcat-Signature";
+
+/// Delay before the first retry, doubled for every retry after
+const BACKOFF_MS: u64 = 500;
+
+/// Longest delay between two retries
+const MAX_BACKOFF_MS: u64 = 60 * 1000;
+
+/// A rendered and signed request, ready to be (re)sent
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Delivery {
+    #[serde(with = "url_serde")]
+    pub url: Url,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    /// Names of credential headers left out of the spool file
+    ///
+    /// These are looked up again from the region's webhooks when flushing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redacted: Vec<String>,
+}
+
+impl Delivery {
+    /// Json body posted with a bearer token, as the audit webhook expects
+    pub fn bearer<T: Serialize>(url: &Url, token: &str, event: &T) -> Result<Delivery> {
+        let mut headers = BTreeMap::new();
+        headers.insert(CONTENT_TYPE.to_string(), "application/json".into());
+        headers.insert(AUTHORIZATION.to_string(), format!("Bearer {}", token));
+        Ok(Delivery { url: url.clone(), headers, body: serde_json::to_string(event)?, redacted: vec![] })
+    }
+
+    /// Copy of the delivery without any credential headers
+    fn redact(&self) -> Delivery {
+        let mut d = self.clone();
+        let sensitive = d.headers.keys().filter(|k| is_sensitive(k)).cloned().collect::<Vec<_>>();
+        for k in &sensitive {
+            d.headers.remove(k);
+        }
+        d.redacted.extend(sensitive);
+        d
+    }
+}
+
+/// Whether a header may carry a credential
+///
+/// The signature is bound to the body, so it is safe to keep.
+fn is_sensitive(header: &str) -> bool {
+    let h = header.to_lowercase();
+    if h == SIGNATURE_HEADER.to_lowercase() {
+        return false;
+    }
+    h == "cookie" || ["auth", "token", "secret", "key", "password"].iter().any(|s| h.contains(s))
+}
+
+/// Hex encoded HMAC-SHA256 of a body, prefixed like github's signatures
+pub fn sign(secret: &str, body: &str) -> Result<String> {
+    let key = PKey::hmac(secret.as_bytes()).chain_err(|| "invalid hmac key")?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).chain_err(|| "could not create signer")?;
+    signer.update(body.as_bytes()).chain_err(|| "could not sign body")?;
+    let mac = signer.sign_to_vec().chain_err(|| "could not sign body")?;
+    let hex : Vec<String> = mac.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("sha256={}", hex.join("")))
+}
+
+/// Render the body for an event and sign it
+pub fn prepare<T: Serialize>(hook: &HttpWebhook, event: &T) -> Result<Delivery> {
+    let body = if let Some(tpl) = &hook.template {
+        let mut ctx = Context::new();
+        ctx.insert("event", event);
+        template::one_off(tpl, &ctx)?
+    } else {
+        serde_json::to_string(event)?
+    };
+    let mut headers = hook.headers.clone();
+    if !headers.keys().any(|k| k.eq_ignore_ascii_case("content-type")) {
+        headers.insert(CONTENT_TYPE.to_string(), "application/json".into());
+    }
+    if let Some(secret) = &hook.secret {
+        headers.insert(SIGNATURE_HEADER.into(), sign(secret, &body)?);
+    }
+    Ok(Delivery { url: hook.url.clone(), headers, body, redacted: vec![] })
+}
+
+/// Send a delivery once, treating anything but a 2XX as a failure
+pub fn post(d: &Delivery) -> Result<()> {
+    let mkerr = || ErrorKind::Url(d.url.clone());
+    let client = reqwest::Client::new();
+    let mut req = client.post(d.url.clone()).body(d.body.clone());
+    for (k, v) in &d.headers {
+        req = req.header(k.as_str(), v.as_str());
+    }
+    let res = req.send().chain_err(&mkerr)?;
+    if !res.status().is_success() {
+        bail!("{} responded with {}", d.url, res.status());
+    }
+    Ok(())
+}
+
+/// Delay before a retry, doubling up to `MAX_BACKOFF_MS`
+fn backoff(attempt: u32) -> u64 {
+    2u64.checked_pow(attempt)
+        .map(|m| BACKOFF_MS.saturating_mul(m))
+        .unwrap_or(MAX_BACKOFF_MS)
+        .min(MAX_BACKOFF_MS)
+}
+
+/// Send a delivery, retrying with exponential backoff
+///
+/// Deliveries that still fail are spooled to disk before the error is returned.
+pub fn deliver(d: &Delivery, retries: u32) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match post(d) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if attempt >= retries {
+                    let pth = spool(d)?;
+                    return Err(e).chain_err(|| format!("spooled undelivered event to {}", pth.display()));
+                }
+                let delay = backoff(attempt);
+                warn!("Delivery to {} failed ({}), retrying in {}ms", d.url, e, delay);
+                thread::sleep(Duration::from_millis(delay));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Render, sign and deliver an event to an http webhook
+pub fn send<T: Serialize>(event: &T, hook: &HttpWebhook) -> Result<()> {
+    let d = prepare(hook, event)?;
+    deliver(&d, hook.retries)
+}
+
+/// Directory undeliverable events are kept in
+///
+/// Overridable with `SHIPCAT_SPOOL_DIR`, otherwise `~/.shipcat/spool`.
+pub fn spool_dir() -> PathBuf {
+    if let Ok(dir) = env::var("SHIPCAT_SPOOL_DIR") {
+        return PathBuf::from(dir);
+    }
+    dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")).join(".shipcat").join("spool")
+}
+
+/// Write a delivery to the spool, readable only by the current user
+fn spool(d: &Delivery) -> Result<PathBuf> {
+    let dir = spool_dir();
+    DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    // tighten directories created by older versions
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+    let name = format!("{}-{}.json", Utc::now().format("%Y%m%dT%H%M%S%.f"), std::process::id());
+    let pth = dir.join(name);
+    let f = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&pth)?;
+    serde_json::to_writer(f, &d.redact())?;
+    Ok(pth)
+}
+
+/// Credential headers the region's webhooks send to a url
+fn credentials(reg: &Region, url: &Url) -> BTreeMap<String, String> {
+    let mut creds = BTreeMap::new();
+    for wh in reg.webhooks.iter().flatten() {
+        match wh {
+            Webhook::Audit(h) if &h.url == url => {
+                creds.insert(AUTHORIZATION.to_string(), format!("Bearer {}", h.token));
+            }
+            Webhook::Http(h) if &h.url == url => {
+                creds.extend(h.headers.clone());
+            }
+            _ => {}
+        }
+    }
+    creds
+}
+
+/// Put back the credential headers left out of a spooled delivery
+fn restore(mut d: Delivery, reg: &Region) -> Result<Delivery> {
+    if d.redacted.is_empty() {
+        return Ok(d);
+    }
+    let creds = credentials(reg, &d.url);
+    for k in &d.redacted {
+        match creds.iter().find(|(c, _)| c.eq_ignore_ascii_case(k)) {
+            Some((_, v)) => { d.headers.insert(k.clone(), v.clone()); }
+            None => bail!("no webhook in {} provides the {} header for {}", reg.name, k, d.url),
+        }
+    }
+    d.redacted.clear();
+    Ok(d)
+}
+
+/// Replay all spooled deliveries, removing the ones that went through
+///
+/// Credentials left out of the spool are taken from the region's webhooks.
+/// Returns the number of deliveries that are still spooled.
+pub fn flush(reg: &Region) -> Result<usize> {
+    let dir = spool_dir();
+    if !dir.is_dir() {
+        info!("Nothing spooled in {}", dir.display());
+        return Ok(0);
+    }
+    let mut entries = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map(|e| e == "json").unwrap_or(false))
+        .collect::<Vec<_>>();
+    entries.sort(); // oldest first
+    let mut remaining = 0;
+    for pth in entries {
+        let spooled : Delivery = serde_json::from_reader(File::open(&pth)?)?;
+        let d = match restore(spooled, reg) {
+            Ok(d) => d,
+            Err(e) => {
+                warn!("Could not deliver spooled event {}: {}", pth.display(), e);
+                remaining += 1;
+                continue;
+            }
+        };
+        match post(&d) {
+            Ok(()) => {
+                info!("Delivered spooled event {} to {}", pth.display(), d.url);
+                fs::remove_file(&pth)?;
+            },
+            Err(e) => {
+                warn!("Could not deliver spooled event {}: {}", pth.display(), e);
+                remaining += 1;
+            }
+        }
+    }
+    Ok(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff, MAX_BACKOFF_MS};
+
+    #[test]
+    fn delivery_backoff_is_capped() {
+        assert_eq!(backoff(0), 500);
+        assert_eq!(backoff(3), 4000);
+        assert_eq!(backoff(7), MAX_BACKOFF_MS);
+        assert_eq!(backoff(64), MAX_BACKOFF_MS);
+        assert_eq!(backoff(u32::max_value()), MAX_BACKOFF_MS);
+    }
+}
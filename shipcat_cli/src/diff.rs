@@ -1,79 +1,91 @@
 use crate::kube;
-use super::{Config, Region, Result};
-use shipcat_definitions::Crd;
-use std::process::Command;
+use super::{Config, Manifest, Region, Result};
+use shipcat_definitions::{Crd, ConfigType};
+use shipcat_filebacked::GitSource;
+use std::process::{Command, Stdio};
+use std::path::PathBuf;
 
+/// Load the manifest of a service at a git revision
+///
+/// Both the config and the manifests are read from the git object database,
+/// so this never touches the working tree.
+fn load_manifest_at(svc: &str, rev: &str, region: &Region) -> Result<(Manifest, Region)> {
+    let src = GitSource::new(rev);
+    let (conf, reg) = shipcat_filebacked::load_config_from(&src)?
+        .filtered(ConfigType::Base, &region.name)?;
+    let mf = shipcat_filebacked::load_manifest_from(&src, svc, &conf, &reg)?;
+    Ok((mf, reg))
+}
+
+/// Export a chart at a git revision into a temporary directory
+///
+/// The directory is removed again if the export fails.
+fn export_chart(rev: &str, chart: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("shipcat-{}-{}-{}", chart, rev.replace('/', "-"), std::process::id()));
+    fs::create_dir_all(&dir)?;
+    if let Err(e) = archive_chart(rev, chart, &dir) {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(e);
+    }
+    Ok(dir)
+}
 
-fn git(args: &[&str]) -> Result<()> {
-    debug!("git {}", args.join(" "));
-    let s = Command::new("git").args(args).status()?;
-    if !s.success() {
-        bail!("Subprocess failure from git: {}", s.code().unwrap_or(1001))
+fn archive_chart(rev: &str, chart: &str, dir: &Path) -> Result<()> {
+    let tree = format!("{}:./charts/{}", rev, chart);
+    debug!("git archive {} | tar -x -C {}", tree, dir.display());
+    let mut archive = Command::new("git").args(&["archive", &tree]).stdout(Stdio::piped()).spawn()?;
+    let stdout = archive.stdout.take().unwrap(); // piped above
+    let untar = Command::new("tar")
+        .args(&["-x", "-C", &dir.display().to_string()])
+        .stdin(stdout)
+        .status();
+    let archived = archive.wait()?;
+    if !archived.success() {
+        bail!("Failed to archive chart {} at git revision {}", chart, rev);
+    }
+    if !untar?.success() {
+        bail!("Failed to export chart {} from git revision {}", chart, rev);
     }
     Ok(())
 }
 
 /// Fast local git compare of the crd
 ///
-/// Should be pretty safe. Stashes existing work, checks out master, compares,
-/// then goes back to previous branch and pops the stash.
-///
-/// Because this does fiddle with git state while running it is not the default implementation.
-pub fn values_vs_git(svc: &str, conf: &Config, region: &Region) -> Result<bool> {
+/// Compares the working tree against `rev` (usually master) by reading the
+/// manifests and config at `rev` straight from git, leaving git state alone.
+pub fn values_vs_git(svc: &str, rev: &str, conf: &Config, region: &Region) -> Result<bool> {
     let aftermf = shipcat_filebacked::load_manifest(&svc, conf, region)?;
     let after = serde_yaml::to_string(&aftermf)?;
 
-    // move git to get before state:
-    git(&["checkout", "master", "--quiet"])?;
-    let needs_stash = git(&["diff", "--quiet", "--exit-code"]).is_err() || git(&["diff", "--cached", "--quiet", "--exit-code"]).is_err();
-    if needs_stash {
-        git(&["stash", "--quiet"])?;
-    }
-
-    // compute before state
-    let beforemf = shipcat_filebacked::load_manifest(&svc, conf, region)?;
+    let (beforemf, _) = load_manifest_at(svc, rev, region)?;
     let before = serde_yaml::to_string(&beforemf)?;
 
-    // move git back
-    if needs_stash {
-        git(&["stash", "pop", "--quiet"])?;
-    }
-    git(&["checkout", "-", "--quiet"])?;
-
     // display diff
     shell_diff(&before, &after)
 }
 
 /// Fast local git compare of shipcat template
 ///
-/// Because this uses the template in master against local state,
+/// Because this uses the template in `rev` against local state,
 /// we don't resolve secrets for this (would compare equal values anyway).
-pub fn template_vs_git(svc: &str, conf: &Config, region: &Region) -> Result<bool> {
+pub fn template_vs_git(svc: &str, rev: &str, conf: &Config, region: &Region) -> Result<bool> {
     use crate::helm;
     let mock = true; // both would be equivalent vault reads anyway
-    let afterpth = Path::new(".").join("after.shipcat.gen.yml");
+    let afterpth = Path::new(".").join(format!("{}.after.shipcat.gen.yml", svc));
     let _after = helm::direct::template(&svc, &region, &conf, None, mock, Some(afterpth.clone()))?;
 
-    // move git to get before state:
-    git(&["checkout", "master", "--quiet"])?;
-    let needs_stash = git(&["diff", "--quiet", "--exit-code"]).is_err() || git(&["diff", "--cached", "--quiet", "--exit-code"]).is_err();
-    if needs_stash {
-        git(&["stash", "--quiet"])?;
-    }
-
-    // compute old state:
-    let beforepth = Path::new(".").join("before.shipcat.gen.yml");
-    let _before = helm::direct::template(&svc, &region, &conf, None, mock, Some(beforepth.clone()))?;
-
-    // move git back
-    if needs_stash {
-        git(&["stash", "pop", "--quiet"])?;
-    }
-    git(&["checkout", "-", "--quiet"])?;
+    // compute old state from the manifests and chart at rev:
+    let (beforemf, beforereg) = load_manifest_at(svc, rev, region)?;
+    let beforemf = beforemf.stub(&beforereg)?;
+    let chartdir = export_chart(rev, &beforemf.chart.clone().unwrap())?;
+    let beforepth = Path::new(".").join(format!("{}.before.shipcat.gen.yml", svc));
+    let before = helm::direct::template_manifest(beforemf, &beforereg, None, &chartdir, Some(beforepth.clone()));
+    fs::remove_dir_all(&chartdir)?;
+    let _before = before?;
 
     // display diff
     // doesn't reuse shell_diff because we already have files from direct::template
-    let args = ["-u", "before.shipcat.gen.yml", "after.shipcat.gen.yml"];
+    let args = ["-u".to_string(), beforepth.display().to_string(), afterpth.display().to_string()];
     debug!("diff {}", args.join(" "));
     let s = Command::new("diff").args(&args).status()?;
     // cleanup
@@ -130,7 +142,7 @@ pub fn template_vs_kubectl(svc: &str, conf: &Config, region: &Region, mock: bool
 
 // Compare using diff(1)
 // difference libraries all seemed to be lacking somewhat
-fn shell_diff(before: &str, after: &str) -> Result<bool> {
+pub(crate) fn shell_diff(before: &str, after: &str) -> Result<bool> {
     let beforepth = Path::new(".").join("before.shipcat.gen.yml");
     debug!("Writing before to {}", beforepth.display());
     let mut f = File::create(&beforepth)?;
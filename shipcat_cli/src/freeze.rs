@@ -0,0 +1,240 @@
+use std::env;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use regex::Regex;
+
+use crate::audit::AuditOverride;
+use super::{DeployPolicy, FreezeWindow, Region};
+use super::{Result, ResultExt};
+
+/// Emergency reason and approval given for an upgrade
+#[derive(Clone, Debug, Default)]
+pub struct Clearance {
+    /// Why an upgrade has to go out during a freeze
+    pub reason: Option<String>,
+    /// Approval token or ticket reference
+    pub approval: Option<String>,
+}
+
+impl Clearance {
+    /// Clearance from flags, falling back to the environment
+    ///
+    /// Reads `SHIPCAT_EMERGENCY_REASON` and `SHIPCAT_APPROVAL` when flags are missing.
+    pub fn new(reason: Option<&str>, approval: Option<&str>) -> Self {
+        let given = |flag: Option<&str>, evar: &str| flag.map(String::from)
+            .or_else(|| env::var(evar).ok())
+            .filter(|v| !v.trim().is_empty());
+        Clearance {
+            reason: given(reason, "SHIPCAT_EMERGENCY_REASON"),
+            approval: given(approval, "SHIPCAT_APPROVAL"),
+        }
+    }
+
+    /// Clearance from the environment only (for reconciles)
+    pub fn from_env() -> Self {
+        Clearance::new(None, None)
+    }
+}
+
+/// Check an upgrade in a region against its deploy policy
+///
+/// Returns the overrides and approvals to record in audit events.
+pub fn check(region: &Region, cl: &Clearance) -> Result<Option<AuditOverride>> {
+    match &region.deployPolicy {
+        Some(policy) => check_at(policy, &region.name, cl, Utc::now()),
+        None => Ok(None),
+    }
+}
+
+/// Check a deploy policy at a given time
+pub fn check_at(policy: &DeployPolicy, region: &str, cl: &Clearance, now: DateTime<Utc>) -> Result<Option<AuditOverride>> {
+    let mut active = vec![];
+    for w in &policy.freezes {
+        if window_active(w, now).chain_err(|| format!("invalid freeze window {} in {}", w.name, region))? {
+            active.push(w.name.clone());
+        }
+    }
+
+    let approval = match (&policy.approval, &cl.approval) {
+        (Some(ap), Some(a)) => {
+            let re = Regex::new(&ap.pattern).chain_err(|| format!("invalid approval pattern in {}", region))?;
+            if !re.is_match(a) {
+                bail!("Approval '{}' does not match {} required in {}", a, ap.pattern, region);
+            }
+            Some(a.clone())
+        }
+        (Some(ap), None) if ap.always => {
+            bail!("Upgrades in {} need an approval matching {} (pass --approval)", region, ap.pattern);
+        }
+        (_, a) => a.clone(),
+    };
+
+    if active.is_empty() {
+        return Ok(approval.map(|a| AuditOverride { approval: Some(a), ..Default::default() }));
+    }
+    let frozen = active.join(", ");
+    let reason = match &cl.reason {
+        Some(r) => r.clone(),
+        None => bail!("{} is frozen for {} (pass --emergency-reason to override)", region, frozen),
+    };
+    if !policy.allowEmergency {
+        bail!("{} is frozen for {} and does not allow emergency overrides", region, frozen);
+    }
+    if policy.approval.is_some() && approval.is_none() {
+        bail!("Emergency overrides in {} need an approval (pass --approval)", region);
+    }
+    warn!("Overriding the {} freeze in {}: {}", frozen, region, reason);
+    Ok(Some(AuditOverride { freezes: active, reason: Some(reason), approval }))
+}
+
+/// Parse every window of a policy
+pub fn verify(policy: &DeployPolicy, region: &str) -> Result<()> {
+    for w in &policy.freezes {
+        window_active(w, Utc::now()).chain_err(|| format!("invalid freeze window {} in {}", w.name, region))?;
+    }
+    Ok(())
+}
+
+fn window_active(w: &FreezeWindow, now: DateTime<Utc>) -> Result<bool> {
+    if let Some(cron) = &w.cron {
+        return cron_matches(cron, now);
+    }
+    let parse = |t: &Option<String>| -> Result<DateTime<Utc>> {
+        let t = t.as_ref().ok_or_else(|| "needs either start and end, or cron")?;
+        Ok(DateTime::parse_from_rfc3339(t).chain_err(|| format!("invalid time '{}'", t))?.with_timezone(&Utc))
+    };
+    let (start, end) = (parse(&w.start)?, parse(&w.end)?);
+    if start >= end {
+        bail!("ends before it starts");
+    }
+    Ok(start <= now && now < end)
+}
+
+/// Whether a five field cron expression matches the minute of a time
+///
+/// Fields support `*`, values, ranges, lists and steps (`*/15`, `1-5/2`).
+/// Like cron, a restricted day of month and day of week match if either does.
+fn cron_matches(expr: &str, t: DateTime<Utc>) -> Result<bool> {
+    let fields = expr.split_whitespace().collect::<Vec<_>>();
+    if fields.len() != 5 {
+        bail!("cron expression '{}' needs 5 fields", expr);
+    }
+    let minute = cron_field(fields[0], 0, 59, t.minute())?;
+    let hour = cron_field(fields[1], 0, 23, t.hour())?;
+    let dom = cron_field(fields[2], 1, 31, t.day())?;
+    let month = cron_field(fields[3], 1, 12, t.month())?;
+    // sunday is both 0 and 7
+    let wd = t.weekday().num_days_from_sunday();
+    let dow = cron_field(fields[4], 0, 7, wd)? || (wd == 0 && cron_field(fields[4], 0, 7, 7)?);
+    let day = if fields[2] != "*" && fields[4] != "*" { dom || dow } else { dom && dow };
+    Ok(minute && hour && month && day)
+}
+
+fn cron_field(field: &str, min: u32, max: u32, value: u32) -> Result<bool> {
+    let num = |s: &str| s.parse::<u32>().chain_err(|| format!("invalid cron field '{}'", field));
+    let mut hit = false;
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => (&part[..i], num(&part[i+1..])?),
+            None => (part, 1),
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some(i) = range.find('-') {
+            (num(&range[..i])?, num(&range[i+1..])?)
+        } else {
+            // `5/15` runs from 5 to the end of the range
+            let v = num(range)?;
+            (v, if step > 1 { max } else { v })
+        };
+        if step == 0 || lo < min || hi > max || lo > hi {
+            bail!("invalid cron field '{}'", field);
+        }
+        if (lo..=hi).contains(&value) && (value - lo) % step == 0 {
+            hit = true;
+        }
+    }
+    Ok(hit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_at, cron_matches, Clearance};
+    use crate::{ApprovalPolicy, DeployPolicy, FreezeWindow};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn freeze_cron_matches() {
+        // 2019-03-02 is a saturday
+        let sat = Utc.ymd(2019, 3, 2).and_hms(18, 30, 0);
+        assert!(cron_matches("* * * * 0,6", sat).unwrap());
+        assert!(cron_matches("* * * * 6-7", sat).unwrap());
+        assert!(!cron_matches("* * * * 1-5", sat).unwrap());
+        assert!(cron_matches("*/15 17-23 * * *", sat).unwrap());
+        assert!(!cron_matches("*/20 17-23 * * *", sat).unwrap());
+        assert!(cron_matches("* * 2 3 *", sat).unwrap());
+        // restricted day of month or day of week
+        assert!(cron_matches("* * 25 * 6", sat).unwrap());
+        // sunday as 7
+        assert!(cron_matches("* * * * 7", Utc.ymd(2019, 3, 3).and_hms(0, 0, 0)).unwrap());
+        assert!(cron_matches("* * * *", sat).is_err());
+        assert!(cron_matches("* 24 * * *", sat).is_err());
+        assert!(cron_matches("*/0 * * * *", sat).is_err());
+        assert!(cron_matches("* * * * mon", sat).is_err());
+    }
+
+    fn xmas() -> DeployPolicy {
+        DeployPolicy {
+            freezes: vec![FreezeWindow {
+                name: "xmas".into(),
+                start: Some("2019-12-20T17:00:00Z".into()),
+                end: Some("2020-01-02T09:00:00Z".into()),
+                cron: None,
+            }],
+            allowEmergency: true,
+            approval: None,
+        }
+    }
+
+    #[test]
+    fn freeze_check_windows() {
+        let during = Utc.ymd(2019, 12, 24).and_hms(12, 0, 0);
+        let after = Utc.ymd(2020, 1, 2).and_hms(9, 0, 0);
+        let none = Clearance::default();
+        let emergency = Clearance { reason: Some("INC-42 outage".into()), approval: None };
+
+        let mut policy = xmas();
+        assert!(check_at(&policy, "prod-uk", &none, after).unwrap().is_none());
+        assert!(check_at(&policy, "prod-uk", &none, during).is_err());
+        let ovr = check_at(&policy, "prod-uk", &emergency, during).unwrap().unwrap();
+        assert_eq!(ovr.freezes, vec!["xmas".to_string()]);
+        assert_eq!(ovr.reason, Some("INC-42 outage".into()));
+
+        policy.allowEmergency = false;
+        assert!(check_at(&policy, "prod-uk", &emergency, during).is_err());
+    }
+
+    #[test]
+    fn freeze_check_approvals() {
+        let during = Utc.ymd(2019, 12, 24).and_hms(12, 0, 0);
+        let after = Utc.ymd(2020, 1, 2).and_hms(9, 0, 0);
+        let emergency = Clearance { reason: Some("INC-42 outage".into()), approval: None };
+        let approved = Clearance { reason: Some("INC-42 outage".into()), approval: Some("CHG-1234".into()) };
+        let typoed = Clearance { reason: None, approval: Some("CHG1234".into()) };
+
+        let mut policy = xmas();
+        policy.approval = Some(ApprovalPolicy { pattern: "^CHG-[0-9]+$".into(), always: false });
+        // overrides need approvals
+        assert!(check_at(&policy, "prod-uk", &emergency, during).is_err());
+        let ovr = check_at(&policy, "prod-uk", &approved, during).unwrap().unwrap();
+        assert_eq!(ovr.approval, Some("CHG-1234".into()));
+        // normal upgrades do not, but approvals given are checked and recorded
+        assert!(check_at(&policy, "prod-uk", &Clearance::default(), after).unwrap().is_none());
+        assert!(check_at(&policy, "prod-uk", &typoed, after).is_err());
+        let ovr = check_at(&policy, "prod-uk", &approved, after).unwrap().unwrap();
+        assert!(ovr.freezes.is_empty() && ovr.reason.is_none());
+
+        policy.approval = Some(ApprovalPolicy { pattern: "^CHG-[0-9]+$".into(), always: true });
+        assert!(check_at(&policy, "prod-uk", &Clearance::default(), after).is_err());
+    }
+}
@@ -3,10 +3,10 @@
 ///
 use reqwest;
 use chrono::Utc;
-use std::env;
 use serde_json::json;
 
 use super::{Result, ErrorKind, ResultExt};
+use super::GrafanaWebhook;
 
 /// At what time the annotation should be made
 #[derive(Debug)]
@@ -32,18 +32,6 @@ pub struct Annotation {
     pub time: TimeSpec,
 }
 
-/// Extracts grafana URL + HTTP scheme from environment
-pub fn env_hook_url() -> Result<String> {
-    env::var("GRAFANA_SHIPCAT_HOOK_URL")
-        .map_err(|_| ErrorKind::MissingGrafanaUrl.into())
-}
-
-/// Extracts grafana API key from environment
-pub fn env_token() -> Result<String> {
-    env::var("GRAFANA_SHIPCAT_TOKEN")
-        .map_err(|_| ErrorKind::MissingGrafanaToken.into())
-}
-
 /// Convert timespec to UNIX time, in milliseconds
 fn unix_timestamp(spec: &TimeSpec) -> Result<u64> {
   let timestamp = match spec {
@@ -54,10 +42,7 @@ fn unix_timestamp(spec: &TimeSpec) -> Result<u64> {
 }
 
 /// Create an annotation for a deployment using grafana's REST API
-pub fn create(annotation: Annotation) -> Result<()> {
-    let hook_url = env_hook_url()?;
-    let hook_token = env_token()?;
-
+pub fn create(annotation: Annotation, hook: &GrafanaWebhook) -> Result<()> {
     let timestamp = unix_timestamp(&annotation.time)?;
 
     let data = json!({
@@ -78,12 +63,12 @@ pub fn create(annotation: Annotation) -> Result<()> {
         ]
     });
 
-    let url = reqwest::Url::parse(&hook_url)?.join("api/annotations")?;
+    let url = hook.url.join("api/annotations")?;
     let mkerr = || ErrorKind::Url(url.clone());
     let client = reqwest::Client::new();
 
     client.post(url.clone())
-        .bearer_auth(hook_token)
+        .bearer_auth(hook.token.clone())
         .json(&data)
         .send()
         .chain_err(&mkerr)?;
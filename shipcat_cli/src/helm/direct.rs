@@ -1,19 +1,24 @@
+use std::env;
 use std::fs;
 use std::fmt;
 use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::Write;
+use std::time::Instant;
 
 use serde_yaml;
+use crate::audit::AuditOverride;
 use crate::webhooks::{self, UpgradeState};
+use crate::{freeze, lock, mesh, networkpolicy, registry, slack};
+use crate::networkpolicy::CallerGraph;
 use super::kube;
 use super::Metadata;
-use super::{Manifest, Config, Region};
+use super::{Manifest, Config, Region, HelmVersion};
 use super::{Result, ResultExt, ErrorKind};
 use super::helpers::{self, hout, hexec};
 
 /// The different modes we allow `helm upgrade` to run in
-#[derive(PartialEq, Clone, Debug)]
+#[derive(Serialize, PartialEq, Clone, Debug)]
 pub enum UpgradeMode {
     /// Upgrade dry-run
     DiffOnly,
@@ -68,44 +73,125 @@ impl UpgradeMode {
     }
 }
 
+/// What a rollback should restore
+#[derive(Clone, Debug, PartialEq)]
+pub enum RollbackTarget {
+    /// The release before the current one
+    Previous,
+    /// A specific helm revision
+    Revision(u32),
+    /// The latest revision that ran a version
+    Version(String),
+}
+
 /// Direct rollback command using synthetic or failed `UpgradeData`
 ///
-/// Always just rolls back using to the helm's previous release and doesn't block
-/// I.e. it assumes th previous release is stable and can be upgraded to.
-/// If this is not the case you might have degraded service (fewer replicas).
-///
-/// TODO: deprecate
-pub fn rollback(reg: &Region, ud: &UpgradeData, mf: &Manifest) -> Result<()> {
+/// Rolls back to a given helm revision, or helm's previous release if none,
+/// then waits for the rollout like an upgrade would.
+pub fn rollback(reg: &Region, ud: &UpgradeData, mf: &Manifest, revision: Option<u32>) -> Result<()> {
     assert!(ud.namespace.len() > 0);
-    let rollbackvec = vec![
-        format!("--tiller-namespace={}", ud.namespace),
+    let mut rollbackvec = helpers::namespace_args(reg.helmVersion, &ud.namespace);
+    rollbackvec.extend_from_slice(&[
         "rollback".into(),
         ud.name.clone(),
-        "0".into(), // magic helm number for previous
-    ];
+    ]);
+    if let Some(r) = revision {
+        rollbackvec.push(r.to_string());
+    } else if reg.helmVersion == HelmVersion::V2 {
+        rollbackvec.push("0".into()); // magic helm number for previous
+    }
     info!("helm {}", rollbackvec.join(" "));
 
     webhooks::upgrade_rollback_event(UpgradeState::RollingBack, &ud, &reg);
-    match hexec(rollbackvec) {
+    if let Err(e) = hexec(rollbackvec) {
+        error!("{}", e);
+        webhooks::upgrade_rollback_event(UpgradeState::RollbackFailed, &ud, &reg);
+        return Err(e);
+    }
+    match kube::await_rollout_status(&mf) {
+        Ok(true) => {
+            info!("successfully rolled back {}", &ud.name);
+            webhooks::upgrade_rollback_event(UpgradeState::RolledBack, &ud, &reg);
+            Ok(())
+        }
+        Ok(false) => {
+            let _ = kube::debug_rollout_status(&mf);
+            warn!("failed to roll back {}", &ud.name);
+            webhooks::upgrade_rollback_event(UpgradeState::RollbackFailed, &ud, &reg);
+            Err(ErrorKind::UpgradeTimeout(mf.name.clone(), mf.estimate_wait_time()).into())
+        }
         Err(e) => {
-            error!("{}", e);
             webhooks::upgrade_rollback_event(UpgradeState::RollbackFailed, &ud, &reg);
             Err(e)
-        },
-        Ok(_) => {
-            let res = kube::await_rollout_status(&mf);
-            webhooks::upgrade_rollback_event(UpgradeState::RolledBack, &ud, &reg);
-            res?; // propagate errors from rollback check if any
-            Ok(())
         }
     }
 }
 
+/// Find the revision to roll back to in a release history
+///
+/// The history must be oldest first, and the current revision is never picked.
+/// Versions only match revisions that did not fail to deploy.
+pub fn pick_revision<F>(history: &[helpers::Revision], target: &RollbackTarget, version_of: F) -> Result<u32>
+    where F: Fn(u32) -> Result<String>
+{
+    let current = match history.last() {
+        Some(r) => r.revision,
+        None => bail!("release has no history"),
+    };
+    let older = || history.iter().rev().skip(1);
+    let found = match target {
+        RollbackTarget::Previous => older().next().map(|r| r.revision),
+        RollbackTarget::Revision(n) => {
+            if *n == current {
+                bail!("revision {} is the current revision", n);
+            }
+            history.iter().find(|r| r.revision == *n).map(|r| r.revision)
+        }
+        RollbackTarget::Version(v) => {
+            let mut res = None;
+            for r in older().filter(|r| !r.status.eq_ignore_ascii_case("FAILED")) {
+                if version_of(r.revision)? == *v {
+                    res = Some(r.revision);
+                    break;
+                }
+            }
+            res
+        }
+    };
+    match found {
+        Some(r) => Ok(r),
+        None => bail!("no revision to roll back to for {:?}", target),
+    }
+}
+
 /// Rollback entrypoint using a plain service and region
-pub fn rollback_wrapper(svc: &str, conf: &Config, region: &Region) -> Result<()> {
-    let base = shipcat_filebacked::load_manifest(svc, &conf, region)?;
-    let ud = UpgradeData::from_rollback(&base);
-    rollback(&region, &ud, &base)
+///
+/// Resolves the target in the release history and checks that its images still exist.
+pub fn rollback_wrapper(svc: &str, conf: &Config, region: &Region, target: RollbackTarget, wait_for_lock: bool) -> Result<()> {
+    let mut mf = shipcat_filebacked::load_manifest(svc, &conf, region)?;
+    let (ns, hv) = (mf.namespace.clone(), region.helmVersion);
+
+    // nobody else upgrades this service until we return (before reading what is running)
+    let _lock = lock::acquire(svc, &ns, lock::ttl_for(&mf), wait_for_lock)?;
+    let history = helpers::release_history(svc, &ns, hv)?;
+    let revision = pick_revision(&history, &target, |r| helpers::revision_version(svc, &ns, hv, r))
+        .chain_err(|| format!("cannot roll back {}", svc))?;
+    let restored = helpers::revision_version(svc, &ns, hv, revision)?;
+    let running = helpers::infer_fallback_version(svc, &ns, hv).ok();
+    info!("Rolling back {} from {} to {} (revision {})",
+        svc, running.clone().unwrap_or_else(|| "unknown".into()), restored, revision);
+
+    mf.version = Some(restored.clone());
+    registry::verify_rollback_images(&mf, region)?;
+
+    let mut ud = UpgradeData::from_rollback(&mf);
+    // rollback events carry the version being replaced and the version restored
+    if let Some(v) = running {
+        ud.version = v;
+    }
+    ud.rollback_to = Some(restored);
+    ud.started = Some(Instant::now());
+    rollback(&region, &ud, &mf, Some(revision))
 }
 
 // All data needed for an upgrade
@@ -131,6 +217,20 @@ pub struct UpgradeData {
     pub values: String,
     /// Metadata used in slack notifications
     pub metadata: Option<Metadata>,
+    /// Image digest the version was pinned to
+    pub digest: Option<String>,
+    /// Version running before the upgrade (if known)
+    pub previous_version: Option<String>,
+    /// Version an explicit rollback restores
+    pub rollback_to: Option<String>,
+    /// When the upgrade started
+    pub started: Option<Instant>,
+    /// Slack threads started for this upgrade
+    pub slack_threads: slack::Threads,
+    /// Helm version managing the release
+    pub helm_version: HelmVersion,
+    /// Deploy policy overrides and approvals for audits
+    pub policy_override: Option<AuditOverride>,
 }
 
 impl UpgradeData {
@@ -140,11 +240,11 @@ impl UpgradeData {
     /// (DiffOnly can sneak by early due to it not being technically needed)
     ///
     /// Performs basic sanity checks, and populates canonical values that are reused a lot.
-    pub fn new(mf: &Manifest, hfile: &str, mode: UpgradeMode, exists: bool) ->  Result<Option<UpgradeData>> {
+    pub fn new(mf: &Manifest, hfile: &str, mode: UpgradeMode, exists: bool, hv: HelmVersion) ->  Result<Option<UpgradeData>> {
         let helmdiff = if !exists {
             "".into() // can't diff against what's not there!
         } else {
-            let hdiff = diff(mf, hfile, DiffMode::Upgrade)?;
+            let hdiff = diff(mf, hfile, DiffMode::Upgrade, hv)?;
             if mode == UpgradeMode::DiffOnly {
                 return Ok(None)
             }
@@ -163,6 +263,13 @@ impl UpgradeData {
             name: mf.name.clone(),
             diff: helmdiff,
             metadata: mf.metadata.clone(),
+            digest: mf.imageDigest.clone(),
+            previous_version: None, // set by callers that know what is running
+            rollback_to: None,
+            started: Some(Instant::now()),
+            slack_threads: Default::default(),
+            helm_version: hv,
+            policy_override: None, // set by callers that checked the deploy policy
             chart: mf.chart.clone().unwrap(),
             waittime: mf.estimate_wait_time(),
             region: mf.region.clone(),
@@ -184,6 +291,14 @@ impl UpgradeData {
             ..Default::default()
         }
     }
+
+    /// Version a rollback restores
+    ///
+    /// Automatic rollbacks go back to whatever ran before the upgrade.
+    pub fn restored_version(&self) -> Option<&String> {
+        self.rollback_to.as_ref().or_else(|| self.previous_version.as_ref())
+    }
+
     pub fn from_rollback(mf: &Manifest) -> UpgradeData {
         UpgradeData {
             name: mf.name.clone(),
@@ -202,8 +317,8 @@ impl UpgradeData {
 
 pub fn upgrade(data: &UpgradeData) -> Result<()> {
     // upgrade it using the same command
-    let mut upgradevec = vec![
-        format!("--tiller-namespace={}", data.namespace),
+    let mut upgradevec = helpers::namespace_args(data.helm_version, &data.namespace);
+    upgradevec.extend_from_slice(&[
         "upgrade".into(),
         data.name.clone(),
         format!("charts/{}", data.chart),
@@ -211,7 +326,7 @@ pub fn upgrade(data: &UpgradeData) -> Result<()> {
         data.values.clone(),
         "--set".into(),
         format!("version={}", data.version),
-    ];
+    ]);
 
     // TODO: dedupe
     match data.mode {
@@ -264,11 +379,11 @@ impl fmt::Display for DiffMode {
 /// helm diff against current running release
 ///
 /// Shells out to helm diff, then obfuscates secrets
-fn diff(mf: &Manifest, hfile: &str, dmode: DiffMode) -> Result<String> {
+fn diff(mf: &Manifest, hfile: &str, dmode: DiffMode, hv: HelmVersion) -> Result<String> {
     let ver = mf.version.clone().unwrap(); // must be set outside
     let namespace = mf.namespace.clone();
-    let diffvec = vec![
-        format!("--tiller-namespace={}", namespace),
+    let mut diffvec = helpers::namespace_args(hv, &namespace);
+    diffvec.extend_from_slice(&[
         "diff".into(),
         dmode.to_string(),
         "--no-color".into(),
@@ -278,7 +393,7 @@ fn diff(mf: &Manifest, hfile: &str, dmode: DiffMode) -> Result<String> {
         "-f".into(),
         hfile.into(),
         format!("--version={}", ver),
-    ];
+    ]);
     info!("helm {}", diffvec.join(" "));
     let (hdiffunobfusc, hdifferr, _) = hout(diffvec.clone())?;
     let helmdiff = helpers::obfuscate_secrets(
@@ -287,7 +402,7 @@ fn diff(mf: &Manifest, hfile: &str, dmode: DiffMode) -> Result<String> {
     );
     if !hdifferr.is_empty() {
         if hdifferr.starts_with(&format!("Error: \"{}\" has no deployed releases", mf.name)) {
-            let cmd = format!("helm --tiller-namespace={} del --purge {}", namespace, mf.name);
+            let cmd = helpers::purge_command(hv, &namespace, &mf.name);
             let reason = "to let you be able to retry the install/reconcile";
             error!("Previous installs of {} failed, you need to run: \n\t{}\n{}",
                 mf.name, cmd, reason
@@ -340,7 +455,18 @@ pub fn template(svc: &str, region: &Region, conf: &Config, ver: Option<String>,
     } else {
         shipcat_filebacked::load_manifest(svc, conf, region)?.complete(region)?
     };
+    let graph = CallerGraph::new(conf, region)?;
+    networkpolicy::inject(&mut mf, &graph, region);
+    mesh::inject(&mut mf, &graph, region)?;
+    let chart = Path::new(".").join("charts").join(mf.chart.clone().unwrap());
+    template_manifest(mf, region, ver, &chart, output)
+}
 
+/// Helm template of an already loaded manifest using a specific chart folder
+///
+/// Allows templating manifests that were not loaded from the working tree.
+pub fn template_manifest(mut mf: Manifest, region: &Region, ver: Option<String>, chart: &Path, output: Option<PathBuf>) -> Result<String> {
+    let svc = mf.name.clone();
     // template or values does not need version - but respect passed in / manifest
     if ver.is_some() {
         // override with set version only if set - respect pin otherwise
@@ -355,13 +481,16 @@ pub fn template(svc: &str, region: &Region, conf: &Config, ver: Option<String>,
     values(&mf, Some(hfile.clone()))?;
 
     // helm template with correct params
-    let tplvec = vec![
-        "template".into(),
-        format!("charts/{}", mf.chart.unwrap()),
+    let mut tplvec = vec!["template".into()];
+    if region.helmVersion == HelmVersion::V3 {
+        tplvec.push(svc.clone()); // helm 3 needs a release name
+    }
+    tplvec.extend_from_slice(&[
+        chart.display().to_string(),
         "-f".into(),
         hfile.clone(),
-    ];
-    // NB: this call does NOT need --tiller-namespace (offline call)
+    ]);
+    // NB: this call does NOT need a tiller or release namespace (offline call)
     let (tpl, tplerr, success) = hout(tplvec.clone())?;
     if !success {
         warn!("{} stderr: {}", tplvec.join(" "), tplerr);
@@ -382,14 +511,14 @@ pub fn template(svc: &str, region: &Region, conf: &Config, ver: Option<String>,
 
 /// Helm history wrapper
 ///
-/// Analogue to `helm history {service}` uses the right tiller namespace
+/// Analogue to `helm history {service}` uses the right tiller or release namespace
 pub fn history(svc: &str, conf: &Config, region: &Region) -> Result<()> {
     let mf = shipcat_filebacked::load_manifest(svc, &conf, region)?;
-    let histvec = vec![
-        format!("--tiller-namespace={}", mf.namespace),
+    let mut histvec = helpers::namespace_args(region.helmVersion, &mf.namespace);
+    histvec.extend_from_slice(&[
         "history".into(),
         svc.into(),
-    ];
+    ]);
     debug!("helm {}", histvec.join(" "));
     hexec(histvec)?;
     Ok(())
@@ -397,14 +526,14 @@ pub fn history(svc: &str, conf: &Config, region: &Region) -> Result<()> {
 
 /// Helm status wrapper
 ///
-/// Analogue to `helm status {service}` uses the right tiller namespace
+/// Analogue to `helm status {service}` uses the right tiller or release namespace
 pub fn status(svc: &str, conf: &Config, region: &Region) -> Result<()> {
     let mf = shipcat_filebacked::load_manifest(svc, &conf, region)?;
-    let histvec = vec![
-        format!("--tiller-namespace={}", mf.namespace),
+    let mut histvec = helpers::namespace_args(region.helmVersion, &mf.namespace);
+    histvec.extend_from_slice(&[
         "status".into(),
         svc.into(),
-    ];
+    ]);
     debug!("helm {}", histvec.join(" "));
     hexec(histvec)?;
     Ok(())
@@ -420,7 +549,7 @@ pub fn handle_upgrade_rollbacks(reg: &Region, u: &UpgradeData, mf: &Manifest) ->
         _ => {}
     }
     if u.mode == UpgradeMode::UpgradeWaitMaybeRollback {
-        rollback(&reg, &u, mf)?;
+        rollback(&reg, &u, mf, None)?;
     }
     Ok(())
 }
@@ -430,6 +559,9 @@ pub fn handle_upgrade_rollbacks(reg: &Region, u: &UpgradeData, mf: &Manifest) ->
 /// Completes a manifest and prints it out with the given version
 pub fn values_wrapper(svc: &str, region: &Region, conf: &Config, ver: Option<String>) -> Result<()> {
     let mut mf = shipcat_filebacked::load_manifest(svc, conf, region)?.complete(region)?;
+    let graph = CallerGraph::new(conf, region)?;
+    networkpolicy::inject(&mut mf, &graph, region);
+    mesh::inject(&mut mf, &graph, region)?;
 
     // template or values does not need version - but respect passed in / manifest
     if ver.is_some() {
@@ -441,13 +573,44 @@ pub fn values_wrapper(svc: &str, region: &Region, conf: &Config, ver: Option<Str
     values(&mf, None)
 }
 
+/// Exceptions to region policies for a single upgrade
+#[derive(Clone, Debug, Default)]
+pub struct UpgradeOptions {
+    /// Skip the region's `versionPolicy`
+    pub allow_downgrade: bool,
+    /// Wait for other upgrades of the service rather than fail
+    pub wait_for_lock: bool,
+    /// Emergency reason and approval checked against the region's `deployPolicy`
+    pub clearance: freeze::Clearance,
+}
+
+impl UpgradeOptions {
+    /// Exceptions for reconciles, which take them from the environment
+    ///
+    /// Reads `SHIPCAT_ALLOW_DOWNGRADE` along with the variables of `Clearance::from_env`.
+    /// Reconciles always wait for the lock.
+    pub fn from_env() -> Self {
+        UpgradeOptions {
+            allow_downgrade: env::var("SHIPCAT_ALLOW_DOWNGRADE").map(|v| !v.trim().is_empty()).unwrap_or(false),
+            wait_for_lock: true,
+            clearance: freeze::Clearance::from_env(),
+        }
+    }
+}
+
 /// Full helm wrapper for a single upgrade/diff/install
-pub fn upgrade_wrapper(svc: &str, mode: UpgradeMode, region: &Region, conf: &Config, ver: Option<String>) -> Result<Option<UpgradeData>> {
+///
+/// Versions that break the region's `versionPolicy` are refused unless `allow_downgrade` is set,
+/// and upgrades during freezes need an emergency reason.
+pub fn upgrade_wrapper(svc: &str, mode: UpgradeMode, region: &Region, conf: &Config, ver: Option<String>, opts: UpgradeOptions) -> Result<Option<UpgradeData>> {
     if let Err(e) = webhooks::ensure_requirements(&region) {
         warn!("Could not ensure webhook requirements: {}", e);
     }
 
     let mut mf = shipcat_filebacked::load_manifest(svc, conf, region)?.complete(region)?;
+    let graph = CallerGraph::new(conf, region)?;
+    networkpolicy::inject(&mut mf, &graph, region);
+    mesh::inject(&mut mf, &graph, region)?;
 
     // Ensure we have a version - or are able to infer one
     if ver.is_some() {
@@ -463,19 +626,55 @@ pub fn upgrade_wrapper(svc: &str, mode: UpgradeMode, region: &Region, conf: &Con
     let exists = mode != UpgradeMode::UpgradeInstall && mode != UpgradeMode::UpgradeInstallNoWait;
     // Other modes can infer in a pinch
 
+    // freezes and approvals only concern changes
+    let policy_override = if mode != UpgradeMode::DiffOnly {
+        freeze::check(region, &opts.clearance)?
+    } else {
+        None
+    };
+
+    // nobody else upgrades this service until we return (before reading what is running)
+    let _lock = if mode != UpgradeMode::DiffOnly {
+        Some(lock::acquire(svc, &region.namespace, lock::ttl_for(&mf), opts.wait_for_lock)?)
+    } else {
+        None
+    };
+
+    // version running now (for policies and audits)
+    let running = if exists {
+        helpers::infer_fallback_version(&svc, &mf.namespace, region.helmVersion).ok()
+    } else {
+        None
+    };
+
     // ..but if they already exist on kube, don't block on that..
     if mf.version.is_none() {
-        mf.version = Some(helpers::infer_fallback_version(&svc, &mf.namespace)?);
+        mf.version = Some(running.clone().ok_or_else(|| ErrorKind::MissingRollingVersion(svc.into()))?);
     };
     // sanity verify what we changed (no-shoehorning in illegal versions in rolling envs)
     region.versioningScheme.verify(&mf.version.clone().unwrap())?;
+    if let Some(policy) = &region.versionPolicy {
+        if opts.allow_downgrade {
+            warn!("Not enforcing the version policy of {} for {}", region.name, svc);
+        } else {
+            policy.verify(&region.versioningScheme, running.as_ref().map(String::as_str), &mf.version.clone().unwrap())?;
+        }
+    }
+    // catch typoed versions before they sit in ImagePullBackOff
+    registry::verify_images(&mf, region)?;
+    // pin the tag so it cannot move between validate and the rollout
+    registry::inject(&mut mf, region)?;
 
     // Template values file
     let hfile = format!("{}.helm.gen.yml", &svc);
     values(&mf, Some(hfile.clone()))?;
 
     // Sanity step that gives canonical upgrade data
-    let upgrade_opt = UpgradeData::new(&mf, &hfile, mode, exists)?;
+    let mut upgrade_opt = UpgradeData::new(&mf, &hfile, mode, exists, region.helmVersion)?;
+    if let Some(ref mut udata) = upgrade_opt {
+        udata.previous_version = running;
+        udata.policy_override = policy_override;
+    }
     if let Some(ref udata) = upgrade_opt {
         webhooks::upgrade_event(UpgradeState::Pending, &udata, &region);
         match upgrade(&udata) {
@@ -509,3 +708,39 @@ pub fn upgrade_wrapper(svc: &str, mode: UpgradeMode, region: &Region, conf: &Con
     let _ = fs::remove_file(&hfile); // try to remove temporary file
     Ok(upgrade_opt)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{pick_revision, RollbackTarget};
+    use super::helpers::Revision;
+    use crate::Result;
+
+    fn history() -> Vec<Revision> {
+        (1..=4).map(|n| Revision { revision: n, status: "SUPERSEDED".into(), description: "".into() }).collect()
+    }
+    fn version_of(r: u32) -> Result<String> {
+        // revisions 2 and 3 both ran 1.4.2
+        Ok(match r { 1 => "1.4.1", 2 | 3 => "1.4.2", _ => "1.5.0" }.into())
+    }
+
+    #[test]
+    fn rollback_pick_revision() {
+        let hist = history();
+        assert_eq!(pick_revision(&hist, &RollbackTarget::Previous, version_of).unwrap(), 3);
+        assert_eq!(pick_revision(&hist, &RollbackTarget::Revision(1), version_of).unwrap(), 1);
+        assert!(pick_revision(&hist, &RollbackTarget::Revision(4), version_of).is_err()); // current
+        assert!(pick_revision(&hist, &RollbackTarget::Revision(9), version_of).is_err());
+        // latest revision with the version wins
+        assert_eq!(pick_revision(&hist, &RollbackTarget::Version("1.4.2".into()), version_of).unwrap(), 3);
+        assert_eq!(pick_revision(&hist, &RollbackTarget::Version("1.4.1".into()), version_of).unwrap(), 1);
+        // the running version is not a rollback
+        assert!(pick_revision(&hist, &RollbackTarget::Version("1.5.0".into()), version_of).is_err());
+        // failed releases of a version are skipped
+        let mut failed = history();
+        failed[2].status = "FAILED".into();
+        assert_eq!(pick_revision(&failed, &RollbackTarget::Version("1.4.2".into()), version_of).unwrap(), 2);
+        failed[1].status = "failed".into();
+        assert!(pick_revision(&failed, &RollbackTarget::Version("1.4.2".into()), version_of).is_err());
+        assert!(pick_revision(&[], &RollbackTarget::Previous, version_of).is_err());
+    }
+}
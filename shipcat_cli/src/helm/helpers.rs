@@ -1,7 +1,9 @@
+use std::collections::BTreeSet;
+
 use serde_yaml;
 
 use regex::Regex;
-use super::{Result};
+use super::{Result, HelmVersion};
 
 
 pub fn diff_format(diff: String) -> String {
@@ -12,6 +14,53 @@ pub fn diff_format(diff: String) -> String {
     }).collect::<Vec<_>>().join("\n")
 }
 
+/// Shape of a helm diff without any of its values
+///
+/// Safe to send to external systems, unlike the diff itself, which can
+/// contain secrets that are not in the manifest (eg from other charts).
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct DiffSummary {
+    /// Resources with changes, as reported by helm diff
+    pub resources: Vec<String>,
+    /// Yaml keys on changed lines
+    pub keys: BTreeSet<String>,
+    /// Number of added lines
+    pub added: usize,
+    /// Number of removed lines
+    pub removed: usize,
+}
+
+impl DiffSummary {
+    pub fn new(diff: &str) -> Self {
+        let key_re = Regex::new(r"^[+-]\s*(?:-\s+)?([A-Za-z0-9_.\-/]+):").unwrap();
+        let mut summary = DiffSummary::default();
+        for l in diff.lines() {
+            if let Some(res) = [" has changed:", " has been added:", " has been removed:"].iter()
+                .find(|suffix| l.ends_with(*suffix))
+                .map(|suffix| &l[..l.len() - suffix.len()])
+            {
+                summary.resources.push(res.to_string());
+                continue;
+            }
+            if l.starts_with('+') {
+                summary.added += 1;
+            } else if l.starts_with('-') {
+                summary.removed += 1;
+            } else {
+                continue;
+            }
+            if let Some(cap) = key_re.captures(l) {
+                summary.keys.insert(cap[1].to_string());
+            }
+        }
+        summary
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty() && self.added == 0 && self.removed == 0
+    }
+}
+
 pub fn diff_is_version_only(diff: &str, vers: (&str, &str)) -> bool {
     let smalldiff = diff_format(diff.to_string());
     trace!("Checking diff for {:?}", vers);
@@ -64,6 +113,24 @@ struct HelmVals {
     version: String,
 }
 
+/// Arguments scoping a helm command to the releases of a namespace
+///
+/// Helm 2 talks to the tiller in the namespace, helm 3 reads the releases stored in it.
+pub fn namespace_args(hv: HelmVersion, ns: &str) -> Vec<String> {
+    match hv {
+        HelmVersion::V2 => vec![format!("--tiller-namespace={}", ns)],
+        HelmVersion::V3 => vec![format!("--namespace={}", ns)],
+    }
+}
+
+/// Command that removes a release entirely
+pub fn purge_command(hv: HelmVersion, ns: &str, service: &str) -> String {
+    match hv {
+        HelmVersion::V2 => format!("helm --tiller-namespace={} del --purge {}", ns, service),
+        HelmVersion::V3 => format!("helm --namespace={} uninstall {}", ns, service),
+    }
+}
+
 pub fn hexec(args: Vec<String>) -> Result<()> {
     use std::process::Command;
     debug!("helm {}", args.join(" "));
@@ -88,14 +155,14 @@ use std::collections::HashSet;
 /// Used to warn (for now) when services run that are not in Manifest::available()
 /// This is an experimental warning only function.
 /// It is possible that `helm ls -q` is still unreliable.
-pub fn find_redundant_services(ns: &str, svcs: &[String]) -> Result<Vec<String>> {
+pub fn find_redundant_services(hv: HelmVersion, ns: &str, svcs: &[String]) -> Result<Vec<String>> {
     let requested: HashSet<_> = svcs.iter().cloned().collect();
 
-    let lsargs = vec![
-        format!("--tiller-namespace={}", ns),
+    let mut lsargs = namespace_args(hv, ns);
+    lsargs.extend_from_slice(&[
         "ls".into(),
         "-q".into()
-    ];
+    ]);
     debug!("helm {}", lsargs.join(" "));
     let found : HashSet<_> = match hout(lsargs.clone()) {
         Ok((vout, verr, true)) => {
@@ -106,7 +173,7 @@ pub fn find_redundant_services(ns: &str, svcs: &[String]) -> Result<Vec<String>>
             vout.lines().into_iter().map(String::from).collect()
         }
         _ => {
-            bail!("No services found in {} tiller", ns)
+            bail!("No services found in {} helm releases", ns)
         }
     };
     let excess : HashSet<_> = found.difference(&requested).collect();
@@ -118,14 +185,58 @@ pub fn find_redundant_services(ns: &str, svcs: &[String]) -> Result<Vec<String>>
     Ok(excess.into_iter().cloned().collect())
 }
 
-pub fn infer_fallback_version(service: &str, ns: &str) -> Result<String> {
+/// Whether helm 3 has a release for a service
+pub fn has_v3_release(service: &str, ns: &str) -> bool {
+    let mut statusvec = namespace_args(HelmVersion::V3, ns);
+    statusvec.extend_from_slice(&["status".into(), service.into()]);
+    match hout(statusvec) {
+        Ok((_, _, success)) => success,
+        Err(_) => false,
+    }
+}
+
+/// Adopt a helm 2 release into helm 3 storage using the `2to3` plugin
+///
+/// The helm 2 release is left in tiller so the region can be switched back.
+pub fn convert_release(service: &str, ns: &str, dry_run: bool) -> Result<()> {
+    let mut convertvec = vec![
+        "2to3".into(),
+        "convert".into(),
+        format!("--tiller-ns={}", ns),
+        "--release-storage=secrets".into(),
+    ];
+    if dry_run {
+        convertvec.push("--dry-run".into());
+    }
+    convertvec.push(service.into());
+    info!("helm {}", convertvec.join(" "));
+    hexec(convertvec)
+}
+
+pub fn infer_fallback_version(service: &str, ns: &str, hv: HelmVersion) -> Result<String> {
+    values_version(service, ns, hv, None)
+}
+
+/// Version a release ran at a revision
+pub fn revision_version(service: &str, ns: &str, hv: HelmVersion, revision: u32) -> Result<String> {
+    values_version(service, ns, hv, Some(revision))
+}
+
+fn values_version(service: &str, ns: &str, hv: HelmVersion, revision: Option<u32>) -> Result<String> {
     // fetch current version from helm
-    let imgvec = vec![
-        format!("--tiller-namespace={}", ns),
+    let mut imgvec = namespace_args(hv, ns);
+    imgvec.extend_from_slice(&[
         "get".into(),
         "values".into(),
         service.into(),
-    ];
+    ]);
+    if let Some(r) = revision {
+        imgvec.push(format!("--revision={}", r));
+    }
+    if hv == HelmVersion::V3 {
+        // helm 3 prefixes the values with a header unless asked for yaml
+        imgvec.extend_from_slice(&["--output".into(), "yaml".into()]);
+    }
     debug!("helm {}", imgvec.join(" "));
     match hout(imgvec.clone()) {
         // got a result from helm + rc was 0:
@@ -140,15 +251,74 @@ pub fn infer_fallback_version(service: &str, ns: &str) -> Result<String> {
         },
         _ => {
             // nothing from helm
-            bail!("Service {} not found in in {} tiller", service, ns);
+            bail!("Service {} not found in {} helm releases", service, ns);
         }
     }
 }
 
+/// A revision from `helm history`
+#[derive(Deserialize, Debug, Clone)]
+pub struct Revision {
+    pub revision: u32,
+    pub status: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Revisions of a release, oldest first
+pub fn release_history(service: &str, ns: &str, hv: HelmVersion) -> Result<Vec<Revision>> {
+    let mut histvec = namespace_args(hv, ns);
+    histvec.extend_from_slice(&[
+        "history".into(),
+        service.into(),
+        "--output".into(),
+        "json".into(),
+    ]);
+    debug!("helm {}", histvec.join(" "));
+    let (out, err, success) = hout(histvec.clone())?;
+    if !success {
+        bail!("helm {} failed: {}", histvec.join(" "), err.trim());
+    }
+    let mut revs : Vec<Revision> = serde_json::from_str(&out)?;
+    revs.sort_by_key(|r| r.revision);
+    Ok(revs)
+}
+
 
 #[cfg(test)]
 mod tests {
-    use super::{infer_version_change, diff_is_version_only};
+    use super::{infer_version_change, diff_is_version_only, namespace_args, purge_command, DiffSummary};
+    use super::HelmVersion;
+
+    #[test]
+    fn helm_version_namespace_args() {
+        assert_eq!(namespace_args(HelmVersion::V2, "apps"), vec!["--tiller-namespace=apps"]);
+        assert_eq!(namespace_args(HelmVersion::V3, "apps"), vec!["--namespace=apps"]);
+        assert_eq!(purge_command(HelmVersion::V2, "apps", "fake-ask"), "helm --tiller-namespace=apps del --purge fake-ask");
+        assert_eq!(purge_command(HelmVersion::V3, "apps", "fake-ask"), "helm --namespace=apps uninstall fake-ask");
+    }
+
+    #[test]
+    fn diff_summary_drops_values() {
+        let input = "default, fake-ask, Deployment (apps) has changed:
+  # Source: base/templates/deployment.yaml
+-         image: \"quay.io/babylonhealth/fake-ask:1.0.0\"
++         image: \"quay.io/babylonhealth/fake-ask:1.1.0\"
++         - name: DB_PASSWORD
++           value: hunter2hunter2
+default, fake-ask-secrets, Secret (v1) has changed:
+-   API_TOKEN: c2VjcmV0c2VjcmV0";
+        let summary = DiffSummary::new(input);
+        assert_eq!(summary.resources, vec![
+            "default, fake-ask, Deployment (apps)".to_string(),
+            "default, fake-ask-secrets, Secret (v1)".to_string(),
+        ]);
+        assert_eq!(summary.keys.iter().map(String::as_str).collect::<Vec<_>>(),
+            vec!["API_TOKEN", "image", "name", "value"]);
+        assert_eq!((summary.added, summary.removed), (3, 2));
+        assert!(!format!("{:?}", summary).contains("hunter2"));
+        assert!(DiffSummary::new("").is_empty());
+    }
 
     #[test]
     fn version_change_test() {
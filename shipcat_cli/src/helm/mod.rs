@@ -1,7 +1,7 @@
 /// Allow normal error handling from structs
 pub use super::{Result, ResultExt, ErrorKind, Error};
 /// Verify trait gets the Config
-pub use super::{Config, Region, VersionScheme, AuditWebhook};
+pub use super::{Config, Region, VersionScheme, AuditWebhook, HelmVersion};
 /// Need basic manifest handling
 pub use super::Manifest;
 
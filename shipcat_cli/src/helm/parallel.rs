@@ -1,12 +1,16 @@
 use threadpool::ThreadPool;
 use std::sync::mpsc::channel;
+use std::sync::Arc;
 use std::fs;
 
 use super::{Config, Manifest, Region};
 use super::{UpgradeMode, UpgradeData};
-use super::direct;
+use super::direct::{self, UpgradeOptions};
 use super::helpers;
 use super::kube;
+use crate::{freeze, lock, mesh, networkpolicy, registry};
+use crate::audit::AuditOverride;
+use crate::networkpolicy::CallerGraph;
 use crate::webhooks::{self, UpgradeState};
 use super::{Result, Error, ErrorKind};
 
@@ -22,10 +26,18 @@ pub fn reconcile(svcs: Vec<Manifest>, conf: &Config, region: &Region, umode: Upg
     let n_jobs = svcs.len();
     let pool = ThreadPool::new(n_workers);
     info!("Starting {} parallel helm jobs using {} workers", n_jobs, n_workers);
-    webhooks::reconcile_event(UpgradeState::Pending, &region);
+    // fail before any upgrade if the region is frozen
+    let ovr = if umode != UpgradeMode::DiffOnly {
+        freeze::check(region, &freeze::Clearance::from_env())?
+    } else {
+        None
+    };
+    webhooks::reconcile_event(UpgradeState::Pending, &region, ovr.as_ref());
 
     // get a list of services for find_redundant_services (done at end)
     let expected : Vec<String> = svcs.iter().map(|mf| mf.name.clone()).collect();
+    // who calls who is shared by all the workers
+    let graph = Arc::new(CallerGraph::new(conf, region)?);
 
     let (tx, rx) = channel();
     for mf in svcs {
@@ -33,11 +45,13 @@ pub fn reconcile(svcs: Vec<Manifest>, conf: &Config, region: &Region, umode: Upg
         let mode = umode.clone();
         let reg = region.clone();
         let config = conf.clone();
+        let graph = graph.clone();
+        let ovr = ovr.clone();
 
         let tx = tx.clone(); // tx channel reused in each thread
         pool.execute(move || {
             info!("Running {} for {}", mode, mf.name);
-            let res = reconcile_worker(mf, mode, config, reg);
+            let res = reconcile_worker(mf, mode, config, reg, &graph, ovr);
             tx.send(res).expect("channel will be there waiting for the pool");
         });
     }
@@ -61,15 +75,15 @@ pub fn reconcile(svcs: Vec<Manifest>, conf: &Config, region: &Region, umode: Upg
             },
             // remaining cases not ignorable
             _ => {
-                webhooks::reconcile_event(UpgradeState::Failed, &region);
+                webhooks::reconcile_event(UpgradeState::Failed, &region, ovr.as_ref());
                 return Err(e)
             },
         }
     }
-    webhooks::reconcile_event(UpgradeState::Completed, &region);
+    webhooks::reconcile_event(UpgradeState::Completed, &region, ovr.as_ref());
 
     // check for redundant services (informational only for now)
-    let _ = helpers::find_redundant_services(&region.namespace, &expected);
+    let _ = helpers::find_redundant_services(region.helmVersion, &region.namespace, &expected);
     Ok(())
 }
 
@@ -77,14 +91,24 @@ pub fn reconcile(svcs: Vec<Manifest>, conf: &Config, region: &Region, umode: Upg
 /// Parallel reconcile worker that reports information sequentially
 ///
 /// This logs errors and upgrade successes individually.
+/// The deploy policy must have been checked by the caller, which passes on its overrides.
 /// NB: This can reconcile lock-step upgraded services at the moment.
-pub fn reconcile_worker(mut mf: Manifest, mode: UpgradeMode, _conf: Config, region: Region) -> Result<Option<UpgradeData>> {
+pub fn reconcile_worker(mut mf: Manifest, mode: UpgradeMode, _conf: Config, region: Region, graph: &CallerGraph, policy_override: Option<AuditOverride>) -> Result<Option<UpgradeData>> {
     mf = mf.complete(&region)?;
+    networkpolicy::inject(&mut mf, graph, &region);
+    mesh::inject(&mut mf, graph, &region)?;
     let svc = mf.name.clone();
 
+    // wait for upgrades of the service in progress
+    let _lock = if mode != UpgradeMode::DiffOnly {
+        Some(lock::acquire(&svc, &region.namespace, lock::ttl_for(&mf), true)?)
+    } else {
+        None
+    };
+
     // get version running now (to limit race condition with deploys)
     // this query also lets us detect if we have to install or simply upgrade
-    let (exists, fallback) = match helpers::infer_fallback_version(&svc, &region.namespace) {
+    let (exists, fallback) = match helpers::infer_fallback_version(&svc, &region.namespace, region.helmVersion) {
         Ok(running_ver) => (true, running_ver),
         Err(e) => {
             if let Some(v) = mf.version.clone() {
@@ -103,17 +127,33 @@ pub fn reconcile_worker(mut mf: Manifest, mode: UpgradeMode, _conf: Config, regi
 
     // only override version if not in manifests
     if mf.version.is_none() {
-         mf.version = Some(fallback)
+         mf.version = Some(fallback.clone())
     };
     // sanity verify what we changed (no-shoehorning in illegal versions in rolling envs)
     region.versioningScheme.verify(&mf.version.clone().unwrap())?;
+    // reconciles can only skip the version policy through the environment
+    if let (true, Some(policy)) = (exists, &region.versionPolicy) {
+        if UpgradeOptions::from_env().allow_downgrade {
+            warn!("Not enforcing the version policy of {} for {}", region.name, svc);
+        } else {
+            policy.verify(&region.versioningScheme, Some(&fallback), &mf.version.clone().unwrap())?;
+        }
+    }
+    // pin the tag so it cannot move during the rollout
+    registry::inject(&mut mf, &region)?;
 
 
     // Template values file
     let hfile = format!("{}.helm.gen.yml", &svc);
     direct::values(&mf, Some(hfile.clone()))?;
 
-    let upgrade_opt = UpgradeData::new(&mf, &hfile, mode, exists)?;
+    let mut upgrade_opt = UpgradeData::new(&mf, &hfile, mode, exists, region.helmVersion)?;
+    if let Some(ref mut udata) = upgrade_opt {
+        if exists {
+            udata.previous_version = Some(fallback);
+        }
+        udata.policy_override = policy_override;
+    }
     if let Some(ref udata) = upgrade_opt {
         webhooks::upgrade_event(UpgradeState::Pending, &udata, &region);
 
@@ -65,6 +65,9 @@ impl KongCrdOutput {
 }
 
 pub fn generate_kong_output(conf: &Config, region: &Region) -> Result<KongOutput> {
+    if region.ingress.is_some() {
+        bail!("Region {} exposes services through ingresses instead of kong", region.name);
+    }
     let mut apis = BTreeMap::new();
 
     // Generate list of APIs to feed to Kong
@@ -54,13 +54,14 @@ fn rollout_status(mf: &Manifest) -> Result<bool> {
     let statusvec = vec![
         "rollout".into(),
         "status".into(),
-        format!("deployment/{}", mf.name.clone()), // always one deployment with same name
+        // always one deployment or statefulset with same name
+        format!("{}/{}", mf.workload.kube_kind(), mf.name.clone()),
         format!("-n={}", mf.namespace),
         "--watch=false".into(), // always just print current status
     ];
     let (rollres, _) = kout(statusvec)?;
     debug!("{}", rollres);
-    if rollres.contains("successfully rolled out") {
+    if rollout_complete(&rollres) {
         Ok(true)
     } else {
         // TODO: check if any of the new pods have restarts in them
@@ -69,6 +70,15 @@ fn rollout_status(mf: &Manifest) -> Result<bool> {
     }
 }
 
+/// Whether `kubectl rollout status` output signals a finished rollout
+///
+/// Deployments and StatefulSets report completion differently.
+fn rollout_complete(status: &str) -> bool {
+    status.contains("successfully rolled out") // deployments
+        || status.contains("rolling update complete") // statefulsets
+        || status.contains("partitioned roll out complete") // statefulsets with a partition
+}
+
 /// A replacement for helm upgrade's --wait and --timeout
 pub fn await_rollout_status(mf: &Manifest) -> Result<bool> {
     use std::{thread, time};
@@ -81,7 +91,7 @@ pub fn await_rollout_status(mf: &Manifest) -> Result<bool> {
         Ok(false) => debug!("Ignoring rollout failure right after upgrade"),
         Err(e) => warn!("Ignoring rollout failure right after upgrade: {}", e),
     };
-    info!("Waiting {}s for deployment {} to rollout (not ready yet)", waittime, mf.name);
+    info!("Waiting {}s for {} {} to rollout (not ready yet)", waittime, mf.workload.kube_kind(), mf.name);
     for i in 1..10 {
         trace!("poll iteration {}", i);
         let mut waited = 0;
@@ -410,12 +420,12 @@ pub fn port_forward(mf: &Manifest) -> Result<()> {
     // first 1024 ports need sudo so avoid that
     let localport = if port <= 1024 { 7777 } else { port };
 
-    debug!("Port forwarding kube deployment {} to localhost:{}", mf.name, localport);
+    debug!("Port forwarding kube {} {} to localhost:{}", mf.workload.kube_kind(), mf.name, localport);
     //kubectl port-forward deployment/${name} localport:httpPort
     let pfargs = vec![
         format!("-n={}", mf.namespace),
         "port-forward".into(),
-        format!("deployment/{}", mf.name),
+        format!("{}/{}", mf.workload.kube_kind(), mf.name),
         format!("{}:{}", port, port)
     ];
     kexec(pfargs)?;
@@ -469,6 +479,139 @@ pub fn apply_crd<T: Into<Crd<T>> + Serialize>(name: &str, data: T, ns: &str) ->
     let _ = fs::remove_file(&crdfile); // try to remove temporary file
     Ok(changed)
 }
+/// Fetch a ConfigMap as json, if it exists
+pub fn get_configmap(name: &str, ns: &str) -> Result<Option<serde_json::Value>> {
+    let getargs = vec![
+        "get".into(),
+        format!("-n={}", ns),
+        "configmap".into(),
+        name.into(),
+        "-ojson".into(),
+        "--ignore-not-found".into(),
+    ];
+    let (out, status) = kout(getargs.clone())?;
+    if !status {
+        bail!("subprocess failure from kubectl: {:?}", getargs);
+    }
+    if out.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&out)?))
+}
+
+/// Create or replace a ConfigMap from json
+///
+/// Replacing fails when the `resourceVersion` in the json is stale,
+/// so callers can retry read-modify-write cycles on conflicts.
+pub fn write_configmap(cm: &serde_json::Value, ns: &str, create: bool) -> Result<()> {
+    use std::fs::{self, File};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let pth = std::env::temp_dir().join(format!("shipcat-configmap-{}-{}.json", std::process::id(), nanos));
+    serde_json::to_writer(File::create(&pth)?, cm)?;
+    let args = vec![
+        format!("-n={}", ns),
+        if create { "create".into() } else { "replace".into() },
+        "-f".into(),
+        format!("{}", pth.display()),
+    ];
+    let res = kout(args.clone());
+    let _ = fs::remove_file(&pth); // try to remove temporary file
+    let (_, status) = res?;
+    if !status {
+        bail!("subprocess failure from kubectl: {:?}", args);
+    }
+    Ok(())
+}
+
+/// Fetch all ConfigMaps matching a label selector as json
+pub fn list_configmaps(ns: &str, selector: &str) -> Result<Vec<serde_json::Value>> {
+    let getargs = vec![
+        "get".into(),
+        format!("-n={}", ns),
+        "configmaps".into(),
+        format!("-l={}", selector),
+        "-ojson".into(),
+    ];
+    let (out, status) = kout(getargs.clone())?;
+    if !status {
+        bail!("subprocess failure from kubectl: {:?}", getargs);
+    }
+    let list : serde_json::Value = serde_json::from_str(&out)?;
+    Ok(list["items"].as_array().cloned().unwrap_or_default())
+}
+
+/// Delete a ConfigMap if it exists
+pub fn delete_configmap(name: &str, ns: &str) -> Result<()> {
+    let delargs = vec![
+        format!("-n={}", ns),
+        "delete".into(),
+        "configmap".into(),
+        name.into(),
+        "--ignore-not-found".into(),
+    ];
+    kexec(delargs)
+}
+
+/// Delete a ConfigMap only if it is still at `resource_version`
+///
+/// Fails when someone else has written the ConfigMap since it was read.
+pub fn delete_configmap_at(name: &str, ns: &str, resource_version: &str) -> Result<()> {
+    use std::fs::{self, File};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let opts = serde_json::json!({
+        "kind": "DeleteOptions",
+        "apiVersion": "v1",
+        "preconditions": { "resourceVersion": resource_version },
+    });
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let pth = std::env::temp_dir().join(format!("shipcat-delete-{}-{}.json", std::process::id(), nanos));
+    serde_json::to_writer(File::create(&pth)?, &opts)?;
+    let args = vec![
+        "delete".into(),
+        format!("--raw=/api/v1/namespaces/{}/configmaps/{}", ns, name),
+        "-f".into(),
+        format!("{}", pth.display()),
+    ];
+    let res = kout(args.clone());
+    let _ = fs::remove_file(&pth); // try to remove temporary file
+    let (_, status) = res?;
+    if !status {
+        bail!("subprocess failure from kubectl: {:?}", args);
+    }
+    Ok(())
+}
+
+/// Names of the helm 2 releases stored by the tiller in a namespace
+pub fn tiller_releases(ns: &str) -> Result<Vec<String>> {
+    let getargs = vec![
+        "get".into(),
+        format!("-n={}", ns),
+        "configmaps".into(),
+        "-l=OWNER=TILLER".into(),
+        // no shell here, so no quotes around the expression
+        "-ojsonpath={.items[*].metadata.labels.NAME}".into(),
+    ];
+    let (out, status) = kout(getargs.clone())?;
+    if !status {
+        bail!("subprocess failure from kubectl: {:?}", getargs);
+    }
+    Ok(release_names(&out))
+}
+
+/// Unique release names from the space separated labels of their revisions
+fn release_names(out: &str) -> Vec<String> {
+    // one configmap per revision
+    let mut names = out.split_whitespace()
+        .map(|n| n.trim_matches('\''))
+        .filter(|n| !n.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
 /// Find all ManifestCrds in a given namespace
 ///
 /// Allows us to purge manifests that are not in Manifest::available()
@@ -536,7 +679,16 @@ pub fn remove_redundant_manifests(ns: &str, svcs: &Vec<String>) -> Result<Vec<St
 #[cfg(test)]
 mod tests {
     use dirs;
-    use super::current_context;
+    use super::{current_context, release_names, rollout_complete};
+
+    #[test]
+    fn tiller_release_names() {
+        assert_eq!(release_names("fake-ask fake-storage fake-ask\n"), vec!["fake-ask", "fake-storage"]);
+        assert_eq!(release_names(""), Vec::<String>::new());
+        // older invocations quoted the jsonpath, which kubectl echoed back
+        assert_eq!(release_names("'fake-ask fake-ask'"), vec!["fake-ask"]);
+        assert_eq!(release_names("''"), Vec::<String>::new());
+    }
 
     #[test]
     fn validate_ctx() {
@@ -548,4 +700,12 @@ mod tests {
             assert_ne!(ctx, "");
         }
     }
+
+    #[test]
+    fn rollout_outputs() {
+        assert!(rollout_complete("deployment \"fake-ask\" successfully rolled out"));
+        assert!(rollout_complete("statefulset rolling update complete 3 pods at revision fake-ask-5d8b9\n"));
+        assert!(!rollout_complete("Waiting for 1 pods to be ready...\n"));
+        assert!(!rollout_complete("Waiting for deployment \"fake-ask\" rollout to finish: 1 of 2 updated replicas are available..."));
+    }
 }
@@ -35,14 +35,6 @@ error_chain! {
             description("SLACK_SHIPCAT_CHANNEL not specified")
             display("SLACK_SHIPCAT_CHANNEL not specified")
         }
-        MissingGrafanaUrl {
-            description("GRAFANA_SHIPCAT_HOOK_URL not specified")
-            display("GRAFANA_SHIPCAT_HOOK_URL not specified")
-        }
-        MissingGrafanaToken {
-            description("GRAFANA_SHIPCAT_TOKEN not specified")
-            display("GRAFANA_SHIPCAT_TOKEN not specified")
-        }
         Url(url: reqwest::Url) {
             description("could not access URL")
             display("could not access URL '{}'", &url)
@@ -67,13 +59,30 @@ error_chain! {
             description("slack message send failed")
             display("Failed to send the slack message to '{}' ", &hook)
         }
+        DigestResolutionFailure(image: String) {
+            description("image digest resolution failed")
+            display("Failed to resolve the digest of {}", &image)
+        }
+        ImageCheckFailure(image: String) {
+            description("image existence check failed")
+            display("Failed to check that {} exists", &image)
+        }
+        MissingImage(image: String, registry: String) {
+            description("image does not exist")
+            display("Image {} does not exist in {} (typo in version or image not pushed?)", &image, &registry)
+        }
+        ServiceLocked(svc: String, holder: String, expires: String) {
+            description("service is locked by another deploy")
+            display("{} is locked by {} until {} (use --wait-for-lock or shipcat lock)", &svc, &holder, &expires)
+        }
     }
 }
 
 pub use shipcat_definitions::{Manifest, ConfigType};
 pub use shipcat_definitions::structs;
 pub use shipcat_definitions::config::{self, Config, Team};
-pub use shipcat_definitions::region::{Region, VersionScheme, KongConfig, Webhook, AuditWebhook};
+pub use shipcat_definitions::region::{Region, VersionScheme, KongConfig, Webhook, AuditWebhook, SlackWebhook, GrafanaWebhook, HttpWebhook, AuditLogWebhook, SlackRoute, HelmVersion};
+pub use shipcat_definitions::region::{DeployPolicy, FreezeWindow, ApprovalPolicy};
 //pub use shipcat_definitions::Product;
 
 /// Convenience listers
@@ -84,6 +93,14 @@ pub mod slack;
 pub mod grafana;
 /// Audit objects and API caller
 pub mod audit;
+/// Signed http delivery with retries and a local spool
+pub mod delivery;
+/// Append-only audit log and history timelines
+pub mod auditlog;
+/// Lease based locks against concurrent upgrades
+pub mod lock;
+/// Deploy freeze windows and change approvals
+pub mod freeze;
 /// Cluster level operations
 pub mod cluster;
 
@@ -123,6 +140,24 @@ pub mod webhooks;
 /// Simple printers
 pub mod show;
 
+/// JSON Schema generation for manifests and config
+pub mod schema;
+
+/// Rewriting of deprecated manifest syntax
+pub mod migrate;
+
+/// Scaffolding of new services
+pub mod scaffold;
+
+/// Network policy generation from dependencies
+pub mod networkpolicy;
+
+/// Istio service mesh generation
+pub mod mesh;
+
+/// Docker registry v2 client for image digests
+pub mod registry;
+
 /// Smart initialiser with safety
 ///
 /// Tricks the library into reading from your manifest location.
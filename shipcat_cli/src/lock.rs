@@ -0,0 +1,229 @@
+use std::env;
+use std::thread;
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde_json::Value;
+
+use crate::audit::AuditInitiator;
+use crate::kube;
+use super::{Manifest, Result, ErrorKind};
+
+/// Label put on every lock ConfigMap
+pub const LOCK_LABEL: &str = "shipcat-lock";
+
+/// How often a held lock is polled when waiting for it
+const POLL_SECS: u64 = 10;
+
+/// Longest time to wait for a lock
+const MAX_WAIT_SECS: i64 = 60 * 60;
+
+/// A lease on upgrading a service in a region
+#[derive(Debug, Clone)]
+pub struct Lease {
+    pub service: String,
+    /// Who took the lock
+    pub holder: String,
+    pub acquired: DateTime<Utc>,
+    /// Leases past this can be taken over
+    pub expires: DateTime<Utc>,
+}
+
+impl Lease {
+    fn from_configmap(cm: &Value) -> Option<Lease> {
+        let data = &cm["data"];
+        let time = |k: &str| data[k].as_str()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc));
+        Some(Lease {
+            service: data["service"].as_str()?.to_string(),
+            holder: data["holder"].as_str()?.to_string(),
+            acquired: time("acquired")?,
+            expires: time("expires")?,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires < Utc::now()
+    }
+}
+
+/// Identity of this process as a lock holder
+///
+/// Who started it (ci job or user), where and which process.
+pub fn holder() -> String {
+    let who = AuditInitiator::infer();
+    let host = env::var("HOSTNAME").unwrap_or_else(|_| "unknown".into());
+    format!("{}:{}@{}/{}", who.kind, who.name, host, std::process::id())
+}
+
+/// Lease length for upgrading a manifest
+///
+/// Covers a rollout and a rollback, plus some time for diffs and hooks.
+pub fn ttl_for(mf: &Manifest) -> Duration {
+    Duration::seconds(2 * i64::from(mf.estimate_wait_time()) + 300)
+}
+
+fn configmap_name(svc: &str) -> String {
+    format!("{}-shipcat-lock", svc)
+}
+
+/// A held lock, released when dropped
+pub struct DeployLock {
+    service: String,
+    namespace: String,
+    holder: String,
+}
+
+impl Drop for DeployLock {
+    fn drop(&mut self) {
+        // only release what is still ours, it may have expired and been taken over
+        let name = configmap_name(&self.service);
+        let res = kube::get_configmap(&name, &self.namespace).and_then(|cm| {
+            let ours = cm.as_ref()
+                .and_then(|c| Some((Lease::from_configmap(c)?, c["metadata"]["resourceVersion"].as_str()?)))
+                .filter(|(l, _)| l.holder == self.holder);
+            match ours {
+                // a takeover between the read and the delete makes the precondition fail
+                Some((_, rv)) => kube::delete_configmap_at(&name, &self.namespace, rv),
+                None => {
+                    warn!("Lock on {} was taken over before it was released", self.service);
+                    Ok(())
+                }
+            }
+        });
+        if let Err(e) = res {
+            warn!("Failed to release the lock on {}: {}", self.service, e);
+        }
+    }
+}
+
+/// Take the upgrade lock of a service for `ttl`
+///
+/// Expired leases are taken over. Held leases fail with `ServiceLocked`,
+/// unless `wait` is set, in which case the lock is polled for up to an hour.
+pub fn acquire(svc: &str, ns: &str, ttl: Duration, wait: bool) -> Result<DeployLock> {
+    let holder = holder();
+    let give_up = Utc::now() + Duration::seconds(MAX_WAIT_SECS);
+    loop {
+        let held = match try_acquire(svc, ns, &holder, ttl) {
+            Ok(None) => {
+                debug!("Locked {} as {}", svc, holder);
+                return Ok(DeployLock { service: svc.into(), namespace: ns.into(), holder });
+            }
+            Ok(Some(lease)) => lease,
+            Err(e) => {
+                // usually someone else writing the lock at the same time
+                if !wait || Utc::now() > give_up {
+                    return Err(e);
+                }
+                warn!("Failed to lock {}: {}", svc, e);
+                thread::sleep(std::time::Duration::from_secs(POLL_SECS));
+                continue;
+            }
+        };
+        let expires = held.expires.to_rfc3339_opts(SecondsFormat::Secs, true);
+        if !wait || Utc::now() > give_up {
+            return Err(ErrorKind::ServiceLocked(svc.into(), held.holder, expires).into());
+        }
+        info!("Waiting for the lock on {} held by {} (until {})", svc, held.holder, expires);
+        thread::sleep(std::time::Duration::from_secs(POLL_SECS));
+    }
+}
+
+/// One attempt at taking a lock, returning the lease that blocks it if any
+fn try_acquire(svc: &str, ns: &str, holder: &str, ttl: Duration) -> Result<Option<Lease>> {
+    let now = Utc::now();
+    let (mut cm, create) = match kube::get_configmap(&configmap_name(svc), ns)? {
+        Some(cm) => {
+            if let Some(lease) = Lease::from_configmap(&cm) {
+                if !lease.is_expired() && lease.holder != holder {
+                    return Ok(Some(lease));
+                }
+                if lease.holder != holder {
+                    warn!("Taking over the expired lock on {} from {}", svc, lease.holder);
+                }
+            }
+            (cm, false)
+        }
+        None => {
+            let mut cm = Value::Null;
+            cm["apiVersion"] = "v1".into();
+            cm["kind"] = "ConfigMap".into();
+            cm["metadata"]["name"] = configmap_name(svc).into();
+            cm["metadata"]["namespace"] = ns.into();
+            cm["metadata"]["labels"][LOCK_LABEL] = "true".into();
+            (cm, true)
+        }
+    };
+    cm["data"]["service"] = svc.into();
+    cm["data"]["holder"] = holder.into();
+    cm["data"]["acquired"] = now.to_rfc3339_opts(SecondsFormat::Secs, true).into();
+    cm["data"]["expires"] = (now + ttl).to_rfc3339_opts(SecondsFormat::Secs, true).into();
+    // create fails if it exists, replace fails if the resourceVersion moved
+    if let Err(e) = kube::write_configmap(&cm, ns, create) {
+        // report a lock someone else got in first as held rather than as a kubectl error
+        match get(svc, ns) {
+            Ok(Some(lease)) if lease.holder != holder && !lease.is_expired() => return Ok(Some(lease)),
+            _ => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// The current lease on a service, if any
+pub fn get(svc: &str, ns: &str) -> Result<Option<Lease>> {
+    Ok(kube::get_configmap(&configmap_name(svc), ns)?.and_then(|cm| Lease::from_configmap(&cm)))
+}
+
+/// All leases in a namespace, including expired ones
+pub fn list(ns: &str) -> Result<Vec<Lease>> {
+    let cms = kube::list_configmaps(ns, &format!("{}=true", LOCK_LABEL))?;
+    Ok(cms.iter().filter_map(Lease::from_configmap).collect())
+}
+
+/// Remove the lock on a service regardless of who holds it
+pub fn break_lock(svc: &str, ns: &str) -> Result<()> {
+    match get(svc, ns)? {
+        Some(l) => warn!("Breaking the lock on {} held by {}", svc, l.holder),
+        None => info!("{} is not locked", svc),
+    }
+    kube::delete_configmap(&configmap_name(svc), ns)
+}
+
+/// Print the leases in a namespace, or of one service
+pub fn show(svc: Option<&str>, ns: &str) -> Result<()> {
+    let leases = match svc {
+        Some(s) => get(s, ns)?.into_iter().collect(),
+        None => list(ns)?,
+    };
+    if leases.is_empty() {
+        info!("No locks in {}", ns);
+    }
+    for l in leases {
+        let state = if l.is_expired() { "expired" } else { "held" };
+        println!("{:<30} {:<7} {:<50} since {} until {}", l.service, state, l.holder,
+            l.acquired.format("%Y-%m-%d %H:%M:%S"), l.expires.format("%Y-%m-%d %H:%M:%S"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lease;
+    use serde_json::Value;
+
+    #[test]
+    fn lock_lease_from_configmap() {
+        let mut cm = Value::Null;
+        cm["data"]["service"] = "fake-ask".into();
+        cm["data"]["holder"] = "jenkins:deploy#12@agent-3/4242".into();
+        cm["data"]["acquired"] = "2019-03-01T10:00:00Z".into();
+        cm["data"]["expires"] = "2019-03-01T10:30:00Z".into();
+        let lease = Lease::from_configmap(&cm).unwrap();
+        assert_eq!(lease.holder, "jenkins:deploy#12@agent-3/4242");
+        assert!(lease.is_expired());
+
+        cm["data"]["expires"] = "not a time".into();
+        assert!(Lease::from_configmap(&cm).is_none());
+    }
+}
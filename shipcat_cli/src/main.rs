@@ -3,6 +3,7 @@
 
 use shipcat::*;
 use clap::{Arg, App, AppSettings, SubCommand, ArgMatches};
+use shipcat::helm::direct::{RollbackTarget, UpgradeOptions};
 use std::process;
 
 fn print_error_debug(e: &Error) {
@@ -69,6 +70,18 @@ fn main() {
             .subcommand(SubCommand::with_name("diff")
                 .about("Diff kubernetes configs with local state"))
             .subcommand(SubCommand::with_name("rollback")
+                .arg(Arg::with_name("to-version")
+                    .long("to-version")
+                    .takes_value(true)
+                    .conflicts_with("revision")
+                    .help("Roll back to the latest release of a version"))
+                .arg(Arg::with_name("revision")
+                    .long("revision")
+                    .takes_value(true)
+                    .help("Roll back to a helm revision"))
+                .arg(Arg::with_name("wait-for-lock")
+                    .long("wait-for-lock")
+                    .help("Wait for other upgrades of the service to finish rather than fail"))
                 .about("Rollback deployment (and children) to previous"))
             .subcommand(SubCommand::with_name("history")
                 .about("Show helm history for a service"))
@@ -85,7 +98,21 @@ fn main() {
                     .long("auto-rollback"))
                 .arg(Arg::with_name("dryrun")
                     .long("dry-run")
-                    .help("Show the diff only"))))
+                    .help("Show the diff only"))
+                .arg(Arg::with_name("allow-downgrade")
+                    .long("allow-downgrade")
+                    .help("Skip the region's downgrade and pre-release protection"))
+                .arg(Arg::with_name("wait-for-lock")
+                    .long("wait-for-lock")
+                    .help("Wait for other upgrades of the service to finish rather than fail"))
+                .arg(Arg::with_name("emergency-reason")
+                    .long("emergency-reason")
+                    .takes_value(true)
+                    .help("Why the upgrade has to go out during a deploy freeze"))
+                .arg(Arg::with_name("approval")
+                    .long("approval")
+                    .takes_value(true)
+                    .help("Change approval token or ticket reference"))))
 
         .subcommand(SubCommand::with_name("shell")
             .about("Shell into pods for a service described in a manifest")
@@ -106,6 +133,29 @@ fn main() {
                 .required(true)
                 .help("Service name")))
 
+        .subcommand(SubCommand::with_name("history")
+            .about("Show a timeline of upgrades in a region from its audit log")
+            .arg(Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .default_value("2d")
+                .help("How far back to look (eg 30m, 12h, 2d, 1w)")))
+
+        .subcommand(SubCommand::with_name("lock")
+            .about("Show or break the locks taken by upgrades in a region")
+            .arg(Arg::with_name("break")
+                .long("break")
+                .requires("service")
+                .help("Remove the lock on the service regardless of its holder"))
+            .arg(Arg::with_name("service")
+                .help("Service to show the lock of")))
+
+        .subcommand(SubCommand::with_name("webhooks")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("flush")
+                .about("Replay events that could not be delivered to audit and http webhooks"))
+            .about("Webhook delivery maintenance"))
+
         .subcommand(SubCommand::with_name("slack")
             .arg(Arg::with_name("url")
                 .short("u")
@@ -235,7 +285,12 @@ fn main() {
                 .subcommand(SubCommand::with_name("reconcile")
                     .about("Reconcile kubernetes region configs with local state"))
                 .subcommand(SubCommand::with_name("diff")
-                    .about("Diff kubernetes region configs with local state"))))
+                    .about("Diff kubernetes region configs with local state"))
+                .subcommand(SubCommand::with_name("migrate")
+                    .arg(Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Only show what would be migrated"))
+                    .about("Adopt helm 2 releases in the region into helm 3"))))
         // all the listers (hidden from cli output)
         .subcommand(SubCommand::with_name("list-regions")
             .setting(AppSettings::Hidden)
@@ -293,11 +348,43 @@ fn main() {
               .arg(Arg::with_name("no-wait")
                     .long("no-wait")
                     .help("Do not wait for service timeout"))
+              .arg(Arg::with_name("allow-downgrade")
+                    .long("allow-downgrade")
+                    .help("Skip the region's downgrade and pre-release protection"))
+              .arg(Arg::with_name("wait-for-lock")
+                    .long("wait-for-lock")
+                    .help("Wait for other upgrades of the service to finish rather than fail"))
+              .arg(Arg::with_name("emergency-reason")
+                    .long("emergency-reason")
+                    .takes_value(true)
+                    .help("Why the upgrade has to go out during a deploy freeze"))
+              .arg(Arg::with_name("approval")
+                    .long("approval")
+                    .takes_value(true)
+                    .help("Change approval token or ticket reference"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to upgrad"))
             .about("Apply a service's configuration in kubernetes (through helm)"))
 
+        .subcommand(SubCommand::with_name("rollback")
+              .arg(Arg::with_name("to-version")
+                .long("to-version")
+                .takes_value(true)
+                .conflicts_with("revision")
+                .help("Roll back to the latest release of a version"))
+              .arg(Arg::with_name("revision")
+                .long("revision")
+                .takes_value(true)
+                .help("Roll back to a helm revision"))
+              .arg(Arg::with_name("wait-for-lock")
+                .long("wait-for-lock")
+                .help("Wait for other upgrades of the service to finish rather than fail"))
+              .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service to roll back"))
+            .about("Roll back a service to its previous release, a revision, or a version"))
+
         .subcommand(SubCommand::with_name("env")
               .arg(Arg::with_name("service")
                 .required(true)
@@ -308,7 +395,12 @@ fn main() {
               .arg(Arg::with_name("git")
                 .long("git")
                 .global(true)
-                .help("Comparing with master using a temporary git stash and git checkout"))
+                .help("Compare with master (or --ref) by reading manifests from git"))
+              .arg(Arg::with_name("ref")
+                .long("ref")
+                .takes_value(true)
+                .requires("git")
+                .help("Git revision to compare with when using --git"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to be diffed"))
@@ -334,6 +426,61 @@ fn main() {
             .subcommand(SubCommand::with_name("verify")
                 .about("Verify the parsed config")))
 
+        // schemas
+        .subcommand(SubCommand::with_name("schema")
+            .arg(Arg::with_name("kind")
+                .required(true)
+                .possible_values(&["manifest", "overrides", "fragment", "config"])
+                .help("Kind of file to generate the schema for"))
+            .about("Generate JSON Schema for shipcat.yml, override files or shipcat.conf"))
+
+        // migrations
+        .subcommand(SubCommand::with_name("migrate")
+            .arg(Arg::with_name("services")
+                .multiple(true)
+                .help("Services to migrate (defaults to all)"))
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Show a diff of the changes without writing them"))
+            .arg(Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .help("Only apply migrations introduced after this shipcat version"))
+            .arg(Arg::with_name("list")
+                .long("list")
+                .conflicts_with("services")
+                .help("List available migrations"))
+            .about("Rewrite deprecated syntax in manifests in place"))
+
+        .subcommand(SubCommand::with_name("new")
+            .arg(Arg::with_name("service")
+                .required(true)
+                .help("Name of the new service"))
+            .arg(Arg::with_name("team")
+                .long("team")
+                .short("t")
+                .takes_value(true)
+                .required(true)
+                .help("Owning team from shipcat.conf"))
+            .arg(Arg::with_name("language")
+                .long("language")
+                .short("l")
+                .takes_value(true)
+                .help("Language skeleton to use from templates/skeletons"))
+            .arg(Arg::with_name("kong")
+                .long("kong")
+                .help("Expose the service through kong"))
+            .arg(Arg::with_name("regions")
+                .long("regions")
+                .takes_value(true)
+                .use_delimiter(true)
+                .help("Comma separated regions to deploy to (defaults to all dev regions)"))
+            .arg(Arg::with_name("repo")
+                .long("repo")
+                .takes_value(true)
+                .help("Git repository of the service"))
+            .about("Scaffold a new service in the manifests repo"))
+
         // products
         .subcommand(SubCommand::with_name("product")
             .setting(AppSettings::SubcommandRequiredElseHelp)
@@ -410,6 +557,27 @@ fn resolve_config(args: &ArgMatches, ct: ConfigType) -> Result<(Config, Region)>
 
 fn void<T>(_x: T) { () } // helper so that dispatch_commands can return Result<()>
 
+/// Rollback target from `--to-version` or `--revision`
+fn rollback_target(args: &ArgMatches) -> Result<RollbackTarget> {
+    if let Some(v) = args.value_of("to-version") {
+        Ok(RollbackTarget::Version(v.into()))
+    } else if let Some(r) = args.value_of("revision") {
+        let n = r.parse().map_err(|_| format!("revision must be a number, got {}", r))?;
+        Ok(RollbackTarget::Revision(n))
+    } else {
+        Ok(RollbackTarget::Previous)
+    }
+}
+
+/// Policy exceptions from the flags of `apply` and `helm upgrade`
+fn upgrade_options(args: &ArgMatches) -> UpgradeOptions {
+    UpgradeOptions {
+        allow_downgrade: args.is_present("allow-downgrade"),
+        wait_for_lock: args.is_present("wait-for-lock"),
+        clearance: shipcat::freeze::Clearance::new(args.value_of("emergency-reason"), args.value_of("approval")),
+    }
+}
+
 /// Dispatch clap arguments to shipcat handlers
 ///
 /// A boring and somewhat error-prone "if-x-then-fnx dance". We are relying on types
@@ -428,6 +596,43 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
         let (conf , region) = resolve_config(a, ConfigType::Base)?;
         return shipcat::list::services(&conf, &region);
     }
+    else if let Some(a) = args.subcommand_matches("schema") {
+        let kind = match a.value_of("kind").unwrap() {
+            "manifest" => shipcat::schema::SchemaKind::Manifest,
+            "overrides" => shipcat::schema::SchemaKind::Overrides,
+            "fragment" => shipcat::schema::SchemaKind::Fragment,
+            "config" => shipcat::schema::SchemaKind::Config,
+            _ => unreachable!("schema kind is validated by clap"),
+        };
+        return shipcat::schema::print(kind);
+    }
+    else if let Some(a) = args.subcommand_matches("migrate") {
+        if a.is_present("list") {
+            return shipcat::migrate::list();
+        }
+        let services = a.values_of("services").map_or(vec![], |v| v.map(String::from).collect());
+        let since = if let Some(v) = a.value_of("since") {
+            match semver::Version::parse(v) {
+                Ok(sv) => Some(sv),
+                Err(e) => return Err(format!("--since {} is not a valid version: {}", v, e).into()),
+            }
+        } else {
+            None
+        };
+        return shipcat::migrate::run(services, since, a.is_present("dry-run"));
+    }
+    else if let Some(a) = args.subcommand_matches("new") {
+        let conf = Config::read()?;
+        let svc = shipcat::scaffold::NewService {
+            name: a.value_of("service").unwrap().into(),
+            team: a.value_of("team").unwrap().into(),
+            language: a.value_of("language").map(String::from),
+            kong: a.is_present("kong"),
+            regions: a.values_of("regions").map_or(vec![], |v| v.map(String::from).collect()),
+            repo: a.value_of("repo").map(String::from),
+        };
+        return shipcat::scaffold::create(svc, &conf);
+    }
     //if let Some(a) = args.subcommand_matches("list-products") {
     //    let l = a.value_of("location").unwrap().into();
     //    return shipcat::list::products(&conf, l);
@@ -596,11 +801,12 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
 
     else if let Some(a) = args.subcommand_matches("diff") {
         let svc = a.value_of("service").map(String::from).unwrap();
+        let rev = a.value_of("ref").unwrap_or("master");
         let has_diff = if a.is_present("crd") {
             let (conf, region) = resolve_config(a, ConfigType::Base)?;
             // NB: no secrets in CRD
             if a.is_present("git") {
-                shipcat::diff::values_vs_git(&svc, &conf, &region)?
+                shipcat::diff::values_vs_git(&svc, rev, &conf, &region)?
             } else {
                 shipcat::diff::values_vs_kubectl(&svc, &conf, &region)?
             }
@@ -609,7 +815,7 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
             let (conf, region) = resolve_config(a, ss)?;
             let mock = !a.is_present("secrets");
             if a.is_present("git") {
-                shipcat::diff::template_vs_git(&svc, &conf, &region)?
+                shipcat::diff::template_vs_git(&svc, rev, &conf, &region)?
             } else {
                 // the only mode that can support secrets!
                 shipcat::diff::template_vs_kubectl(&svc, &conf, &region, mock)?
@@ -654,9 +860,17 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
         };
         let ver = a.value_of("tag").map(String::from); // needed for some subcommands
         assert!(conf.has_secrets()); // sanity on cluster disruptive commands
+        let opts = upgrade_options(a);
         return shipcat::helm::direct::upgrade_wrapper(&svc,
             umode, &region,
-            &conf, ver).map(void);
+            &conf, ver, opts).map(void);
+    }
+
+    else if let Some(a) = args.subcommand_matches("rollback") {
+        let svc = a.value_of("service").unwrap();
+        let (conf, region) = resolve_config(a, ConfigType::Filtered)?;
+        assert!(conf.has_secrets()); // sanity on cluster disruptive commands
+        return shipcat::helm::direct::rollback_wrapper(&svc, &conf, &region, rollback_target(a)?, a.is_present("wait-for-lock"));
     }
 
     // helm subcommands
@@ -671,8 +885,8 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
             return shipcat::helm::history(&svc, &conf, &region);
         }
         // small wrapper around helm rollback
-        if let Some(_) = a.subcommand_matches("rollback") {
-            return shipcat::helm::direct::rollback_wrapper(&svc, &conf, &region);
+        if let Some(b) = a.subcommand_matches("rollback") {
+            return shipcat::helm::direct::rollback_wrapper(&svc, &conf, &region, rollback_target(b)?, b.is_present("wait-for-lock"));
         }
 
         if let Some(_) = a.subcommand_matches("values") {
@@ -715,10 +929,13 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
         else {
             unreachable!("Helm Subcommand valid, but not implemented")
         };
+        let opts = a.subcommand_matches("upgrade")
+            .map(upgrade_options)
+            .unwrap_or_default();
         assert!(conf.has_secrets()); // sanity on cluster disruptive commands
         return shipcat::helm::direct::upgrade_wrapper(svc,
             umode, &region,
-            &conf, ver).map(void);
+            &conf, ver, opts).map(void);
     }
 
 
@@ -739,6 +956,11 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
             }
         }
         if let Some(b) = a.subcommand_matches("helm") {
+            // migrations only move release storage around
+            if let Some(c) = b.subcommand_matches("migrate") {
+                let (_conf, region) = resolve_config(args, ConfigType::Base)?;
+                return shipcat::cluster::helm_migrate(&region, c.is_present("dry-run"));
+            }
             // absolutely need secrets for helm reconcile
             let (conf, region) = resolve_config(args, ConfigType::Filtered)?;
             assert!(conf.has_secrets()); // sanity on cluster disruptive commands
@@ -786,6 +1008,29 @@ fn dispatch_commands(args: &ArgMatches) -> Result<()> {
     }
 
     // these could technically forgo the kube dependency..
+    else if let Some(a) = args.subcommand_matches("history") {
+        let (_conf, region) = resolve_config(a, ConfigType::Base)?;
+        return shipcat::auditlog::history(&region, a.value_of("since").unwrap());
+    }
+    else if let Some(a) = args.subcommand_matches("lock") {
+        let (_conf, region) = resolve_config(a, ConfigType::Base)?;
+        let svc = a.value_of("service");
+        if a.is_present("break") {
+            return shipcat::lock::break_lock(svc.unwrap(), &region.namespace);
+        }
+        return shipcat::lock::show(svc, &region.namespace);
+    }
+    else if let Some(a) = args.subcommand_matches("webhooks") {
+        if a.subcommand_matches("flush").is_some() {
+            // spooled events are stored without their credentials
+            let (_conf, region) = resolve_config(args, ConfigType::Filtered)?;
+            let remaining = shipcat::delivery::flush(&region)?;
+            if remaining > 0 {
+                return Err(format!("{} spooled events could not be delivered", remaining).into());
+            }
+            return Ok(());
+        }
+    }
     else if let Some(a) = args.subcommand_matches("slack") {
         let (conf, region) = resolve_config(args, ConfigType::Base)?;
         let text = a.values_of("message").unwrap().collect::<Vec<_>>().join(" ");
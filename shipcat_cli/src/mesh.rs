@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+use shipcat_definitions::structs::mesh::{
+    ConnectionPool, Destination, DestinationRule, HttpMatchRequest, HttpRoute, HttpRouteDestination,
+    MtlsMode, OutlierDetection, PeerAuthentication, PeerMtls, PortSelector, Retries, TcpSettings,
+    TlsSettings, TrafficPolicy, VirtualService,
+};
+use shipcat_definitions::structs::networkpolicy::LabelSelector;
+use shipcat_definitions::structs::{RollingUpdate, ServiceMesh};
+use shipcat_definitions::ServiceMeshConfig;
+use crate::networkpolicy::{Caller, CallerGraph};
+use super::{Manifest, Region, Result};
+
+/// A caller with its own timeout or retries for a service
+pub struct RoutedCaller {
+    /// The calling service and its workers
+    pub caller: Caller,
+    /// Timeout from the caller's dependency on the service
+    pub timeout: Option<String>,
+    /// Retries from the caller's dependency on the service
+    pub retries: Option<Retries>,
+}
+
+impl RoutedCaller {
+    /// Callers of a service whose dependency on it overrides timeouts or retries
+    pub fn find(service: &str, graph: &CallerGraph) -> Vec<RoutedCaller> {
+        graph.calls(service).iter()
+            .filter(|(_, dep)| dep.timeout.is_some() || dep.retries.is_some())
+            .map(|(c, dep)| RoutedCaller {
+                caller: c.clone(),
+                timeout: dep.timeout.clone(),
+                retries: dep.retries.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Route matching requests from a caller's pods
+fn caller_route(rc: &RoutedCaller, destination: &HttpRouteDestination, default: &HttpRoute) -> HttpRoute {
+    let c = &rc.caller;
+    let matches = std::iter::once(&c.name).chain(c.workers.iter()).map(|name| {
+        let mut sourceLabels = BTreeMap::new();
+        sourceLabels.insert("app".to_string(), name.clone());
+        HttpMatchRequest { sourceLabels }
+    }).collect();
+    HttpRoute {
+        matches,
+        route: vec![destination.clone()],
+        timeout: rc.timeout.clone().or_else(|| default.timeout.clone()),
+        retries: rc.retries.clone().or_else(|| default.retries.clone()),
+    }
+}
+
+/// Generate the istio objects for a service
+///
+/// Callers that set `timeout` or `retries` on their dependency get their own
+/// route ahead of the default route for everyone else.
+pub fn generate(mf: &Manifest, callers: &[RoutedCaller], cfg: &ServiceMeshConfig) -> Result<ServiceMesh> {
+    let host = format!("{}.{}.svc.cluster.local", mf.name, mf.namespace);
+    let mesh = mf.mesh.clone().unwrap_or_default();
+    let kong = mf.kong.as_ref();
+
+    let destination = HttpRouteDestination {
+        destination: Destination { host: host.clone(), port: PortSelector { number: 80 } },
+    };
+    let default = HttpRoute {
+        matches: vec![],
+        route: vec![destination.clone()],
+        timeout: mesh.timeout.clone()
+            .or_else(|| kong.and_then(|k| k.upstream_read_timeout).map(|t| format!("{}ms", t))),
+        retries: mesh.retries.clone(),
+    };
+    let mut http : Vec<HttpRoute> = callers.iter()
+        .map(|c| caller_route(c, &destination, &default))
+        .collect();
+    http.push(default);
+
+    let mtls = mesh.mtls.clone().unwrap_or_else(|| cfg.mtls.clone());
+    let tls = if mtls == MtlsMode::Disable {
+        None
+    } else {
+        Some(TlsSettings { mode: "ISTIO_MUTUAL".into() })
+    };
+    let connectionPool = kong.and_then(|k| k.upstream_connect_timeout).map(|t| ConnectionPool {
+        tcp: TcpSettings { connectTimeout: format!("{}ms", t) },
+    });
+    // never eject more pods than a rollout is allowed to take down
+    // autoscaled services may scale down to their minimum
+    let replicas = match &mf.autoScaling {
+        Some(hpa) => hpa.minReplicas,
+        None => mf.replicaCount.unwrap_or(1),
+    };
+    let rollout = mf.rollingUpdate.clone().unwrap_or_else(|| RollingUpdate::chart_default(replicas));
+    let outlierDetection = Some(OutlierDetection {
+        maxEjectionPercent: rollout.max_unavailable_percentage(replicas)?,
+    });
+
+    Ok(ServiceMesh {
+        virtualService: VirtualService { hosts: vec![host.clone()], http },
+        destinationRule: DestinationRule {
+            host,
+            trafficPolicy: TrafficPolicy { tls, connectionPool, outlierDetection },
+        },
+        peerAuthentication: PeerAuthentication {
+            selector: LabelSelector::app(&mf.name),
+            mtls: PeerMtls { mode: mtls },
+        },
+    })
+}
+
+/// Attach generated istio objects to a manifest if the region wants them
+pub fn inject(mf: &mut Manifest, graph: &CallerGraph, reg: &Region) -> Result<()> {
+    if let Some(cfg) = &reg.serviceMesh {
+        if mf.external || mf.httpPort.is_none() {
+            return Ok(());
+        }
+        let callers = RoutedCaller::find(&mf.name, graph);
+        debug!("Generating service mesh config for {} with {} routed callers", mf.name, callers.len());
+        mf.serviceMesh = Some(generate(mf, &callers, cfg)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, RoutedCaller};
+    use crate::networkpolicy::Caller;
+    use shipcat_definitions::structs::mesh::{MtlsMode, Retries};
+    use shipcat_definitions::structs::Kong;
+    use shipcat_definitions::structs::autoscaling::AutoScaling;
+    use shipcat_definitions::{Manifest, ServiceMeshConfig};
+
+    fn manifest(replicas: u32) -> Manifest {
+        let mut mf = Manifest::default();
+        mf.name = "fake-storage".into();
+        mf.namespace = "dev".into();
+        mf.replicaCount = Some(replicas);
+        mf
+    }
+
+    #[test]
+    fn mesh_routes() {
+        let mut mf = manifest(4);
+        let mut kong = Kong::default();
+        kong.upstream_read_timeout = Some(30000);
+        kong.upstream_connect_timeout = Some(5000);
+        mf.kong = Some(kong);
+
+        let callers = vec![RoutedCaller {
+            caller: Caller { name: "fake-ask".into(), workers: vec![] },
+            timeout: Some("2s".into()),
+            retries: Some(Retries { attempts: 2, perTryTimeout: None, retryOn: None }),
+        }];
+        let cfg = ServiceMeshConfig { mtls: MtlsMode::Strict };
+
+        let sm = generate(&mf, &callers, &cfg).unwrap();
+        let http = &sm.virtualService.http;
+        assert_eq!(http.len(), 2); // caller route + default route
+        assert_eq!(http[0].matches[0].sourceLabels["app"], "fake-ask");
+        assert_eq!(http[0].timeout, Some("2s".into()));
+        assert_eq!(http[1].timeout, Some("30000ms".into()));
+        assert!(http[1].matches.is_empty());
+
+        let tp = &sm.destinationRule.trafficPolicy;
+        assert_eq!(tp.connectionPool.as_ref().unwrap().tcp.connectTimeout, "5000ms");
+        assert_eq!(tp.outlierDetection.as_ref().unwrap().maxEjectionPercent, 25);
+        assert_eq!(sm.peerAuthentication.mtls.mode, MtlsMode::Strict);
+    }
+
+    #[test]
+    fn mesh_ejection_follows_chart() {
+        let cfg = ServiceMeshConfig { mtls: MtlsMode::Strict };
+        // the chart never takes down the only replica
+        let sm = generate(&manifest(1), &[], &cfg).unwrap();
+        assert_eq!(sm.destinationRule.trafficPolicy.outlierDetection.unwrap().maxEjectionPercent, 0);
+
+        // autoscaled services follow their minimum rather than a missing replicaCount
+        let mut mf = manifest(1);
+        mf.replicaCount = None;
+        mf.autoScaling = Some(AutoScaling {
+            minReplicas: 4,
+            maxReplicas: 8,
+            metrics: vec![],
+            behavior: None,
+        });
+        let sm = generate(&mf, &[], &cfg).unwrap();
+        assert_eq!(sm.destinationRule.trafficPolicy.outlierDetection.unwrap().maxEjectionPercent, 25);
+    }
+}
@@ -0,0 +1,414 @@
+use semver::Version;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use shipcat_definitions::structs::{HealthCheck, VaultOpts};
+use super::diff;
+use super::{Result, ResultExt};
+
+/// A yaml file held as lines
+///
+/// Migrations rewrite blocks of lines rather than re-serializing the yaml,
+/// so that comments, key ordering and formatting survive the rewrite.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestLines {
+    lines: Vec<String>,
+    /// Whether the original ended in a newline
+    trailing_newline: bool,
+}
+
+/// Whether a line starts or ends a yaml document (`---` or `...`)
+fn is_document_marker(line: &str) -> bool {
+    let l = line.trim_end();
+    l == "---" || l == "..." || line.starts_with("--- ")
+}
+
+impl ManifestLines {
+    pub fn new(data: &str) -> Self {
+        ManifestLines {
+            lines: data.lines().map(String::from).collect(),
+            trailing_newline: data.ends_with('\n'),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut res = self.lines.join("\n");
+        if self.trailing_newline {
+            res.push('\n');
+        }
+        res
+    }
+
+    /// Line range `[start, end)` of a top level key along with its body
+    ///
+    /// The body is every indented line following the key (plus any unindented
+    /// sequence items), excluding trailing blank lines and comments.
+    /// Document separators always end the block.
+    fn block(&self, key: &str) -> Option<(usize, usize)> {
+        let prefix = format!("{}:", key);
+        let start = self.lines.iter().position(|l| {
+            l.starts_with(&prefix) && (l.len() == prefix.len() || l[prefix.len()..].starts_with(' '))
+        })?;
+        let mut end = start + 1;
+        for (i, l) in self.lines.iter().enumerate().skip(start + 1) {
+            let trimmed = l.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue; // only part of the block if followed by more of the body
+            }
+            if is_document_marker(l) {
+                break;
+            }
+            if l.starts_with(' ') || l.starts_with('-') {
+                end = i + 1;
+            } else {
+                break;
+            }
+        }
+        Some((start, end))
+    }
+
+    /// The yaml of a top level key block, for deserialization
+    fn block_yaml(&self, range: (usize, usize)) -> String {
+        self.lines[range.0..range.1].join("\n")
+    }
+
+    /// Indented child lines of a block that set `key`
+    fn child(&self, range: (usize, usize), key: &str) -> Option<usize> {
+        let prefix = format!("{}:", key);
+        (range.0 + 1..range.1).find(|&i| {
+            let l = &self.lines[i];
+            let trimmed = l.trim_start();
+            l.starts_with(' ') && trimmed.starts_with(&prefix)
+        })
+    }
+
+    /// Indentation used by the first child of a block
+    fn child_indent(&self, range: (usize, usize)) -> String {
+        (range.0 + 1..range.1)
+            .map(|i| &self.lines[i])
+            .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+            .map(|l| l[..l.len() - l.trim_start().len()].to_string())
+            .unwrap_or_else(|| "  ".into())
+    }
+
+    fn replace(&mut self, range: (usize, usize), new: Vec<String>) {
+        self.lines.splice(range.0..range.1, new);
+    }
+
+    fn top_level_value(&self, key: &str) -> Option<String> {
+        let range = self.block(key)?;
+        let line = &self.lines[range.0];
+        Some(line[key.len() + 1..].trim().to_string())
+    }
+}
+
+/// A rewrite of deprecated manifest syntax
+///
+/// Migrations are applied per file, in registry order, and must leave the file
+/// untouched when there is nothing to migrate. Anything that cannot be rewritten
+/// safely is returned as a manual action for the user.
+pub trait Migration {
+    /// Short identifier of the migration
+    fn name(&self) -> &'static str;
+    /// Shipcat version the migration was introduced in
+    fn version(&self) -> Version;
+    /// Human readable summary of what is rewritten
+    fn description(&self) -> &'static str;
+    /// Rewrite a file belonging to `svc`, returning required manual actions
+    fn apply(&self, svc: &str, file: &mut ManifestLines) -> Result<Vec<String>>;
+}
+
+/// Every known migration in the order they are applied
+pub fn registry() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(HealthToReadinessProbe),
+        Box::new(GatePublic),
+        Box::new(VaultOverride),
+    ]
+}
+
+/// Rewrites `health` into an equivalent `readinessProbe`
+///
+/// Mirrors what the chart does with `health`: an http probe on the `http` port.
+pub struct HealthToReadinessProbe;
+impl Migration for HealthToReadinessProbe {
+    fn name(&self) -> &'static str { "health-to-readiness-probe" }
+    fn version(&self) -> Version { Version::new(0, 107, 0) }
+    fn description(&self) -> &'static str { "Replace `health` with `readinessProbe`" }
+
+    fn apply(&self, _svc: &str, file: &mut ManifestLines) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            health: HealthCheck,
+        }
+        let range = match file.block("health") {
+            Some(r) => r,
+            None => return Ok(vec![]),
+        };
+        let hc = serde_yaml::from_str::<Wrapper>(&file.block_yaml(range))
+            .chain_err(|| "could not parse `health`")?.health;
+        if hc.port.is_some() {
+            return Ok(vec![
+                "`health.port` needs a named port in `ports` - convert `health` to `readinessProbe` manually".into()
+            ]);
+        }
+        if file.block("readinessProbe").is_some() {
+            // readinessProbe already takes precedence over health in the chart
+            file.replace(range, vec![]);
+            return Ok(vec![]);
+        }
+        let probe = vec![
+            "readinessProbe:".into(),
+            "  httpGet:".into(),
+            format!("    path: {}", hc.uri),
+            "    port: http".into(),
+            format!("  initialDelaySeconds: {}", hc.wait),
+            "  periodSeconds: 5".into(),
+        ];
+        file.replace(range, probe);
+        Ok(vec![])
+    }
+}
+
+/// Mirrors `publiclyAccessible` into `gate.public`
+///
+/// Manifests with a `gate` must agree on both during the gate migration plan.
+pub struct GatePublic;
+impl Migration for GatePublic {
+    fn name(&self) -> &'static str { "gate-public" }
+    fn version(&self) -> Version { Version::new(0, 107, 0) }
+    fn description(&self) -> &'static str { "Set `gate.public` to match `publiclyAccessible`" }
+
+    fn apply(&self, _svc: &str, file: &mut ManifestLines) -> Result<Vec<String>> {
+        let public = match file.top_level_value("publiclyAccessible") {
+            Some(p) => p,
+            None => return Ok(vec![]),
+        };
+        let range = match file.block("gate") {
+            Some(r) => r,
+            None => return Ok(vec![]),
+        };
+        let inline = file.top_level_value("gate").unwrap_or_default();
+        if inline == "{}" {
+            file.replace(range, vec!["gate:".into(), format!("  public: {}", public)]);
+            return Ok(vec![]);
+        }
+        if !inline.is_empty() {
+            return Ok(vec!["inline `gate` mapping - set `gate.public` manually".into()]);
+        }
+        if let Some(i) = file.child(range, "public") {
+            let current = file.lines[i].trim_start()["public:".len()..].trim().to_string();
+            if current != public {
+                return Ok(vec![
+                    format!("`gate.public` is {} but `publiclyAccessible` is {} - align them manually", current, public)
+                ]);
+            }
+            return Ok(vec![]);
+        }
+        let line = format!("{}public: {}", file.child_indent(range), public);
+        file.lines.insert(range.0 + 1, line);
+        Ok(vec![])
+    }
+}
+
+/// Removes redundant `vault` overrides
+///
+/// Overrides pointing at another service's secrets cannot be rewritten;
+/// those secrets need to be copied into the service's own folder first.
+pub struct VaultOverride;
+impl Migration for VaultOverride {
+    fn name(&self) -> &'static str { "vault-override" }
+    fn version(&self) -> Version { Version::new(0, 107, 0) }
+    fn description(&self) -> &'static str { "Remove deprecated `vault` options" }
+
+    fn apply(&self, svc: &str, file: &mut ManifestLines) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            vault: VaultOpts,
+        }
+        let range = match file.block("vault") {
+            Some(r) => r,
+            None => return Ok(vec![]),
+        };
+        let opts = serde_yaml::from_str::<Wrapper>(&file.block_yaml(range))
+            .chain_err(|| "could not parse `vault`")?.vault;
+        if opts.name == svc && opts.region.is_none() {
+            file.replace(range, vec![]);
+            return Ok(vec![]);
+        }
+        let folder = opts.region.unwrap_or_else(|| "<region>".into());
+        Ok(vec![
+            format!("move secrets from {}/{} to the {} folder and remove `vault`", folder, opts.name, svc)
+        ])
+    }
+}
+
+/// Files to migrate for a service: `shipcat.yml` and every override file
+fn service_files(svc: &str) -> Result<Vec<PathBuf>> {
+    let dir = Path::new(".").join("services").join(svc);
+    if !dir.is_dir() {
+        bail!("Service folder {} does not exist", dir.display());
+    }
+    let mut files = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map_or(false, |e| e == "yml"))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+fn all_services() -> Result<Vec<String>> {
+    let mut svcs = fs::read_dir(Path::new(".").join("services"))?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    svcs.sort();
+    Ok(svcs)
+}
+
+/// Apply migrations to a single file's contents
+///
+/// Returns the migrated contents and any manual actions required.
+pub fn migrate_file(svc: &str, data: &str, migrations: &[Box<dyn Migration>]) -> Result<(String, Vec<String>)> {
+    let mut file = ManifestLines::new(data);
+    let mut actions = vec![];
+    for m in migrations {
+        for a in m.apply(svc, &mut file)? {
+            actions.push(format!("{}: {}", m.name(), a));
+        }
+    }
+    Ok((file.render(), actions))
+}
+
+/// Print the migration registry
+pub fn list() -> Result<()> {
+    for m in registry() {
+        println!("{} ({}): {}", m.name(), m.version(), m.description());
+    }
+    Ok(())
+}
+
+/// Rewrite deprecated syntax in service manifests
+///
+/// Only migrations introduced after `since` are applied when it is given.
+/// With `dryrun` the changes are shown as a diff without touching any file.
+pub fn run(services: Vec<String>, since: Option<Version>, dryrun: bool) -> Result<()> {
+    let migrations = registry().into_iter()
+        .filter(|m| since.as_ref().map_or(true, |v| m.version() > *v))
+        .collect::<Vec<_>>();
+    let svcs = if services.is_empty() { all_services()? } else { services };
+
+    let mut pending = 0;
+    for svc in svcs {
+        for pth in service_files(&svc)? {
+            let before = fs::read_to_string(&pth)?;
+            let (after, actions) = migrate_file(&svc, &before, &migrations)
+                .chain_err(|| format!("Failed to migrate {}", pth.display()))?;
+            for a in &actions {
+                warn!("{}: {}", pth.display(), a);
+            }
+            pending += actions.len();
+            if after == before {
+                continue;
+            }
+            if dryrun {
+                info!("Would migrate {}", pth.display());
+                diff::shell_diff(before.trim_end(), after.trim_end())?;
+            } else {
+                info!("Migrating {}", pth.display());
+                fs::write(&pth, after)?;
+            }
+        }
+    }
+    if pending > 0 {
+        warn!("{} manual action(s) required - see warnings above", pending);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{migrate_file, registry, ManifestLines};
+
+    #[test]
+    fn health_to_readiness_probe() {
+        let input = "name: fake-ask
+# health of the app
+health:
+  uri: /status
+  wait: 20
+
+# resources
+resources:
+  requests:
+    cpu: 100m
+";
+        let (res, actions) = migrate_file("fake-ask", input, &registry()).unwrap();
+        assert!(actions.is_empty());
+        assert_eq!(res, "name: fake-ask
+# health of the app
+readinessProbe:
+  httpGet:
+    path: /status
+    port: http
+  initialDelaySeconds: 20
+  periodSeconds: 5
+
+# resources
+resources:
+  requests:
+    cpu: 100m
+");
+    }
+
+    #[test]
+    fn health_with_port_is_manual() {
+        let input = "health:\n  uri: /health\n  port: 8081\n";
+        let (res, actions) = migrate_file("fake-ask", input, &registry()).unwrap();
+        assert_eq!(res, input);
+        assert_eq!(actions.len(), 1);
+    }
+
+    #[test]
+    fn render_keeps_trailing_newline() {
+        assert_eq!(ManifestLines::new("").render(), "");
+        assert_eq!(ManifestLines::new("name: fake-ask").render(), "name: fake-ask");
+        assert_eq!(ManifestLines::new("name: fake-ask\n").render(), "name: fake-ask\n");
+    }
+
+    #[test]
+    fn block_stops_at_documents() {
+        let lines = ManifestLines::new("health:\n  uri: /status\n---\nname: fake-ask\n");
+        assert_eq!(lines.block("health"), Some((0, 2)));
+        let input = "vault:\n  name: fake-ask\n---\n- item\n";
+        let (res, _) = migrate_file("fake-ask", input, &registry()).unwrap();
+        assert_eq!(res, "---\n- item\n");
+    }
+
+    #[test]
+    fn gate_public() {
+        let input = "publiclyAccessible: true\ngate:\n    websockets: true\nregions:\n- dev-uk\n";
+        let (res, actions) = migrate_file("fake-ask", input, &registry()).unwrap();
+        assert!(actions.is_empty());
+        assert_eq!(res, "publiclyAccessible: true\ngate:\n    public: true\n    websockets: true\nregions:\n- dev-uk\n");
+
+        let conflict = "publiclyAccessible: true\ngate:\n  public: false\n";
+        let (res, actions) = migrate_file("fake-ask", conflict, &registry()).unwrap();
+        assert_eq!(res, conflict);
+        assert_eq!(actions.len(), 1);
+    }
+
+    #[test]
+    fn vault_override() {
+        let redundant = "name: fake-ask\nvault:\n  name: fake-ask\nimage: foo\n";
+        let (res, actions) = migrate_file("fake-ask", redundant, &registry()).unwrap();
+        assert!(actions.is_empty());
+        assert_eq!(res, "name: fake-ask\nimage: foo\n");
+
+        let shared = "vault:\n  name: fake-storage\n  region: dev-uk\n";
+        let (res, actions) = migrate_file("fake-ask", shared, &registry()).unwrap();
+        assert_eq!(res, shared);
+        assert_eq!(actions.len(), 1);
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+use shipcat_definitions::structs::networkpolicy::{
+    IpBlock, LabelSelector, NetworkPolicyIngressRule, NetworkPolicyPeer, NetworkPolicyPort,
+};
+use shipcat_definitions::structs::port::PortProtocol;
+use shipcat_definitions::structs::{Dependency, DependencyProtocol, NetworkPolicy};
+use shipcat_definitions::{NetworkPolicyConfig, NetworkPolicyMode};
+use super::{Config, Manifest, Region, Result};
+
+/// A service calling another service directly over the network
+#[derive(Clone)]
+pub struct Caller {
+    /// Name of the calling service
+    pub name: String,
+    /// Names of the calling service's workers (each with their own pods)
+    pub workers: Vec<String>,
+}
+
+/// Direct callers of every service in a region
+///
+/// Built once from all manifests in the region, so that generating policies
+/// for many services does not reload every manifest for each of them.
+#[derive(Clone, Default)]
+pub struct CallerGraph {
+    calls: BTreeMap<String, Vec<(Caller, Dependency)>>,
+}
+
+impl CallerGraph {
+    /// Find the direct callers of all services in a region
+    ///
+    /// Only http and grpc dependencies count; message based dependencies
+    /// go through their brokers and never reach the service itself.
+    /// Manifests that fail to load are skipped with a warning.
+    pub fn new(conf: &Config, reg: &Region) -> Result<CallerGraph> {
+        let mut graph = CallerGraph::default();
+        if reg.networkPolicies.is_none() && reg.serviceMesh.is_none() {
+            return Ok(graph); // nothing in the region uses it
+        }
+        for svc in shipcat_filebacked::available(conf, reg)? {
+            let mf = match shipcat_filebacked::load_manifest(&svc.base.name, conf, reg) {
+                Ok(mf) => mf,
+                Err(e) => {
+                    warn!("Ignoring {} as a caller in {}: {}", svc.base.name, reg.name, e);
+                    continue;
+                }
+            };
+            for dep in &mf.dependencies {
+                match dep.protocol {
+                    DependencyProtocol::Http | DependencyProtocol::Grpc => {},
+                    _ => continue,
+                }
+                let caller = Caller {
+                    name: mf.name.clone(),
+                    workers: mf.workers.iter().map(|w| w.container.name.clone()).collect(),
+                };
+                graph.calls.entry(dep.name.clone()).or_insert_with(Vec::new).push((caller, dep.clone()));
+            }
+        }
+        Ok(graph)
+    }
+
+    /// The services calling `service` directly
+    pub fn callers(&self, service: &str) -> Vec<Caller> {
+        self.calls(service).iter().map(|(c, _)| c.clone()).collect()
+    }
+
+    /// The services calling `service` directly with their dependency entry for it
+    pub fn calls(&self, service: &str) -> &[(Caller, Dependency)] {
+        self.calls.get(service).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+/// Ports the service's main pods listen on
+fn ports(mf: &Manifest) -> Vec<NetworkPolicyPort> {
+    let mut res = vec![];
+    if let Some(p) = mf.httpPort {
+        res.push(NetworkPolicyPort { port: p, protocol: "TCP".into() });
+    }
+    if let Some(hc) = &mf.health {
+        if let Some(p) = hc.port {
+            if Some(p) != mf.httpPort {
+                res.push(NetworkPolicyPort { port: p, protocol: "TCP".into() });
+            }
+        }
+    }
+    for p in &mf.ports {
+        let protocol = match p.protocol {
+            PortProtocol::Tcp => "TCP",
+            PortProtocol::Udp => "UDP",
+            PortProtocol::Sctp => "SCTP",
+        };
+        res.push(NetworkPolicyPort { port: p.port, protocol: protocol.into() });
+    }
+    res
+}
+
+/// Generate the ingress policy for a service from its callers
+pub fn generate(mf: &Manifest, callers: &[Caller], cfg: &NetworkPolicyConfig) -> NetworkPolicy {
+    let ports = ports(mf);
+    let mut ingress = vec![];
+
+    let mut from = vec![];
+    for c in callers {
+        for name in std::iter::once(&c.name).chain(c.workers.iter()) {
+            from.push(NetworkPolicyPeer {
+                podSelector: Some(LabelSelector::app(name)),
+                ..Default::default()
+            });
+        }
+    }
+    if mf.kong.is_some() || mf.gate.is_some() {
+        from.push(NetworkPolicyPeer {
+            podSelector: Some(cfg.proxyPods.clone()),
+            namespaceSelector: cfg.proxyNamespace.clone(),
+            ..Default::default()
+        });
+    }
+    if !from.is_empty() {
+        ingress.push(NetworkPolicyIngressRule { from, ports: ports.clone() });
+    }
+
+    // load balancer exposure can reach any port the service exposes
+    if !mf.sourceRanges.is_empty() {
+        let from = mf.sourceRanges.iter().map(|cidr| NetworkPolicyPeer {
+            ipBlock: Some(IpBlock { cidr: cidr.clone() }),
+            ..Default::default()
+        }).collect();
+        ingress.push(NetworkPolicyIngressRule { from, ports: ports.clone() });
+    }
+    if !cfg.alwaysAllow.is_empty() {
+        ingress.push(NetworkPolicyIngressRule { from: cfg.alwaysAllow.clone(), ports: vec![] });
+    }
+
+    let audit = cfg.mode == NetworkPolicyMode::Audit;
+    if audit {
+        ingress.push(NetworkPolicyIngressRule::default()); // allows everything
+    }
+    NetworkPolicy { audit, ingress }
+}
+
+/// Attach a generated network policy to a manifest if the region wants them
+pub fn inject(mf: &mut Manifest, graph: &CallerGraph, reg: &Region) {
+    if let Some(cfg) = &reg.networkPolicies {
+        if mf.external {
+            return;
+        }
+        let callers = graph.callers(&mf.name);
+        debug!("Generating network policy for {} with {} callers", mf.name, callers.len());
+        mf.networkPolicy = Some(generate(mf, &callers, cfg));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, Caller};
+    use shipcat_definitions::structs::networkpolicy::LabelSelector;
+    use shipcat_definitions::{Manifest, NetworkPolicyConfig, NetworkPolicyMode};
+
+    fn config(mode: NetworkPolicyMode) -> NetworkPolicyConfig {
+        NetworkPolicyConfig {
+            mode,
+            proxyNamespace: Some(LabelSelector::app("kong")),
+            proxyPods: LabelSelector::app("kong"),
+            alwaysAllow: vec![],
+        }
+    }
+
+    #[test]
+    fn enforced_policy() {
+        let mut mf = Manifest::default();
+        mf.name = "fake-storage".into();
+        mf.httpPort = Some(8080);
+        mf.sourceRanges = vec!["10.0.0.0/8".into()];
+        let callers = vec![Caller {
+            name: "fake-ask".into(),
+            workers: vec!["worker".into()],
+        }];
+
+        let np = generate(&mf, &callers, &config(NetworkPolicyMode::Enforce));
+        assert!(!np.audit);
+        assert_eq!(np.ingress.len(), 2);
+        // main pods and worker pods of the caller
+        assert_eq!(np.ingress[0].from.len(), 2);
+        assert_eq!(np.ingress[0].from[1].podSelector, Some(LabelSelector::app("worker")));
+        assert_eq!(np.ingress[0].ports[0].port, 8080);
+        assert_eq!(np.ingress[1].from[0].ipBlock.clone().unwrap().cidr, "10.0.0.0/8");
+    }
+
+    #[test]
+    fn audit_policy() {
+        let mut mf = Manifest::default();
+        mf.name = "fake-storage".into();
+        let np = generate(&mf, &[], &config(NetworkPolicyMode::Audit));
+        assert!(np.audit);
+        // only the allow-all rule
+        assert_eq!(np.ingress.len(), 1);
+        assert!(np.ingress[0].from.is_empty());
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use reqwest::{self, StatusCode};
+use reqwest::header::{ACCEPT, AUTHORIZATION, WWW_AUTHENTICATE};
+
+use shipcat_definitions::RegistryConfig;
+use super::{Manifest, Region};
+use super::{Result, ResultExt, ErrorKind};
+
+/// Registry used for images without an explicit registry host
+const DOCKER_HUB: &str = "registry-1.docker.io";
+/// Key docker uses for docker hub credentials in its config
+const DOCKER_HUB_AUTH: &str = "https://index.docker.io/v1/";
+
+/// Manifest types we accept, so the registry returns the digest kubernetes pulls by
+const MANIFEST_TYPES: &str = "application/vnd.docker.distribution.manifest.list.v2+json, \
+                              application/vnd.docker.distribution.manifest.v2+json";
+
+/// An image name split into its registry and repository
+#[derive(Debug, PartialEq)]
+pub struct ImageName {
+    /// Registry host, including port if any
+    pub registry: String,
+    /// Repository within the registry, e.g. `library/nginx`
+    pub repository: String,
+}
+
+impl ImageName {
+    /// Split an image name the same way docker does
+    ///
+    /// The first path component is a registry host if it looks like one,
+    /// otherwise the image lives on docker hub.
+    pub fn parse(image: &str) -> ImageName {
+        let mut parts = image.splitn(2, '/');
+        let first = parts.next().unwrap_or("");
+        match parts.next() {
+            Some(rest) if first.contains('.') || first.contains(':') || first == "localhost" => {
+                ImageName { registry: first.into(), repository: rest.into() }
+            },
+            Some(_) => ImageName { registry: DOCKER_HUB.into(), repository: image.into() },
+            None => ImageName { registry: DOCKER_HUB.into(), repository: format!("library/{}", image) },
+        }
+    }
+
+    /// Base url of the registry
+    pub fn base_url(&self, cfg: &RegistryConfig) -> String {
+        let scheme = if cfg.insecureRegistries.contains(&self.registry) { "http" } else { "https" };
+        format!("{}://{}", scheme, self.registry)
+    }
+
+    /// Keys docker uses for the registry in its config
+    fn auth_keys(&self) -> Vec<String> {
+        if self.registry == DOCKER_HUB {
+            vec![DOCKER_HUB_AUTH.to_string(), "docker.io".into(), "index.docker.io".into()]
+        } else {
+            vec![self.registry.clone(), format!("https://{}", self.registry), format!("http://{}", self.registry)]
+        }
+    }
+
+    /// Base64 encoded `user:password` for the registry from the docker config
+    ///
+    /// Uses credential helpers (`credHelpers` and `credsStore`) like docker does.
+    pub fn credentials(&self) -> Result<Option<String>> {
+        let cfg = match DockerConfig::load() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        match cfg.source(&self.auth_keys()) {
+            CredentialSource::Helper(helper) => helper_credentials(&helper, &self.auth_keys()[0], true),
+            CredentialSource::Store(store) => helper_credentials(&store, &self.auth_keys()[0], false),
+            CredentialSource::Auth(auth) => Ok(Some(auth)),
+            CredentialSource::Anonymous => Ok(None),
+        }
+    }
+}
+
+/// Split a container image into name and tag, defaulting the tag like docker
+///
+/// Images pinned by digest (`name@sha256:..`) return the digest as the tag.
+pub fn split_tag(image: &str) -> (String, String) {
+    if let Some(i) = image.find('@') {
+        // any tag next to a digest is ignored, like docker does
+        return (split_tag(&image[..i]).0, image[i + 1..].into());
+    }
+    let name_start = image.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match image[name_start..].rfind(':') {
+        Some(i) => (image[..name_start + i].into(), image[name_start + i + 1..].into()),
+        None => (image.into(), "latest".into()),
+    }
+}
+
+/// The parts of `~/.docker/config.json` we care about
+#[derive(Deserialize, Default)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: BTreeMap<String, DockerAuth>,
+    /// Credential helper for all registries
+    #[serde(default, rename = "credsStore")]
+    creds_store: Option<String>,
+    /// Credential helpers per registry
+    #[serde(default, rename = "credHelpers")]
+    cred_helpers: BTreeMap<String, String>,
+}
+
+/// Where docker would get credentials for a registry from
+#[derive(Debug, PartialEq)]
+enum CredentialSource {
+    /// A `docker-credential-{name}` helper set for the registry
+    Helper(String),
+    /// The `docker-credential-{name}` helper set for all registries
+    Store(String),
+    /// An `auth` entry in the config
+    Auth(String),
+    Anonymous,
+}
+
+/// Output of `docker-credential-{name} get`
+#[derive(Deserialize)]
+struct HelperCredentials {
+    #[serde(rename = "Username")]
+    username: String,
+    #[serde(rename = "Secret")]
+    secret: String,
+}
+
+/// Ask a docker credential helper for the credentials of a registry
+///
+/// Helpers set for all registries only know some of them, so missing credentials
+/// fall back to anonymous access unless the helper was set for this registry.
+fn helper_credentials(helper: &str, server: &str, required: bool) -> Result<Option<String>> {
+    let bin = format!("docker-credential-{}", helper);
+    debug!("{} get {}", bin, server);
+    let mut child = Command::new(&bin).arg("get")
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn()
+        .chain_err(|| format!("Docker credential helper {} for {} is not installed", bin, server))?;
+    child.stdin.take().unwrap().write_all(server.as_bytes())?; // piped above
+    let out = child.wait_with_output()?;
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    if !out.status.success() {
+        if !required && stdout.contains("credentials not found") {
+            return Ok(None);
+        }
+        bail!("{} found no credentials for {}: {}{}", bin, server, stdout.trim(), String::from_utf8_lossy(&out.stderr).trim());
+    }
+    let creds : HelperCredentials = serde_json::from_str(&stdout)
+        .chain_err(|| format!("Unexpected output from {}", bin))?;
+    if creds.username == "<token>" {
+        bail!("{} returned an identity token for {}, which shipcat does not support", bin, server);
+    }
+    Ok(Some(base64::encode(&format!("{}:{}", creds.username, creds.secret))))
+}
+
+#[derive(Deserialize)]
+struct DockerAuth {
+    #[serde(default)]
+    auth: Option<String>,
+}
+
+impl DockerConfig {
+    /// Pick the credentials for a registry in the order docker does
+    fn source(&self, keys: &[String]) -> CredentialSource {
+        if let Some(h) = keys.iter().filter_map(|k| self.cred_helpers.get(k)).next() {
+            return CredentialSource::Helper(h.clone());
+        }
+        if let Some(s) = &self.creds_store {
+            return CredentialSource::Store(s.clone());
+        }
+        match keys.iter().filter_map(|k| self.auths.get(k)).filter_map(|a| a.auth.clone()).next() {
+            Some(a) => CredentialSource::Auth(a),
+            None => CredentialSource::Anonymous,
+        }
+    }
+
+    fn path() -> Option<PathBuf> {
+        if let Ok(dir) = env::var("DOCKER_CONFIG") {
+            return Some(PathBuf::from(dir).join("config.json"));
+        }
+        dirs::home_dir().map(|h| h.join(".docker").join("config.json"))
+    }
+
+    fn load() -> Option<DockerConfig> {
+        let pth = DockerConfig::path()?;
+        let f = File::open(&pth).ok()?;
+        match serde_json::from_reader(f) {
+            Ok(cfg) => Some(cfg),
+            Err(e) => {
+                warn!("Ignoring unparseable docker config {}: {}", pth.display(), e);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    access_token: Option<String>,
+}
+
+/// Parse the key="value" pairs of a `WWW-Authenticate: Bearer ..` challenge
+///
+/// Quoted values can contain commas (e.g. `scope="repository:x:pull,push"`) and escapes.
+fn parse_challenge(header: &str) -> Option<BTreeMap<String, String>> {
+    if !header.starts_with("Bearer ") {
+        return None;
+    }
+    let mut params = BTreeMap::new();
+    let mut chars = header["Bearer ".len()..].chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace() || *c == ',') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+        let key = chars.by_ref().take_while(|c| *c != '=').collect::<String>();
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next()? { // unterminated quote
+                    '"' => break,
+                    '\\' => value.push(chars.next()?),
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.peek() {
+                if *c == ',' {
+                    break;
+                }
+                value.push(*c);
+                chars.next();
+            }
+        }
+        params.insert(key.trim().to_string(), value.trim().to_string());
+    }
+    Some(params)
+}
+
+/// Fetch a pull token for a registry challenge
+///
+/// Anonymous unless credentials are passed.
+fn fetch_token(client: &reqwest::Client, challenge: &str, creds: Option<&str>) -> Result<String> {
+    let params = match parse_challenge(challenge) {
+        Some(p) => p,
+        None => bail!("Unsupported registry auth challenge: {}", challenge),
+    };
+    let realm = match params.get("realm") {
+        Some(r) => reqwest::Url::parse(r)?,
+        None => bail!("Registry auth challenge has no realm: {}", challenge),
+    };
+    let query : Vec<(&String, &String)> = params.iter().filter(|(k, _)| *k != "realm").collect();
+    let mkerr = || ErrorKind::Url(realm.clone());
+    let mut req = client.get(realm.clone()).query(&query);
+    if let Some(c) = creds {
+        req = req.header(AUTHORIZATION, format!("Basic {}", c));
+    }
+    let mut res = req.send().chain_err(&mkerr)?;
+    if !res.status().is_success() {
+        bail!("Registry token request to {} returned {}", realm, res.status());
+    }
+    let tr : TokenResponse = res.json().chain_err(&mkerr)?;
+    match tr.token.or(tr.access_token) {
+        Some(t) => Ok(t),
+        None => bail!("Registry token response from {} had no token", realm),
+    }
+}
+
+/// HEAD the manifest of a tag through the registry v2 api
+///
+/// Follows bearer token and basic auth challenges if the registry asks for them.
+fn head_manifest(base_url: &str, repository: &str, tag: &str, creds: Option<&str>) -> Result<reqwest::Response> {
+    let url = reqwest::Url::parse(&format!("{}/v2/{}/manifests/{}", base_url, repository, tag))?;
+    let mkerr = || ErrorKind::Url(url.clone());
+    let client = reqwest::Client::new();
+
+    let res = client.head(url.clone()).header(ACCEPT, MANIFEST_TYPES).send().chain_err(&mkerr)?;
+    if res.status() != StatusCode::UNAUTHORIZED {
+        return Ok(res);
+    }
+    let challenge = res.headers().get(WWW_AUTHENTICATE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let auth = if challenge.starts_with("Basic") {
+        match creds {
+            Some(c) => format!("Basic {}", c),
+            None => bail!("Registry {} needs credentials in the docker config", base_url),
+        }
+    } else {
+        format!("Bearer {}", fetch_token(&client, &challenge, creds)?)
+    };
+    let res = client.head(url.clone())
+        .header(ACCEPT, MANIFEST_TYPES)
+        .header(AUTHORIZATION, auth)
+        .send()
+        .chain_err(&mkerr)?;
+    Ok(res)
+}
+
+/// Look up the digest of a tag through the registry v2 api
+pub fn fetch_digest(base_url: &str, repository: &str, tag: &str, creds: Option<&str>) -> Result<String> {
+    let res = head_manifest(base_url, repository, tag, creds)?;
+    if !res.status().is_success() {
+        bail!("Registry returned {} for {}:{}", res.status(), repository, tag);
+    }
+    let digest = res.headers().get("Docker-Content-Digest")
+        .and_then(|h| h.to_str().ok())
+        .map(String::from);
+    match digest {
+        Some(d) if d.starts_with("sha256:") => Ok(d),
+        Some(d) => bail!("Registry returned an unexpected digest {} for {}:{}", d, repository, tag),
+        None => bail!("Registry did not return a digest for {}:{}", repository, tag),
+    }
+}
+
+/// Check whether a tag exists through the registry v2 api
+///
+/// Errors are reserved for registries that cannot be reached or refuse us access.
+pub fn tag_exists(base_url: &str, repository: &str, tag: &str, creds: Option<&str>) -> Result<bool> {
+    let res = head_manifest(base_url, repository, tag, creds)?;
+    match res.status() {
+        s if s.is_success() => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        s => bail!("Registry returned {} for {}:{}", s, repository, tag),
+    }
+}
+
+/// Resolve `image:version` to a `sha256` digest
+pub fn resolve_digest(image: &str, version: &str, cfg: &RegistryConfig) -> Result<String> {
+    let name = ImageName::parse(image);
+    let creds = name.credentials()
+        .chain_err(|| ErrorKind::DigestResolutionFailure(format!("{}:{}", image, version)))?;
+    fetch_digest(&name.base_url(cfg), &name.repository, version, creds.as_ref().map(String::as_str))
+        .chain_err(|| ErrorKind::DigestResolutionFailure(format!("{}:{}", image, version)))
+}
+
+/// Verify that `image:version` exists in its registry
+pub fn verify_image(image: &str, version: &str, cfg: &RegistryConfig) -> Result<()> {
+    let name = ImageName::parse(image);
+    let creds = name.credentials()
+        .chain_err(|| ErrorKind::ImageCheckFailure(format!("{}:{}", image, version)))?;
+    let found = tag_exists(&name.base_url(cfg), &name.repository, version, creds.as_ref().map(String::as_str))
+        .chain_err(|| ErrorKind::ImageCheckFailure(format!("{}:{}", image, version)))?;
+    if !found {
+        return Err(ErrorKind::MissingImage(format!("{}:{}", image, version), name.registry).into());
+    }
+    Ok(())
+}
+
+/// All images a manifest runs, along with what uses them
+///
+/// The main image is skipped when no version is known yet.
+pub fn images(mf: &Manifest) -> Vec<(String, String, String)> {
+    let mut res = vec![];
+    if let (Some(image), Some(version)) = (&mf.image, &mf.version) {
+        res.push((mf.name.clone(), image.clone(), version.clone()));
+    }
+    let containers = mf.workers.iter().map(|w| ("worker", &w.container))
+        .chain(mf.sidecars.iter().map(|c| ("sidecar", c)))
+        .chain(mf.initContainers.iter().map(|c| ("initContainer", c)));
+    for (kind, c) in containers {
+        if let Some(image) = &c.image {
+            let (image, tag) = match &c.version {
+                Some(v) => (image.clone(), v.clone()),
+                None => split_tag(image),
+            };
+            res.push((format!("{} {}", kind, c.name), image, tag));
+        }
+    }
+    res
+}
+
+/// Check that all images of a manifest exist if the region wants it
+pub fn verify_images(mf: &Manifest, reg: &Region) -> Result<()> {
+    if let Some(cfg) = &reg.registry {
+        if !cfg.checkImages {
+            return Ok(());
+        }
+        for (user, image, tag) in images(mf) {
+            debug!("Checking {}:{} used by {}", image, tag, user);
+            verify_image(&image, &tag, cfg).chain_err(|| format!("{} has an unusable image", user))?;
+        }
+    }
+    Ok(())
+}
+
+/// Verify that the images of a version being rolled back to still exist
+///
+/// Unlike upgrades this ignores `checkImages`, as old tags are the ones registries clean up.
+pub fn verify_rollback_images(mf: &Manifest, reg: &Region) -> Result<()> {
+    let cfg = reg.registry.clone().unwrap_or_default();
+    for (user, image, tag) in images(mf) {
+        debug!("Checking {}:{} used by {}", image, tag, user);
+        verify_image(&image, &tag, &cfg).chain_err(|| format!("cannot roll back {}", user))?;
+    }
+    Ok(())
+}
+
+/// Pin the image of a manifest to a digest if the region wants it
+///
+/// Needs the version to be set. Failures only warn unless the region requires digests.
+pub fn inject(mf: &mut Manifest, reg: &Region) -> Result<()> {
+    if let Some(digests) = &reg.imageDigests {
+        let cfg = reg.registry.clone().unwrap_or_default();
+        let image = mf.image.clone().ok_or_else(|| ErrorKind::ManifestFailure("image".into()))?;
+        let version = mf.version.clone().ok_or_else(|| ErrorKind::ManifestFailure("version".into()))?;
+        match resolve_digest(&image, &version, &cfg) {
+            Ok(d) => {
+                info!("Pinning {}:{} to {}", image, version, d);
+                mf.imageDigest = Some(d);
+            },
+            Err(e) => {
+                if digests.required {
+                    return Err(e);
+                }
+                warn!("Deploying {} by tag: {}", mf.name, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{helper_credentials, parse_challenge, split_tag, CredentialSource, DockerConfig, ImageName};
+
+    #[test]
+    fn image_names() {
+        assert_eq!(ImageName::parse("nginx"), ImageName {
+            registry: "registry-1.docker.io".into(),
+            repository: "library/nginx".into(),
+        });
+        assert_eq!(ImageName::parse("babylonhealth/fake-ask"), ImageName {
+            registry: "registry-1.docker.io".into(),
+            repository: "babylonhealth/fake-ask".into(),
+        });
+        assert_eq!(ImageName::parse("quay.io/babylonhealth/fake-ask"), ImageName {
+            registry: "quay.io".into(),
+            repository: "babylonhealth/fake-ask".into(),
+        });
+        assert_eq!(ImageName::parse("localhost:5000/fake-ask"), ImageName {
+            registry: "localhost:5000".into(),
+            repository: "fake-ask".into(),
+        });
+    }
+
+    #[test]
+    fn image_tags() {
+        assert_eq!(split_tag("redis:4"), ("redis".into(), "4".into()));
+        assert_eq!(split_tag("gophernet/netcat"), ("gophernet/netcat".into(), "latest".into()));
+        assert_eq!(split_tag("localhost:5000/netcat"), ("localhost:5000/netcat".into(), "latest".into()));
+        assert_eq!(split_tag("localhost:5000/netcat:1.0"), ("localhost:5000/netcat".into(), "1.0".into()));
+        assert_eq!(split_tag("redis@sha256:abcd"), ("redis".into(), "sha256:abcd".into()));
+        assert_eq!(split_tag("localhost:5000/netcat:1.0@sha256:abcd"), ("localhost:5000/netcat".into(), "sha256:abcd".into()));
+    }
+
+    #[test]
+    fn docker_credential_sources() {
+        let quay = ImageName::parse("quay.io/babylonhealth/fake-ask").auth_keys();
+        let hub = ImageName::parse("nginx").auth_keys();
+        let cfg : DockerConfig = serde_json::from_str(r#"{
+            "auths": {"quay.io": {"auth": "dXNlcjpwYXNz"}, "https://index.docker.io/v1/": {}}
+        }"#).unwrap();
+        assert_eq!(cfg.source(&quay), CredentialSource::Auth("dXNlcjpwYXNz".into()));
+        assert_eq!(cfg.source(&hub), CredentialSource::Anonymous);
+
+        let cfg : DockerConfig = serde_json::from_str(r#"{
+            "auths": {"quay.io": {}},
+            "credsStore": "desktop",
+            "credHelpers": {"quay.io": "ecr-login"}
+        }"#).unwrap();
+        assert_eq!(cfg.source(&quay), CredentialSource::Helper("ecr-login".into()));
+        assert_eq!(cfg.source(&hub), CredentialSource::Store("desktop".into()));
+        assert!(helper_credentials("shipcat-not-installed", "quay.io", true).is_err());
+    }
+
+    #[test]
+    fn bearer_challenge() {
+        let c = parse_challenge(r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull""#).unwrap();
+        assert_eq!(c["realm"], "https://auth.docker.io/token");
+        assert_eq!(c["scope"], "repository:library/nginx:pull");
+        assert!(parse_challenge("Basic realm=\"x\"").is_none());
+
+        // quoted values can contain commas and escaped quotes
+        let c = parse_challenge(r#"Bearer realm="https://quay.io/v2/auth", service=quay.io,scope="repository:a/b:pull,push",error="say \"hi\"""#).unwrap();
+        assert_eq!(c["realm"], "https://quay.io/v2/auth");
+        assert_eq!(c["service"], "quay.io");
+        assert_eq!(c["scope"], "repository:a/b:pull,push");
+        assert_eq!(c["error"], "say \"hi\"");
+        assert_eq!(c.len(), 4);
+        assert!(parse_challenge(r#"Bearer realm="unterminated"#).is_none());
+    }
+}
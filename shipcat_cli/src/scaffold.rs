@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde_yaml::Value;
+
+use shipcat_definitions::{ConfigType, Environment};
+use super::{Config, Team};
+use super::{Result, ResultExt};
+
+/// Parameters for a new service
+pub struct NewService {
+    /// Name of the service, and its folder in `services/`
+    pub name: String,
+    /// Owning team, must exist in shipcat.conf
+    pub team: String,
+    /// Language skeleton to use from `templates/skeletons/`
+    pub language: Option<String>,
+    /// Whether to expose the service through kong
+    pub kong: bool,
+    /// Regions to deploy to (defaults to every dev region)
+    pub regions: Vec<String>,
+    /// Git repository (defaults to the github organisation in shipcat.conf)
+    pub repo: Option<String>,
+}
+
+/// Files generated for a new service, relative to its service folder
+pub struct Scaffold {
+    pub files: Vec<(PathBuf, String)>,
+}
+
+/// Resources used when no skeleton provides any
+const DEFAULT_SKELETON: &str = "resources:
+  limits:
+    cpu: 1
+    memory: 1Gi
+  requests:
+    cpu: 100m
+    memory: 256Mi
+";
+
+fn skeletons_dir() -> PathBuf {
+    Path::new(".").join("templates").join("skeletons")
+}
+
+/// Skeleton folder for a language
+///
+/// Languages are plain folder names so they cannot point outside `templates/skeletons`.
+fn skeleton_dir(language: &str) -> Result<PathBuf> {
+    let re = Regex::new(r"^[a-z0-9-]+$").unwrap();
+    if !re.is_match(language) {
+        bail!("Invalid language '{}': must be lowercase alphanumerics and dashes", language);
+    }
+    Ok(skeletons_dir().join(language))
+}
+
+/// Names of the available language skeletons
+fn skeleton_names() -> Vec<String> {
+    let mut names = fs::read_dir(skeletons_dir()).map(|rd| {
+        rd.filter_map(|e| e.ok())
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|n| n != "default")
+            .collect::<Vec<_>>()
+    }).unwrap_or_default();
+    names.sort();
+    names
+}
+
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s)
+}
+
+/// Text of the generated `shipcat.yml` without any skeleton
+fn render_manifest(svc: &NewService, team: &Team, conf: &Config) -> String {
+    let repo = svc.repo.clone()
+        .unwrap_or_else(|| format!("https://github.com/{}/{}", conf.github.organisation, svc.name));
+    let mut lines = vec![format!("name: {}", svc.name)];
+    if !conf.defaults.imagePrefix.is_empty() {
+        lines.push(format!("image: {}/{}", conf.defaults.imagePrefix, svc.name));
+    }
+    lines.push(format!("replicaCount: {}", conf.defaults.replicaCount));
+    lines.push("metadata:".into());
+    lines.push(format!("  team: {}", team.name));
+    lines.push(format!("  repo: {}", repo));
+    if !team.owners.is_empty() {
+        lines.push("  contacts:".into());
+        for c in &team.owners {
+            lines.push(format!("  - name: {}", quoted(&c.name)));
+            lines.push(format!("    slack: {}", quoted(&c.slack)));
+            if let Some(gh) = &c.github {
+                lines.push(format!("    github: {}", gh));
+            }
+        }
+    }
+    if let Some(s) = &team.support {
+        lines.push(format!("  support: {}", quoted(s)));
+    }
+    if let Some(n) = &team.notifications {
+        lines.push(format!("  notifications: {}", quoted(n)));
+    }
+    if let Some(l) = &svc.language {
+        lines.push(format!("language: {}", l));
+    }
+    lines.push("regions:".into());
+    for r in &svc.regions {
+        lines.push(format!("- {}", r));
+    }
+    if svc.kong {
+        lines.push("kong:".into());
+        lines.push(format!("  uris: /{}", svc.name));
+    }
+    let mut res = lines.join("\n");
+    res.push('\n');
+    res
+}
+
+/// Merge skeleton yaml below the generated yaml
+///
+/// Keys generated from the config and flags win, and nested maps are merged.
+fn merge_yaml(generated: &mut Value, skeleton: Value) {
+    if let (Value::Mapping(gen), Value::Mapping(skel)) = (generated, skeleton) {
+        for (k, v) in skel {
+            if let Some(g) = gen.get_mut(&k) {
+                merge_yaml(g, v);
+            } else {
+                gen.insert(k, v);
+            }
+        }
+    }
+}
+
+/// Add a skeleton `shipcat.yml` to the generated manifest
+fn merge_skeleton(manifest: &str, skeleton: &str, source: &str) -> Result<String> {
+    let mut gen : Value = serde_yaml::from_str(manifest)?;
+    let skel : Value = serde_yaml::from_str(skeleton)
+        .chain_err(|| format!("Failed to parse skeleton manifest {}", source))?;
+    if skel.as_mapping().is_none() {
+        bail!("Skeleton manifest {} must be a yaml mapping", source);
+    }
+    merge_yaml(&mut gen, skel);
+    let res = serde_yaml::to_string(&gen)?;
+    Ok(format!("{}\n", res.trim_start_matches("---\n").trim_end()))
+}
+
+/// Generate the files for a new service
+///
+/// The `shipcat.yml` is built from the config, and the language skeleton's
+/// `shipcat.yml` is merged into it as yaml, dropping its comments.
+/// Other skeleton files are copied as is.
+pub fn render(svc: &NewService, conf: &Config) -> Result<Scaffold> {
+    let team = if let Some(t) = conf.teams.iter().find(|t| t.name == svc.team) {
+        t
+    } else {
+        bail!("The team name must match one of the team names in shipcat.conf");
+    };
+    let mut manifest = render_manifest(svc, team, conf);
+    let mut files = vec![];
+
+    let dir = skeleton_dir(svc.language.as_ref().map_or("default", |l| l.as_str()))?;
+    if dir.is_dir() {
+        let mut entries = fs::read_dir(&dir)?.filter_map(|e| e.ok().map(|e| e.path())).collect::<Vec<_>>();
+        entries.sort();
+        for pth in entries {
+            let data = fs::read_to_string(&pth)
+                .chain_err(|| format!("Failed to read skeleton file {}", pth.display()))?;
+            let fname = pth.file_name().unwrap().to_owned();
+            if fname == "shipcat.yml" {
+                manifest = merge_skeleton(&manifest, &data, &pth.display().to_string())?;
+            } else {
+                files.push((PathBuf::from(fname), data));
+            }
+        }
+    } else if let Some(l) = &svc.language {
+        bail!("No skeleton for {} found in {} (available: {})", l, skeletons_dir().display(), skeleton_names().join(", "));
+    } else {
+        manifest = merge_skeleton(&manifest, DEFAULT_SKELETON, "default")?;
+    }
+    files.insert(0, (PathBuf::from("shipcat.yml"), manifest));
+    Ok(Scaffold { files })
+}
+
+/// Create a new service folder and validate it in all its regions
+///
+/// The service folder is removed again if the manifest fails to validate.
+pub fn create(mut svc: NewService, conf: &Config) -> Result<()> {
+    let dir = Path::new(".").join("services").join(&svc.name);
+    if dir.exists() {
+        bail!("Service folder {} already exists", dir.display());
+    }
+    if svc.regions.is_empty() {
+        for r in conf.list_regions() {
+            if conf.get_region(&r)?.environment == Environment::Dev {
+                svc.regions.push(r);
+            }
+        }
+    }
+    let scaffold = render(&svc, conf)?;
+
+    fs::create_dir_all(&dir)?;
+    for (pth, data) in &scaffold.files {
+        fs::write(dir.join(pth), data)?;
+    }
+    if let Err(e) = validate(&svc) {
+        fs::remove_dir_all(&dir)?;
+        return Err(e).chain_err(|| format!("Generated manifest for {} does not validate", svc.name));
+    }
+    info!("Created {} for {}", dir.display(), svc.regions.join(", "));
+    Ok(())
+}
+
+fn validate(svc: &NewService) -> Result<()> {
+    for r in &svc.regions {
+        let (conf, reg) = Config::new(ConfigType::Base, r)?;
+        let mf = shipcat_filebacked::load_manifest(&svc.name, &conf, &reg)?.stub(&reg)?;
+        mf.verify(&conf, &reg)?;
+        debug!("validated {} for {}", svc.name, reg.name);
+    }
+    Ok(())
+}
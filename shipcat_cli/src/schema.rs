@@ -0,0 +1,33 @@
+use super::{Config, Result};
+
+/// Files shipcat can generate a JSON Schema for
+pub enum SchemaKind {
+    /// A service's `shipcat.yml`
+    Manifest,
+    /// Environment or region override files, e.g. `dev-uk.yml`
+    Overrides,
+    /// Shared fragments listed in `extends`
+    Fragment,
+    /// The main `shipcat.conf`
+    Config,
+}
+
+/// Generate the JSON Schema for a kind of file
+///
+/// Descriptions are taken from the doc comments on the underlying structs.
+pub fn generate(kind: SchemaKind) -> Result<serde_json::Value> {
+    let schema = match kind {
+        SchemaKind::Manifest => shipcat_filebacked::manifest_schema(),
+        SchemaKind::Overrides => shipcat_filebacked::overrides_schema(),
+        SchemaKind::Fragment => shipcat_filebacked::fragment_schema(),
+        SchemaKind::Config => schemars::schema_for!(Config),
+    };
+    Ok(serde_json::to_value(&schema)?)
+}
+
+/// Print the JSON Schema for a kind of file
+pub fn print(kind: SchemaKind) -> Result<()> {
+    let schema = generate(kind)?;
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
@@ -1,10 +1,16 @@
 use slack_hook::{Slack, PayloadBuilder, SlackLink, SlackText, SlackUserLink, AttachmentBuilder};
 use slack_hook::SlackTextContent::{self, Text, Link, User};
+use std::collections::BTreeMap;
 use std::env;
+use std::sync::{Arc, Mutex};
 use semver::Version;
+use serde_json::Value;
 
+use shipcat_definitions::region::slack_api_url;
 use super::helm::helpers;
 use super::structs::Metadata;
+use super::SlackWebhook;
+use crate::webhooks::UpgradeState;
 use super::{Result, ErrorKind, ResultExt};
 
 /// Slack message options we support
@@ -33,6 +39,9 @@ pub struct Message {
 
     /// Optional version to send when not having code diffs
     pub version: Option<String>,
+
+    /// Optional image digest the version was pinned to
+    pub digest: Option<String>,
 }
 
 pub fn env_hook_url() -> Result<String> {
@@ -55,22 +64,52 @@ pub fn have_credentials() -> Result<()> {
     Ok(())
 }
 
+/// Send a `Message` to the slack destination configured in the environment
 pub fn send(msg: Message) -> Result<()> {
-    let hook_chan : String = env_channel()?;
-    send_internal(msg.clone(), hook_chan)?;
-    if let Some(md) = &msg.metadata {
-        if let Some(chan) = &md.notifications {
-            let c = chan.clone();
-            send_internal(msg, c.to_string())?;
-        }
+    let hook = SlackWebhook {
+        url: env_hook_url()?,
+        token: None,
+        apiUrl: slack_api_url(),
+        channel: env_channel()?,
+        username: env_username(),
+        events: vec![],
+        routes: vec![],
+    };
+    // manual messages are not routed, they only go to the configured channel
+    let chan = hook.channel.clone();
+    send_internal(msg, chan, &hook)
+}
+
+/// Send a `Message` about an upgrade state to the channels a region's slack webhook routes it to
+pub fn send_webhook(msg: Message, us: &UpgradeState, hook: &SlackWebhook) -> Result<()> {
+    for chan in route(&msg, us, hook) {
+        send_internal(msg.clone(), chan, hook)?;
     }
     Ok(())
 }
 
+/// Channels a message goes to
+///
+/// The service's notifications channel (which defaults to its team's), else the webhook's channel,
+/// for the webhook's `events`. Then any extra channels routed for the upgrade state.
+pub fn route(msg: &Message, us: &UpgradeState, hook: &SlackWebhook) -> Vec<String> {
+    let mut chans = vec![];
+    if hook.events.contains(us) || (hook.token.is_some() && *us == UpgradeState::Pending) {
+        let notifications = msg.metadata.as_ref().and_then(|md| md.notifications.as_ref());
+        chans.push(notifications.map(|c| c.to_string()).unwrap_or_else(|| hook.channel.clone()));
+    }
+    for r in hook.routes.iter().filter(|r| r.events.contains(us)) {
+        if !chans.contains(&*r.channel) {
+            chans.push(r.channel.to_string());
+        }
+    }
+    chans
+}
+
 /// Send a `Message` to a configured slack destination
-fn send_internal(msg: Message, chan: String) -> Result<()> {
-    let hook_url : &str = &env_hook_url()?;
-    let hook_user : String = env_username();
+fn send_internal(msg: Message, chan: String, hook: &SlackWebhook) -> Result<()> {
+    let hook_url : &str = &hook.url;
+    let hook_user : String = hook.username.clone();
 
     // if hook url is invalid, chain it so we know where it came from:
     let slack = Slack::new(hook_url).chain_err(|| ErrorKind::SlackSendFailure(hook_url.to_string()))?;
@@ -123,14 +162,12 @@ fn send_internal(msg: Message, chan: String) -> Result<()> {
         }
     }
 
+    if let Some(d) = msg.digest {
+        texts.push(Text(SlackText::new(format!("({})", short_digest(&d)))));
+    }
+
     if let Some(link) = msg.link {
-        let split: Vec<&str> = link.split('|').collect();
-        // Full sanity check here as it could come from the CLI
-        if split.len() > 2 {
-            bail!("Link {} not in the form of url|description", link);
-        }
-        let desc = if split.len() == 2 { split[1].into() } else { link.clone() };
-        let addr = if split.len() == 2 { split[0].into() } else { link.clone() };
+        let (addr, desc) = split_link(&link)?;
         texts.push(Link(SlackLink::new(&addr, &desc)));
     } else {
         // Auto link/text from originator if no ink set
@@ -172,17 +209,22 @@ fn short_ver(ver: &str) -> String {
     }
 }
 
-fn infer_metadata_single_link(md: &Metadata, ver: String) -> SlackTextContent {
-    let url = if Version::parse(&ver).is_ok() {
+/// Abbreviate a `sha256:` digest the way docker does
+fn short_digest(digest: &str) -> String {
+    let hex = digest.trim_start_matches("sha256:");
+    format!("sha256:{}", &hex[..std::cmp::min(12, hex.len())])
+}
+
+fn version_url(md: &Metadata, ver: &str) -> String {
+    if Version::parse(&ver).is_ok() {
         let tag = md.version_template(&ver).unwrap_or(ver.to_string());
         format!("{}/releases/tag/{}", md.repo, tag)
     } else {
         format!("{}/commit/{}", md.repo, ver)
-    };
-    Link(SlackLink::new(&url, &short_ver(&ver)))
+    }
 }
 
-fn create_github_compare_url(md: &Metadata, vers: (&str, &str)) -> SlackTextContent {
+fn compare_url(md: &Metadata, vers: (&str, &str)) -> String {
     let (v0, v1) = if Version::parse(vers.0).is_ok() {
         let v0 = md.version_template(&vers.0).unwrap_or(vers.0.to_string());
         let v1 = md.version_template(&vers.1).unwrap_or(vers.1.to_string());
@@ -190,30 +232,209 @@ fn create_github_compare_url(md: &Metadata, vers: (&str, &str)) -> SlackTextCont
     } else {
         (vers.0.into(), vers.1.into())
     };
-    let url = format!("{}/compare/{}...{}", md.repo, v0, v1);
-    Link(SlackLink::new(&url, &short_ver(vers.1)))
+    format!("{}/compare/{}...{}", md.repo, v0, v1)
+}
+
+fn infer_metadata_single_link(md: &Metadata, ver: String) -> SlackTextContent {
+    Link(SlackLink::new(&version_url(md, &ver), &short_ver(&ver)))
+}
+
+fn create_github_compare_url(md: &Metadata, vers: (&str, &str)) -> SlackTextContent {
+    Link(SlackLink::new(&compare_url(md, vers), &short_ver(vers.1)))
 }
 
 fn infer_slack_notifies(md: &Metadata) -> Vec<SlackTextContent> {
     md.contacts.iter().map(|cc| { User(SlackUserLink::new(&cc.slack)) }).collect()
 }
 
-/// Infer originator of a message
-fn infer_ci_links() -> SlackTextContent {
+/// Infer originator of a message as an optional url and a description
+fn infer_ci_origin() -> (Option<String>, String) {
     if let (Ok(url), Ok(name), Ok(nr)) = (env::var("BUILD_URL"),
                                           env::var("JOB_NAME"),
                                           env::var("BUILD_NUMBER")) {
         // we are on jenkins
-        Link(SlackLink::new(&url, &format!("{}#{}", name, nr)))
+        (Some(url), format!("{}#{}", name, nr))
     } else if let (Ok(url), Ok(name), Ok(nr)) = (env::var("CIRCLE_BUILD_URL"),
                                                  env::var("CIRCLE_JOB"),
                                                  env::var("CIRCLE_BUILD_NUM")) {
         // we are on circle
-        Link(SlackLink::new(&url, &format!("{}#{}", name, nr)))
+        (Some(url), format!("{}#{}", name, nr))
     } else if let Ok(user) = env::var("USER") {
-        Text(SlackText::new(format!("(via {})", user)))
+        (None, format!("(via {})", user))
     } else {
         warn!("Could not infer ci links from environment");
-        Text(SlackText::new("via unknown user".to_string()))
+        (None, "via unknown user".to_string())
+    }
+}
+
+/// Infer originator of a message
+fn infer_ci_links() -> SlackTextContent {
+    match infer_ci_origin() {
+        (Some(url), desc) => Link(SlackLink::new(&url, &desc)),
+        (None, desc) => Text(SlackText::new(desc)),
+    }
+}
+
+/// Split a `url|description` link
+fn split_link(link: &str) -> Result<(String, String)> {
+    let split: Vec<&str> = link.split('|').collect();
+    // Full sanity check here as it could come from the CLI
+    if split.len() > 2 {
+        bail!("Link {} not in the form of url|description", link);
+    }
+    let desc = if split.len() == 2 { split[1].into() } else { link.to_string() };
+    let addr = if split.len() == 2 { split[0].into() } else { link.to_string() };
+    Ok((addr, desc))
+}
+
+// ----------------------------------------------------------------------------
+// Slack Web API mode
+
+/// Messages that started an upgrade's threads
+///
+/// Maps the configured channel name to the channel id and timestamp slack gave the message.
+/// Shared between clones of the same `UpgradeData` so rollbacks end up in the same thread.
+pub type Threads = Arc<Mutex<BTreeMap<String, (String, String)>>>;
+
+/// Post a `Message` through the Slack Web API, keeping one message per upgrade
+///
+/// The first event of an upgrade posts a message with the diff in its thread.
+/// Later events edit that message, and contacts are mentioned in the thread on failures.
+pub fn send_threaded(msg: Message, us: &UpgradeState, hook: &SlackWebhook, threads: &Threads) -> Result<()> {
+    let token = match &hook.token {
+        Some(t) => t.clone(),
+        None => bail!("slack webhook for {} has no api token", hook.channel),
+    };
+    for chan in route(&msg, us, hook) {
+        send_threaded_internal(&msg, us, &chan, hook, &token, threads)?;
+    }
+    Ok(())
+}
+
+fn send_threaded_internal(msg: &Message, us: &UpgradeState, chan: &str, hook: &SlackWebhook, token: &str, threads: &Threads) -> Result<()> {
+    debug!("Got threaded slack notify {:?}", msg);
+    let summary = api_summary(msg)?;
+    let parent = threads.lock().unwrap().get(chan).cloned();
+    let (channel, ts, new_thread) = if let Some((channel, ts)) = parent {
+        let update = serde_json::json!({
+            "channel": channel,
+            "ts": ts,
+            "text": "",
+            "attachments": [summary],
+        });
+        api_call(hook, token, "chat.update", &update)?;
+        (channel, ts, false)
+    } else {
+        let post = serde_json::json!({
+            "channel": chan,
+            "username": hook.username,
+            "icon_emoji": ":ship:",
+            "attachments": [summary],
+        });
+        let res = api_call(hook, token, "chat.postMessage", &post)?;
+        let (channel, ts) = match (res["channel"].as_str(), res["ts"].as_str()) {
+            (Some(c), Some(t)) => (c.to_string(), t.to_string()),
+            _ => bail!("slack did not return a message timestamp for {}", chan),
+        };
+        threads.lock().unwrap().insert(chan.to_string(), (channel.clone(), ts.clone()));
+        (channel, ts, true)
+    };
+
+    // diffs go in the thread once, mentions only when something went wrong
+    let mut attachments = vec![];
+    if new_thread {
+        if let Some(diff) = api_diff_attachment(msg) {
+            attachments.push(diff);
+        }
+    }
+    let failed = *us == UpgradeState::Failed || *us == UpgradeState::RollbackFailed;
+    let mentions = match &msg.metadata {
+        Some(md) if failed && !msg.quiet => {
+            md.contacts.iter().map(|cc| format!("<{}>", cc.slack)).collect::<Vec<_>>().join(" ")
+        }
+        _ => String::new(),
+    };
+    if !attachments.is_empty() || !mentions.is_empty() {
+        let text = if mentions.is_empty() { "diff".to_string() } else { mentions };
+        let reply = serde_json::json!({
+            "channel": channel,
+            "thread_ts": ts,
+            "username": hook.username,
+            "icon_emoji": ":ship:",
+            "text": text,
+            "attachments": attachments,
+        });
+        api_call(hook, token, "chat.postMessage", &reply)?;
+    }
+    Ok(())
+}
+
+/// Main attachment with text, version links and origin
+fn api_summary(msg: &Message) -> Result<Value> {
+    let mut texts = vec![msg.text.clone()];
+    if let Some(md) = &msg.metadata {
+        let versions = msg.code.as_ref().and_then(|diff| helpers::infer_version_change(diff));
+        if let Some((v1, v2)) = versions {
+            texts.push(format!("<{}|{}>", compare_url(md, (&v1, &v2)), short_ver(&v2)));
+        } else if let Some(v) = &msg.version {
+            texts.push(format!("<{}|{}>", version_url(md, v), short_ver(v)));
+        }
+    }
+    if let Some(d) = &msg.digest {
+        texts.push(format!("({})", short_digest(d)));
+    }
+    let (url, desc) = if let Some(link) = &msg.link {
+        let (addr, desc) = split_link(link)?;
+        (Some(addr), desc)
+    } else {
+        infer_ci_origin()
+    };
+    texts.push(match url {
+        Some(u) => format!("<{}|{}>", u, desc),
+        None => desc,
+    });
+    Ok(serde_json::json!({
+        "fallback": msg.text,
+        "color": msg.color,
+        "text": texts.join(" "),
+        "mrkdwn_in": ["text"],
+    }))
+}
+
+/// Diff attachment, collapsed by slack when long
+///
+/// Left out when the diff is just a version change, as the summary links to it.
+fn api_diff_attachment(msg: &Message) -> Option<Value> {
+    let diff = msg.code.as_ref()?;
+    if let Some((v1, v2)) = helpers::infer_version_change(diff) {
+        if helpers::diff_is_version_only(diff, (&v1, &v2)) {
+            return None;
+        }
+    }
+    Some(serde_json::json!({
+        "fallback": "diff",
+        "color": "#439FE0",
+        "title": "diff",
+        "text": format!("```{}```", diff),
+        "mrkdwn_in": ["text"],
+    }))
+}
+
+/// Call a Slack Web API method, failing on slack errors
+fn api_call(hook: &SlackWebhook, token: &str, method: &str, body: &Value) -> Result<Value> {
+    let url = format!("{}/{}", hook.apiUrl.trim_end_matches('/'), method);
+    let client = reqwest::Client::new();
+    let mut res = client.post(&url)
+        .bearer_auth(token)
+        .json(body)
+        .send()
+        .chain_err(|| ErrorKind::SlackSendFailure(url.clone()))?;
+    if !res.status().is_success() {
+        bail!("slack {} responded with {}", method, res.status());
+    }
+    let reply : Value = res.json().chain_err(|| ErrorKind::SlackSendFailure(url.clone()))?;
+    if reply["ok"].as_bool() != Some(true) {
+        bail!("slack {} failed: {}", method, reply["error"].as_str().unwrap_or("unknown error"));
     }
+    Ok(reply)
 }
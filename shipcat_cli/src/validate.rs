@@ -1,5 +1,6 @@
 use super::{Config, Region};
 use super::Result;
+use crate::{freeze, registry};
 
 /// Validate the manifest of a service in the services directory
 ///
@@ -7,6 +8,7 @@ use super::Result;
 /// and `verify` their parameters.
 /// Optionally, it will also verify that all secrets are found in the corresponding
 /// vault locations serverside (which require vault credentials).
+/// Images are checked against their registries in regions with `registry.checkImages` set.
 pub fn manifest(services: Vec<String>, conf: &Config, reg: &Region, secrets: bool) -> Result<()> {
     conf.verify()?; // this should work even with a limited config!
     for svc in services {
@@ -17,6 +19,7 @@ pub fn manifest(services: Vec<String>, conf: &Config, reg: &Region, secrets: boo
             shipcat_filebacked::load_manifest(&svc, conf, reg)?.stub(reg)?
         };
         mf.verify(conf, reg)?;
+        registry::verify_images(&mf, reg)?;
         info!("validated {} for {}", svc, reg.name);
     }
     Ok(())
@@ -67,6 +70,12 @@ pub fn secret_presence_git(conf: &Config, regions: Vec<String>) -> Result<()> {
 /// Manifest repositories should verify with the full file configs for all the sanity.
 pub fn config(conf: Config) -> Result<()> {
     conf.verify()?;
+    // times and cron expressions need parsing beyond the definitions
+    for r in conf.list_regions() {
+        if let Some(dp) = &conf.get_region(&r)?.deployPolicy {
+            freeze::verify(dp, &r)?;
+        }
+    }
     Ok(())
 }
 
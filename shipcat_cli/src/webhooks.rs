@@ -1,31 +1,40 @@
+use std::env;
+
 use crate::{
     audit,
+    auditlog,
+    delivery,
     grafana,
     slack,
     Result
 };
+use crate::audit::{AuditEvent, AuditDeploymentPayload, AuditOverride, AuditReconciliationPayload};
 use crate::helm::{UpgradeData, UpgradeMode};
 use super::{Region, Webhook};
 
-/// The different states an upgrade can be in
-#[derive(Serialize, PartialEq, Clone)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-pub enum UpgradeState {
-    /// Before action
-    Pending,
-    /// No errors
-    Completed,
-    /// Errors
-    Failed,
-    // Before revert
-    RollingBack,
-    // After revert
-    RolledBack,
-    // Fail to revert
-    RollbackFailed,
+pub use shipcat_definitions::region::UpgradeState;
+
+/// Environment variables that configured notifications before region webhooks
+///
+/// Returns the prefixes (and webhook kinds) that are set while the region has no matching webhook.
+pub fn unused_legacy_env(reg: &Region, vars: &[String]) -> Vec<(&'static str, &'static str)> {
+    let whs = reg.webhooks.clone().unwrap_or_default();
+    let slack = whs.iter().any(|wh| match wh { Webhook::Slack(_) => true, _ => false });
+    let grafana = whs.iter().any(|wh| match wh { Webhook::Grafana(_) => true, _ => false });
+    let mut res = vec![];
+    for (prefix, kind, configured) in &[("SLACK_SHIPCAT_", "slack", slack), ("GRAFANA_SHIPCAT_", "grafana", grafana)] {
+        if !configured && vars.iter().any(|v| v.starts_with(prefix)) {
+            res.push((*prefix, *kind));
+        }
+    }
+    res
 }
 
 pub fn ensure_requirements(reg: &Region) -> Result<()> {
+    let vars = env::vars().map(|(k, _)| k).collect::<Vec<_>>();
+    for (prefix, kind) in unused_legacy_env(reg, &vars) {
+        warn!("{}* is set but {} has no {} webhook in shipcat.conf, so upgrades will not notify {}", prefix, reg.name, kind, kind);
+    }
     if let Some(whs) = &reg.webhooks {
         for wh in whs {
             wh.get_configuration()?;
@@ -37,14 +46,27 @@ pub fn ensure_requirements(reg: &Region) -> Result<()> {
 /// Throw events to configured webhooks - warning on delivery errors
 ///
 /// Http errors are NOT propagated from here
-pub fn reconcile_event(us: UpgradeState, reg: &Region) {
+pub fn reconcile_event(us: UpgradeState, reg: &Region, ovr: Option<&AuditOverride>) {
     if let Some(whs) = &reg.webhooks {
         for wh in whs {
             if let Ok(whc) = wh.get_configuration() {
                 if let Err(e) = match wh {
                     Webhook::Audit(h) => {
-                        audit::audit_reconciliation(&us, &reg.name, &h, whc)
+                        audit::audit_reconciliation(&us, &reg.name, ovr, &h, whc)
                     }
+                    Webhook::Http(h) => {
+                        if !wh.wants(&us) {
+                            continue;
+                        }
+                        let ae = AuditEvent::new(&whc, &us, AuditReconciliationPayload::new(&whc, &reg.name, ovr));
+                        delivery::send(&ae, h)
+                    }
+                    Webhook::AuditLog(h) => {
+                        let ae = AuditEvent::new(&whc, &us, AuditReconciliationPayload::new(&whc, &reg.name, ovr));
+                        auditlog::append(&ae, h, &reg.namespace)
+                    }
+                    // only individual upgrades are notified about
+                    Webhook::Slack(_) | Webhook::Grafana(_) => Ok(()),
                 } {
                     warn!("Failed to notify about reconciliation event: {}", e)
                 }
@@ -57,115 +79,114 @@ pub fn reconcile_event(us: UpgradeState, reg: &Region) {
 ///
 /// Http errors are NOT propagated from here
 pub fn upgrade_event(us: UpgradeState, ud: &UpgradeData, reg: &Region) {
-    //for wh in &reg.webhooks {
-    //    if let Err(e) = match wh {
-    //        Webhook::Audit(h) => {
-    //            audit::audit_deployment(us, ud, h)
-    //        }
-    //    } {
-    //        warn!("Failed to notify about deployment event: {}", e)
-    //    }
-    //}
-    handle_upgrade_notifies(us, ud, &reg);
-    // TODO: make a smarter loop over webhooks in here
-    // TODO: first add grafana and slack to webhooks for region
+    dispatch(&us, ud, reg, Action::Upgrade);
+}
+
+/// Throw events to configured webhooks - warning on delivery errors
+///
+/// Http errors are NOT propagated from here
+pub fn upgrade_rollback_event(us: UpgradeState, ud: &UpgradeData, reg: &Region) {
+    dispatch(&us, ud, reg, Action::Rollback);
+}
+
+/// What a deployment event is about
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Upgrade,
+    Rollback,
 }
 
-/// Notify slack / audit endpoint of upgrades from a single upgrade
-fn handle_upgrade_notifies(us: UpgradeState, ud: &UpgradeData, reg: &Region) {
+/// Notify every configured webhook that wants to hear about an event
+fn dispatch(us: &UpgradeState, ud: &UpgradeData, reg: &Region, action: Action) {
     if let Some(whs) = &reg.webhooks {
-        for wh in whs {
-            if let Ok(whc) = wh.get_configuration() {
-                if let Err(e) = match wh {
-                    Webhook::Audit(h) => {
-                        audit::audit_deployment(&us, &ud, &h, whc)
+        for wh in whs.iter().filter(|wh| wh.wants(us)) {
+            let whc = match wh.get_configuration() {
+                Ok(whc) => whc,
+                Err(_) => continue, // warned about in ensure_requirements
+            };
+            if let Err(e) = match wh {
+                Webhook::Audit(h) => audit::audit_deployment(us, ud, action == Action::Rollback, h, whc),
+                Webhook::Slack(h) => {
+                    let msg = slack_message(us, ud, action);
+                    if h.token.is_some() {
+                        slack::send_threaded(msg, us, h, &ud.slack_threads)
+                    } else {
+                        slack::send_webhook(msg, us, h)
                     }
-                } {
-                    warn!("Failed to notify about deployment event: {}", e)
                 }
+                Webhook::Grafana(h) => {
+                    // only annotate things that changed the cluster
+                    let failed_rollback = action == Action::Rollback &&
+                        (*us == UpgradeState::Failed || *us == UpgradeState::RollbackFailed);
+                    if ud.mode == UpgradeMode::DiffOnly || failed_rollback {
+                        continue;
+                    }
+                    grafana::create(grafana_annotation(ud, action), h)
+                }
+                Webhook::Http(h) => {
+                    let ae = AuditEvent::new(&whc, us, AuditDeploymentPayload::new(&whc, ud, action == Action::Rollback));
+                    delivery::send(&ae, h)
+                }
+                Webhook::AuditLog(h) => {
+                    let ae = AuditEvent::new(&whc, us, AuditDeploymentPayload::new(&whc, ud, action == Action::Rollback));
+                    auditlog::append(&ae, h, &reg.namespace)
+                }
+            } {
+                warn!("Failed to notify about deployment event: {}", e)
             }
         }
     }
+}
 
-    // Slack and Grafana
+/// Slack message for a deployment event
+fn slack_message(us: &UpgradeState, ud: &UpgradeData, action: Action) -> slack::Message {
+    if action == Action::Rollback {
+        let versions = match ud.restored_version() {
+            Some(v) => format!(" from {} to {}", ud.version, v),
+            None => String::new(),
+        };
+        let (color, text) = match us {
+            UpgradeState::Failed | UpgradeState::RollbackFailed => {
+                ("danger", format!("failed to rollback `{}` in {}{}", &ud.name, &ud.region, versions))
+            },
+            UpgradeState::RolledBack => ("warning", format!("rolled back `{}` in {}{}", &ud.name, &ud.region, versions)),
+            _ => ("warning", format!("rolling back `{}` in {}{}", &ud.name, &ud.region, versions)),
+        };
+        return slack::Message {
+            text,
+            color: Some(color.into()),
+            metadata: ud.metadata.clone(),
+            ..Default::default()
+        };
+    }
 
     let code = if ud.diff.is_empty() { None } else { Some(ud.diff.clone()) };
     let (color, text) = match us {
+        UpgradeState::Pending => ("#439FE0", format!("starting to {} `{}` in `{}`", ud.mode, ud.name, ud.region)),
         UpgradeState::Completed => ("good".into(), format!("{} `{}` in `{}`", ud.mode.action_verb(), ud.name, ud.region)),
         UpgradeState::Failed => ("danger".into(), format!("failed to {} `{}` in `{}`", ud.mode, ud.name, ud.region)),
         _ => ("good", format!("action state: {}", serde_json::to_string(&us).unwrap_or("unknown".into()))),
     };
-
-    match us {
-        UpgradeState::Completed | UpgradeState::Failed => {
-            if ud.mode != UpgradeMode::DiffOnly {
-              let _ = grafana::create(grafana::Annotation {
-                  event: grafana::Event::Upgrade,
-                  service: ud.name.clone(),
-                  version: ud.version.clone(),
-                  region: ud.region.clone(),
-                  time: grafana::TimeSpec::Now,
-              });
-            }
-            let _ = slack::send(slack::Message {
-                text, code,
-                color: Some(String::from(color)),
-                version: Some(ud.version.clone()),
-                metadata: ud.metadata.clone(),
-                ..Default::default()
-            });
-        }
-        _ => {},
+    slack::Message {
+        text, code,
+        color: Some(String::from(color)),
+        version: Some(ud.version.clone()),
+        digest: ud.digest.clone(),
+        metadata: ud.metadata.clone(),
+        ..Default::default()
     }
 }
 
-/// Throw events to configured webhooks - warning on delivery errors
-///
-/// Http errors are NOT propagated from here
-pub fn upgrade_rollback_event(us: UpgradeState, ud: &UpgradeData, reg: &Region) {
-    if let Some(whs) = &reg.webhooks {
-        for wh in whs {
-            if let Ok(whc) = wh.get_configuration() {
-                if let Err(e) = match wh {
-                    Webhook::Audit(h) => {
-                        audit::audit_deployment(&us, &ud, &h, whc)
-                    }
-                } {
-                    warn!("Failed to notify about rollback event: {}", e)
-                }
-            }
-        }
-    }
-
-    if let Err(e) = match us {
-        // UpgradeState::RollingBack => {},
-        UpgradeState::Completed | UpgradeState::RolledBack => {
-            let _ = slack::send(slack::Message {
-                text: format!("rolling back `{}` in {}", &ud.name, &ud.region),
-                color: Some("warning".into()),
-                metadata: ud.metadata.clone(),
-                ..Default::default()
-            });
-            grafana::create(grafana::Annotation {
-                event: grafana::Event::Rollback,
-                service: ud.name.clone(),
-                version: ud.version.clone(),
-                region: ud.region.clone(),
-                time: grafana::TimeSpec::Now,
-            })
-        },
-        UpgradeState::Failed | UpgradeState::RollbackFailed => {
-            slack::send(slack::Message {
-                text: format!("failed to rollback `{}` in {}", &ud.name, &ud.region),
-                color: Some("danger".into()),
-                metadata: ud.metadata.clone(),
-                ..Default::default()
-            })
+/// Grafana annotation for a deployment event
+fn grafana_annotation(ud: &UpgradeData, action: Action) -> grafana::Annotation {
+    grafana::Annotation {
+        event: match action {
+            Action::Upgrade => grafana::Event::Upgrade,
+            Action::Rollback => grafana::Event::Rollback,
         },
-        _ => { Ok(()) },
-    } {
-        warn!("Failed to notify about rollback event: {}", e);
+        service: ud.name.clone(),
+        version: ud.version.clone(),
+        region: ud.region.clone(),
+        time: grafana::TimeSpec::Now,
     }
-    // TODO: make a smarter loop over webhooks in here
-    // TODO: first add grafana and slack to webhooks for region
 }
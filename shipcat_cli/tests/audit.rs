@@ -3,6 +3,7 @@
 mod common;
 
 use std::collections::BTreeMap;
+use std::time::Instant;
 
 use url::Url;
 
@@ -14,7 +15,7 @@ use crate::mockito::mock;
 
 use crate::shipcat::audit;
 use crate::shipcat::{AuditWebhook};
-use crate::shipcat::helm::direct::UpgradeData;
+use crate::shipcat::helm::direct::{UpgradeData, UpgradeMode};
 use crate::shipcat::webhooks;
 
 #[test]
@@ -27,6 +28,7 @@ fn audit_does_audit_deployment() {
     let audcfg = AuditWebhook{
         url: Url::parse(&format!("{}/audit", mockito::SERVER_URL)).unwrap(),
         token: "1234auth".into(),
+        retries: 0,
     };
     let us = webhooks::UpgradeState::Completed;
     let ud = UpgradeData{
@@ -60,7 +62,7 @@ fn audit_does_audit_deployment() {
         .expect(1)
         .create();
 
-    assert!(audit::audit_deployment(&us, &ud, &audcfg, whc).is_ok());
+    assert!(audit::audit_deployment(&us, &ud, false, &audcfg, whc).is_ok());
     mocked.assert();
 }
 
@@ -71,7 +73,71 @@ fn audit_reconciliation_has_type() {
     whc.insert("SHIPCAT_AUDIT_CONTEXT_LINK".into(), "http://eg.server/".into());
     whc.insert("SHIPCAT_AUDIT_REVISION".into(), "egrevision".into());
 
-    let arp = audit::AuditReconciliationPayload::new(&whc, "region_name");
+    let arp = audit::AuditReconciliationPayload::new(&whc, "region_name", None);
     let ae = audit::AuditEvent::new(&whc, &webhooks::UpgradeState::Completed, arp);
     assert_eq!(ae.domain_type, "reconciliation");
 }
+
+#[test]
+fn audit_deployment_payload_details() {
+    let mut whc: BTreeMap<String, String> = BTreeMap::default();
+    whc.insert("SHIPCAT_AUDIT_CONTEXT_ID".into(), "egcontextid".into());
+    whc.insert("SHIPCAT_AUDIT_REVISION".into(), "egrevision".into());
+
+    let ud = UpgradeData{
+        name: "svc".into(),
+        version: "1.1.0".into(),
+        previous_version: Some("1.0.0".into()),
+        region: "r1".into(),
+        mode: UpgradeMode::UpgradeWaitMaybeRollback,
+        diff: "-  image: \"svc:1.0.0\"\n+  image: \"svc:1.1.0\"\n+  DB_PASSWORD: hunter2hunter2".into(),
+        digest: Some("sha256:abc".into()),
+        started: Some(Instant::now()),
+        policy_override: Some(audit::AuditOverride {
+            freezes: vec!["christmas".into()],
+            reason: Some("INC-42 outage".into()),
+            approval: Some("CHG-1234".into()),
+        }),
+        ..Default::default()
+    };
+    let adp = audit::AuditDeploymentPayload::new(&whc, &ud, true);
+    let ae = audit::AuditEvent::new(&whc, &webhooks::UpgradeState::RolledBack, adp);
+    let json = serde_json::to_value(&ae).unwrap();
+
+    assert_eq!(json["schema_version"], audit::AUDIT_SCHEMA_VERSION);
+    let payload = &json["payload"];
+    assert_eq!(payload["previous_version"], "1.0.0");
+    assert_eq!(payload["mode"], "UpgradeWaitMaybeRollback");
+    assert_eq!(payload["image_digest"], "sha256:abc");
+    assert_eq!(payload["diff"]["keys"], serde_json::json!(["DB_PASSWORD", "image"]));
+    assert_eq!(payload["diff"]["added"], 2);
+    assert_eq!(payload["diff"]["removed"], 1);
+    assert!(!json.to_string().contains("hunter2"));
+    assert_eq!(payload["rollback"]["failed_version"], "1.1.0");
+    assert_eq!(payload["rollback"]["restored_version"], "1.0.0");
+    assert!(payload["duration_ms"].is_u64());
+    assert!(payload["initiator"]["kind"].is_string());
+    assert_eq!(payload["policy_override"]["freezes"][0], "christmas");
+    assert_eq!(payload["policy_override"]["reason"], "INC-42 outage");
+    assert_eq!(payload["policy_override"]["approval"], "CHG-1234");
+}
+
+#[test]
+fn audit_explicit_rollback_payload() {
+    let mut whc: BTreeMap<String, String> = BTreeMap::default();
+    whc.insert("SHIPCAT_AUDIT_CONTEXT_ID".into(), "egcontextid".into());
+    whc.insert("SHIPCAT_AUDIT_REVISION".into(), "egrevision".into());
+
+    let ud = UpgradeData{
+        name: "svc".into(),
+        version: "1.1.0".into(),
+        rollback_to: Some("1.0.0".into()),
+        region: "r1".into(),
+        ..Default::default()
+    };
+    let adp = audit::AuditDeploymentPayload::new(&whc, &ud, true);
+    let json = serde_json::to_value(&adp).unwrap();
+    assert!(json.get("previous_version").is_none());
+    assert_eq!(json["rollback"]["failed_version"], "1.1.0");
+    assert_eq!(json["rollback"]["restored_version"], "1.0.0");
+}
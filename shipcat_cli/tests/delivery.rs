@@ -0,0 +1,103 @@
+#![warn(rust_2018_idioms)]
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use serde_derive::Serialize;
+use url::Url;
+
+use mockito;
+use shipcat;
+
+use crate::mockito::mock;
+
+use crate::shipcat::delivery;
+use crate::shipcat::{HttpWebhook, Webhook};
+use shipcat_definitions::{Config, ConfigType};
+use crate::common::setup;
+
+fn hook(path: &str) -> HttpWebhook {
+    HttpWebhook {
+        url: Url::parse(&format!("{}{}", mockito::SERVER_URL, path)).unwrap(),
+        template: Some("{\"text\": \"{{ event.service }} is {{ event.status }}\"}".into()),
+        secret: Some("sekrit".into()),
+        headers: BTreeMap::new(),
+        retries: 0,
+        events: vec![],
+    }
+}
+
+#[derive(Serialize)]
+struct FakeEvent {
+    service: String,
+    status: String,
+}
+
+#[test]
+fn delivery_signs_templated_body() {
+    let event = FakeEvent { service: "fake-ask".into(), status: "COMPLETED".into() };
+    let body = "{\"text\": \"fake-ask is COMPLETED\"}";
+    let signature = delivery::sign("sekrit", body).unwrap();
+    assert!(signature.starts_with("sha256="));
+    assert_eq!(signature.len(), "sha256=".len() + 64);
+
+    let mocked = mock("POST", "/events")
+        .match_header("content-type", "application/json")
+        .match_header("X-Shipcat-Signature", signature.as_str())
+        .match_body(body)
+        .expect(1)
+        .create();
+
+    delivery::send(&event, &hook("/events")).unwrap();
+    mocked.assert();
+}
+
+#[test]
+fn delivery_spools_and_flushes() {
+    setup();
+    let spool = env::temp_dir().join("shipcat-delivery-test");
+    let _ = fs::remove_dir_all(&spool);
+    env::set_var("SHIPCAT_SPOOL_DIR", &spool);
+    let event = FakeEvent { service: "fake-storage".into(), status: "FAILED".into() };
+    let mut h = hook("/flaky");
+    h.headers.insert("X-Api-Key".into(), "hunter2".into());
+
+    {
+        let _down = mock("POST", "/flaky").with_status(503).create();
+        assert!(delivery::send(&event, &h).is_err());
+    }
+    // spooled privately and without the credential
+    assert_eq!(fs::metadata(&spool).unwrap().permissions().mode() & 0o777, 0o700);
+    let spooled = fs::read_dir(&spool).unwrap().map(|e| e.unwrap().path()).collect::<Vec<_>>();
+    assert_eq!(spooled.len(), 1);
+    assert_eq!(fs::metadata(&spooled[0]).unwrap().permissions().mode() & 0o777, 0o600);
+    let data = fs::read_to_string(&spooled[0]).unwrap();
+    assert!(!data.contains("hunter2"));
+    assert!(data.contains("X-Shipcat-Signature"));
+
+    let (_conf, mut reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    // no webhook to take the credential from
+    reg.webhooks = None;
+    assert_eq!(delivery::flush(&reg).unwrap(), 1);
+
+    reg.webhooks = Some(vec![Webhook::Http(h)]);
+    {
+        let _down = mock("POST", "/flaky").with_status(503).create();
+        // still down
+        assert_eq!(delivery::flush(&reg).unwrap(), 1);
+    }
+
+    let up = mock("POST", "/flaky")
+        .match_header("x-api-key", "hunter2")
+        .match_body("{\"text\": \"fake-storage is FAILED\"}")
+        .with_status(200)
+        .expect(1)
+        .create();
+    assert_eq!(delivery::flush(&reg).unwrap(), 0);
+    assert_eq!(fs::read_dir(&spool).unwrap().count(), 0);
+    up.assert();
+}
@@ -0,0 +1,30 @@
+mod common;
+use crate::common::setup;
+use shipcat_definitions::{Config, ConfigType};
+use shipcat::networkpolicy::{inject, CallerGraph};
+
+#[test]
+fn networkpolicy_callers() {
+    setup();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let graph = CallerGraph::new(&conf, &reg).unwrap();
+    let cs = graph.callers("fake-storage");
+    assert_eq!(cs.len(), 1);
+    assert_eq!(cs[0].name, "fake-ask");
+    assert!(graph.callers("fake-ask").is_empty());
+}
+
+#[test]
+fn networkpolicy_inject() {
+    setup();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let mut mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg).unwrap();
+    let graph = CallerGraph::new(&conf, &reg).unwrap();
+    inject(&mut mf, &graph, &reg);
+    let np = mf.networkPolicy.unwrap();
+    assert!(np.audit); // dev-uk only audits
+    let from = &np.ingress[0].from;
+    assert_eq!(from[0].podSelector.clone().unwrap().matchLabels["app"], "fake-ask");
+    // last rule allows everything
+    assert!(np.ingress.last().unwrap().from.is_empty());
+}
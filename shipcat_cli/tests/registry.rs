@@ -0,0 +1,54 @@
+#![warn(rust_2018_idioms)]
+
+mod common;
+
+use mockito;
+use shipcat;
+
+use crate::mockito::mock;
+use crate::shipcat::registry;
+
+const DIGEST: &str = "sha256:45b23dee08af5e43a7fea6c4cf9c25ccf269ee113168c19722f87876677c5cb2";
+
+#[test]
+fn registry_fetch_digest() {
+    let mocked = mock("HEAD", "/v2/babylonhealth/fake-ask/manifests/1.6.0")
+        .match_header("accept", mockito::Matcher::Regex("manifest.v2".into()))
+        .with_status(200)
+        .with_header("Docker-Content-Digest", DIGEST)
+        .expect(1)
+        .create();
+
+    let digest = registry::fetch_digest(mockito::SERVER_URL, "babylonhealth/fake-ask", "1.6.0", None).unwrap();
+    assert_eq!(digest, DIGEST);
+    mocked.assert();
+}
+
+#[test]
+fn registry_missing_tag() {
+    let _m = mock("HEAD", "/v2/babylonhealth/fake-ask/manifests/9.9.9")
+        .with_status(404)
+        .create();
+
+    assert!(registry::fetch_digest(mockito::SERVER_URL, "babylonhealth/fake-ask", "9.9.9", None).is_err());
+    assert!(!registry::tag_exists(mockito::SERVER_URL, "babylonhealth/fake-ask", "9.9.9", None).unwrap());
+}
+
+#[test]
+fn registry_basic_auth() {
+    let challenge = mock("HEAD", "/v2/private/fake-ask/manifests/1.6.0")
+        .match_header("authorization", mockito::Matcher::Missing)
+        .with_status(401)
+        .with_header("WWW-Authenticate", "Basic realm=\"registry\"")
+        .create();
+    let authed = mock("HEAD", "/v2/private/fake-ask/manifests/1.6.0")
+        .match_header("authorization", "Basic dXNlcjpwYXNz")
+        .with_status(200)
+        .with_header("Docker-Content-Digest", DIGEST)
+        .create();
+
+    assert!(registry::tag_exists(mockito::SERVER_URL, "private/fake-ask", "1.6.0", Some("dXNlcjpwYXNz")).unwrap());
+    assert!(registry::tag_exists(mockito::SERVER_URL, "private/fake-ask", "1.6.0", None).is_err());
+    challenge.assert();
+    authed.assert();
+}
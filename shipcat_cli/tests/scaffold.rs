@@ -0,0 +1,90 @@
+mod common;
+use crate::common::setup;
+
+use std::fs;
+use std::path::Path;
+
+use shipcat::scaffold::{create, render, NewService};
+use shipcat::structs::Metadata;
+use shipcat_definitions::Config;
+
+fn new_service(language: Option<&str>) -> NewService {
+    NewService {
+        name: "fake-new".into(),
+        team: "devops".into(),
+        language: language.map(String::from),
+        kong: true,
+        regions: vec!["dev-uk".into()],
+        repo: None,
+    }
+}
+
+#[test]
+fn scaffold_java() {
+    setup();
+    let conf = Config::read().unwrap();
+    let scaffold = render(&new_service(Some("java")), &conf).unwrap();
+    assert_eq!(scaffold.files.len(), 2); // shipcat.yml + config template
+    assert_eq!(scaffold.files[0].0.to_str().unwrap(), "shipcat.yml");
+    assert_eq!(scaffold.files[1].0.to_str().unwrap(), "application.yml.j2");
+
+    let mf: serde_yaml::Value = serde_yaml::from_str(&scaffold.files[0].1).unwrap();
+    assert_eq!(mf["name"].as_str().unwrap(), "fake-new");
+    assert_eq!(mf["image"].as_str().unwrap(), "quay.io/babylonhealth/fake-new");
+    assert_eq!(mf["language"].as_str().unwrap(), "java");
+    assert_eq!(mf["kong"]["uris"].as_str().unwrap(), "/fake-new");
+    assert_eq!(mf["httpPort"].as_u64().unwrap(), 8080); // from skeleton
+
+    // team contacts and channels are valid metadata
+    let md: Metadata = serde_yaml::from_value(mf["metadata"].clone()).unwrap();
+    assert_eq!(md.repo, "https://github.com/Babylonpartners/fake-new");
+    assert_eq!(md.contacts[0].github, Some("clux".into()));
+    assert_eq!(*md.support.clone().unwrap(), "#devops-support");
+    md.verify(&conf.teams, &conf.allowedCustomMetadata).unwrap();
+}
+
+#[test]
+fn scaffold_merges_skeleton() {
+    setup();
+    let conf = Config::read().unwrap();
+    // the go skeleton also sets language and kong
+    let scaffold = render(&new_service(Some("go")), &conf).unwrap();
+    let mf: serde_yaml::Value = serde_yaml::from_str(&scaffold.files[0].1).unwrap();
+    assert_eq!(scaffold.files[0].1.matches("\nkong:").count(), 1);
+    assert_eq!(scaffold.files[0].1.matches("\nlanguage:").count(), 1);
+    assert_eq!(mf["language"].as_str().unwrap(), "go");
+    assert_eq!(mf["kong"]["uris"].as_str().unwrap(), "/fake-new"); // generated wins
+    assert_eq!(mf["kong"]["preserve_host"].as_bool().unwrap(), false); // skeleton kept
+    assert_eq!(mf["resources"]["limits"]["memory"].as_str().unwrap(), "256Mi");
+    assert_eq!(mf["metadata"]["team"].as_str().unwrap(), "devops");
+}
+
+#[test]
+fn scaffold_create_validates() {
+    setup();
+    let conf = Config::read().unwrap();
+    let mut svc = new_service(Some("java"));
+    svc.name = "fake-scaffolded".into();
+    let dir = Path::new(".").join("services").join("fake-scaffolded");
+    let res = create(svc, &conf);
+    let created = dir.join("shipcat.yml").is_file() && dir.join("application.yml.j2").is_file();
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    res.unwrap();
+    assert!(created);
+}
+
+#[test]
+fn scaffold_errors() {
+    setup();
+    let conf = Config::read().unwrap();
+    let err = render(&new_service(Some("cobol")), &conf).err().unwrap(); // no skeleton
+    assert!(err.to_string().contains("available: go, java"));
+    // languages cannot escape the skeletons folder
+    assert!(render(&new_service(Some("../../services/fake-ask")), &conf).is_err());
+    assert!(render(&new_service(Some("Java")), &conf).is_err());
+    let mut svc = new_service(None);
+    svc.team = "notateam".into();
+    assert!(render(&svc, &conf).is_err());
+}
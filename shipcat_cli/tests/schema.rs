@@ -0,0 +1,32 @@
+use shipcat::schema::{generate, SchemaKind};
+
+#[test]
+fn manifest_schema() {
+    let schema = generate(SchemaKind::Manifest).unwrap();
+    let props = &schema["properties"];
+    // top level properties
+    assert_eq!(props["regions"]["description"], "Regions to deploy this service to");
+    assert!(props["extends"].is_object());
+    // flattened overrides and defaults
+    assert!(props["readinessProbe"].is_object());
+    assert!(props["replicaCount"].is_object());
+    assert!(schema["definitions"]["Metadata"].is_object());
+}
+
+#[test]
+fn overrides_schema() {
+    let schema = generate(SchemaKind::Overrides).unwrap();
+    let props = &schema["properties"];
+    assert!(props["version"].is_object());
+    // global properties cannot be overridden
+    assert!(props["regions"].is_null());
+    assert!(props["metadata"].is_null());
+}
+
+#[test]
+fn config_schema() {
+    let schema = generate(SchemaKind::Config).unwrap();
+    assert!(schema["properties"]["regions"].is_object());
+    assert!(schema["properties"]["teams"].is_object());
+    assert!(schema["definitions"]["Region"].is_object());
+}
@@ -1,7 +1,114 @@
 mod common;
 use crate::common::setup;
+use mockito::{mock, Matcher};
 use shipcat_definitions::{Config, ConfigType};
-use shipcat::slack::{send, Message, env_channel};
+use shipcat_definitions::structs::Metadata;
+use shipcat::SlackWebhook;
+use shipcat::slack::{self, send, send_threaded, Message, Threads, env_channel};
+use shipcat::webhooks::UpgradeState;
+
+fn api_hook(prefix: &str) -> SlackWebhook {
+    SlackWebhook {
+        url: "".into(),
+        token: Some("xoxb-fake".into()),
+        apiUrl: format!("{}/{}", mockito::SERVER_URL, prefix),
+        channel: "#deploys".into(),
+        username: "shipcat".into(),
+        events: vec![UpgradeState::Completed, UpgradeState::Failed],
+        routes: vec![],
+    }
+}
+
+#[test]
+fn slack_routes_by_service_team_and_state() {
+    let mut hook = api_hook("unused");
+    hook.token = None;
+    hook.routes = vec![serde_yaml::from_str("
+channel: \"#incidents\"
+events: [FAILED, ROLLBACK_FAILED]
+").unwrap()];
+    let md : Metadata = serde_yaml::from_str("
+repo: https://github.com/Babylonpartners/shipcat
+team: devops
+notifications: \"#devops-notifications\"
+").unwrap();
+    let with_md = Message { metadata: Some(md), ..Default::default() };
+    let without_md = Message::default();
+
+    // notifications channel (already defaulted to the team's) before the global one
+    assert_eq!(slack::route(&with_md, &UpgradeState::Completed, &hook), vec!["#devops-notifications"]);
+    assert_eq!(slack::route(&without_md, &UpgradeState::Completed, &hook), vec!["#deploys"]);
+    // failures also go to the routed channel
+    assert_eq!(slack::route(&with_md, &UpgradeState::Failed, &hook),
+        vec!["#devops-notifications", "#incidents"]);
+    // only routed states that the webhook does not notify about
+    assert_eq!(slack::route(&with_md, &UpgradeState::RollbackFailed, &hook), vec!["#incidents"]);
+    assert!(slack::route(&with_md, &UpgradeState::Pending, &hook).is_empty());
+}
+
+#[test]
+fn slack_threaded_message_is_updated() {
+    let hook = api_hook("threaded");
+    let post = mock("POST", "/threaded/chat.postMessage")
+        .match_header("authorization", "Bearer xoxb-fake")
+        .match_body(Matcher::Regex("starting to upgrade".into()))
+        .with_body(r#"{"ok": true, "channel": "C1", "ts": "1550000000.000100"}"#)
+        .expect(1)
+        .create();
+    let update = mock("POST", "/threaded/chat.update")
+        .match_body(Matcher::Regex(r#""ts":"1550000000.000100""#.into()))
+        .with_body(r#"{"ok": true}"#)
+        .expect(1)
+        .create();
+
+    let threads = Threads::default();
+    send_threaded(Message {
+        text: "starting to upgrade `fake-ask` in `dev-uk`".into(),
+        ..Default::default()
+    }, &UpgradeState::Pending, &hook, &threads).unwrap();
+    send_threaded(Message {
+        text: "upgraded `fake-ask` in `dev-uk`".into(),
+        color: Some("good".into()),
+        ..Default::default()
+    }, &UpgradeState::Completed, &hook, &threads).unwrap();
+
+    post.assert();
+    update.assert();
+    assert_eq!(threads.lock().unwrap()["#deploys"], ("C1".to_string(), "1550000000.000100".to_string()));
+}
+
+#[test]
+fn slack_threaded_failure_mentions_contacts() {
+    let hook = api_hook("failing");
+    let md : Metadata = serde_yaml::from_str("
+repo: https://github.com/Babylonpartners/shipcat
+team: devops
+contacts:
+- name: Eirik
+  slack: \"@U82SKDQB\"
+").unwrap();
+    let update = mock("POST", "/failing/chat.update")
+        .with_body(r#"{"ok": true}"#)
+        .expect(1)
+        .create();
+    let reply = mock("POST", "/failing/chat.postMessage")
+        .match_body(Matcher::Regex(r#""text":"<@U82SKDQB>".*"thread_ts":"1550000000.000200""#.into()))
+        .with_body(r#"{"ok": true, "channel": "C1", "ts": "1550000000.000300"}"#)
+        .expect(1)
+        .create();
+
+    let threads = Threads::default();
+    threads.lock().unwrap().insert("#deploys".into(), ("C1".into(), "1550000000.000200".into()));
+    send_threaded(Message {
+        text: "failed to upgrade `fake-ask` in `dev-uk`".into(),
+        color: Some("danger".into()),
+        metadata: Some(md),
+        ..Default::default()
+    }, &UpgradeState::Failed, &hook, &threads).unwrap();
+
+    update.assert();
+    reply.assert();
+}
 
 // integration temporarily disabled
 #[test]
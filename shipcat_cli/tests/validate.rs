@@ -1,7 +1,9 @@
 mod common;
 use crate::common::setup;
 
-use shipcat_definitions::{Config, ConfigType};
+use shipcat_definitions::{Config, ConfigType, IngressConfig};
+use shipcat_definitions::structs::{PersistentVolume, Rbac, RollingUpdate, StatefulSet, Workload};
+use shipcat_definitions::structs::statefulset::VolumeClaimTemplate;
 use shipcat::validate::manifest as validate;
 
 #[test]
@@ -13,3 +15,95 @@ fn validate_test() {
     let res2 = validate(vec!["fake-storage".into(), "fake-ask".into()], &conf, &reg, false);
     assert!(res2.is_ok())
 }
+
+#[test]
+fn validate_statefulsets() {
+    setup();
+    let (conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg).unwrap().stub(&reg).unwrap();
+    assert!(mf.verify(&conf, &reg).is_ok());
+    // default workload is not serialized
+    assert!(!serde_yaml::to_string(&mf).unwrap().contains("workload"));
+
+    let claim = |name: &str| VolumeClaimTemplate {
+        name: name.into(),
+        mountPath: "/data".into(),
+        storageClass: None,
+        accessMode: "ReadWriteOnce".into(),
+        size: "10Gi".into(),
+    };
+    let mut ss = mf.clone();
+    ss.statefulSet = Some(StatefulSet { volumeClaimTemplates: vec![claim("data")], ..Default::default() });
+    assert!(ss.verify(&conf, &reg).is_err()); // needs workload: StatefulSet
+    ss.workload = Workload::StatefulSet;
+    assert!(ss.verify(&conf, &reg).is_ok());
+    assert!(serde_yaml::to_string(&ss).unwrap().contains("workload: StatefulSet"));
+
+    let mut clash = ss.clone();
+    clash.statefulSet.as_mut().unwrap().volumeClaimTemplates.push(claim("secrets-conf"));
+    assert!(clash.verify(&conf, &reg).is_err()); // clashes with a volume
+
+    let mut rolling = ss.clone();
+    rolling.rollingUpdate = Some(RollingUpdate::default());
+    assert!(rolling.verify(&conf, &reg).is_err());
+
+    let mut pvs = ss.clone();
+    pvs.persistentVolumes.push(PersistentVolume {
+        name: "old".into(),
+        claim: "old-claim".into(),
+        storageClass: "gp2".into(),
+        accessMode: "ReadWriteOnce".into(),
+        size: "1Gi".into(),
+    });
+    assert!(pvs.verify(&conf, &reg).is_err()); // must use volumeClaimTemplates
+}
+
+#[test]
+fn validate_rbac_policies() {
+    setup();
+    let (mut conf, reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let mut mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg).unwrap().stub(&reg).unwrap();
+    let rule = |group: &str, resource: &str, verb: &str| Rbac {
+        apiGroups: vec![group.into()],
+        resources: vec![resource.into()],
+        verbs: vec![verb.into()],
+        clusterWide: false,
+    };
+    // the dev policy in shipcat.conf
+    mf.rbac = vec![rule("batch", "jobs", "create")];
+    assert!(mf.verify(&conf, &reg).is_ok());
+    mf.rbac = vec![rule("", "jobs", "create")];
+    assert!(mf.verify(&conf, &reg).is_err()); // jobs only allowed in batch
+
+    // without an rbac section the old read only allowlist applies
+    conf.rbac.clear();
+    mf.rbac = vec![rule("extensions", "deployments", "watch")];
+    assert!(mf.verify(&conf, &reg).is_ok());
+    mf.rbac = vec![rule("batch", "jobs", "create")];
+    assert!(mf.verify(&conf, &reg).is_err());
+}
+
+#[test]
+fn validate_ingresses() {
+    setup();
+    let ingress = IngressConfig {
+        ingressClass: "nginx".into(),
+        annotations: Default::default(),
+        tlsSecretName: None,
+    };
+
+    let (conf, mut reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let mf = shipcat_filebacked::load_manifest("fake-ask", &conf, &reg).unwrap();
+    assert!(mf.ingress.is_none());
+    // kong auth has no ingress equivalent
+    reg.ingress = Some(ingress.clone());
+    assert!(shipcat_filebacked::load_manifest("fake-ask", &conf, &reg).is_err());
+
+    let (conf, mut reg) = Config::new(ConfigType::Base, "dev-ops").unwrap();
+    reg.ingress = Some(ingress);
+    let mf = shipcat_filebacked::load_manifest("out-of-region", &conf, &reg).unwrap();
+    let ing = mf.ingress.unwrap();
+    assert_eq!(ing.ingressClass, "nginx");
+    assert_eq!(ing.paths, vec!["/(.*)".to_string()]);
+    assert_eq!(ing.annotations["nginx.ingress.kubernetes.io/rewrite-target"], "/$1");
+}
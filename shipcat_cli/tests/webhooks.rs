@@ -24,3 +24,18 @@ fn webhooks_ensure_requirements() {
     env::remove_var("SHIPCAT_AUDIT_REVISION");
     assert!(webhooks::ensure_requirements(&reg).is_err());
 }
+
+#[test]
+fn webhooks_unused_legacy_env() {
+    common::setup();
+
+    let (_conf, mut reg) = Config::new(ConfigType::Base, "dev-uk").unwrap();
+    let vars = vec!["SLACK_SHIPCAT_HOOK_URL".to_string(), "GRAFANA_SHIPCAT_TOKEN".to_string()];
+    // dev-uk has slack and grafana webhooks
+    assert!(webhooks::unused_legacy_env(&reg, &vars).is_empty());
+
+    reg.webhooks = None;
+    let unused = webhooks::unused_legacy_env(&reg, &vars);
+    assert_eq!(unused, vec![("SLACK_SHIPCAT_", "slack"), ("GRAFANA_SHIPCAT_", "grafana")]);
+    assert!(webhooks::unused_legacy_env(&reg, &["PATH".to_string()]).is_empty());
+}
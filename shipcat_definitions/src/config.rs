@@ -11,14 +11,14 @@ use crate::structs::SlackChannel;
 
 #[allow(unused_imports)]
 use super::{Result, Error};
-use super::structs::{Contact};
+use super::structs::{Contact, RbacPolicy};
 use crate::states::ConfigType;
 use crate::region::{Region, Environment};
 
 // ----------------------------------------------------------------------------------
 
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct ManifestDefaults {
     /// Image prefix string
@@ -43,7 +43,7 @@ impl Default for ManifestDefaults {
 
 
 /// Kubernetes cluster information
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Cluster {
     /// Name of the cluster
@@ -54,7 +54,7 @@ pub struct Cluster {
     pub regions: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Team {
     /// Team name
@@ -109,7 +109,7 @@ impl Team {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Location {
     /// Location name
@@ -122,7 +122,7 @@ pub struct Location {
     pub local_region: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct GithubParameters {
     /// Location name
@@ -130,7 +130,7 @@ pub struct GithubParameters {
 }
 
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct SlackParameters {
     /// Location name
@@ -142,7 +142,7 @@ pub struct SlackParameters {
 
 
 /// Main manifest, serializable from shipcat.yml
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Config {
     /// Global defaults for the manifests
@@ -178,6 +178,13 @@ pub struct Config {
     #[serde(default)]
     pub allowedCustomMetadata: BTreeSet<String>,
 
+    /// RBAC allowlist per environment
+    ///
+    /// Services can only request `rbac` rules allowed for their region's environment.
+    /// Environments without a policy get the allowlist from before policies were configurable.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rbac: BTreeMap<Environment, RbacPolicy>,
+
     /// Deprecated shipcat version pin
     ///
     /// TODO: make this an output property once it's not serialized
@@ -185,6 +192,7 @@ pub struct Config {
     version: String,
 
     /// Shipcat version pins
+    #[schemars(with = "BTreeMap<Environment, String>")]
     pub versions: BTreeMap<Environment, Version>,
 
     // Internal state of the config
@@ -242,6 +250,10 @@ impl Config {
                 bail!("Region {} served by missing cluster '{}'", r.name, r.cluster);
             }
             r.vault.verify(&r.name)?;
+            r.versioningScheme.verify_scheme()?;
+            if let Some(dp) = &r.deployPolicy {
+                dp.verify(&r.name)?;
+            }
             for v in r.base_urls.values() {
                 if v.ends_with('/') {
                     bail!("A base_url must not end with a slash");
@@ -402,8 +414,14 @@ impl Config {
     ///
     /// Pass this a region request via argument or a current context
     pub fn new(kind: ConfigType, context: &str) -> Result<(Config, Region)> {
-        let mut conf = Config::read()?;
-        let region = if let Some(r) = conf.resolve_context(context.to_string()) {
+        Config::read()?.filtered(kind, context)
+    }
+
+    /// Filter a raw config down to a region
+    ///
+    /// Used by `new`, and for raw configs read from elsewhere than the working tree.
+    pub fn filtered(mut self, kind: ConfigType, context: &str) -> Result<(Config, Region)> {
+        let region = if let Some(r) = self.resolve_context(context.to_string()) {
             r
         } else {
             error!("Please use an existing kube context or add your current context to shipcat.conf");
@@ -411,23 +429,22 @@ impl Config {
         };
 
         if kind == ConfigType::Filtered || kind == ConfigType::Base {
-            conf.remove_redundant_regions(&region)?;
+            self.remove_redundant_regions(&region)?;
         } else if kind != ConfigType::UnionisedBase {
             bail!("Config::new only supports Filtered, Base and UnionisedBase types");
         }
 
         if kind == ConfigType::Filtered {
-            conf.secrets(&region)?;
+            self.secrets(&region)?;
         }
-        let reg = conf.get_region(&region)?;
-        Ok((conf, reg))
+        let reg = self.get_region(&region)?;
+        Ok((self, reg))
     }
 
     /// Read a config file in an arbitrary path
     fn read_from(pwd: &PathBuf) -> Result<Config> {
         use std::fs::File;
         use std::io::prelude::*;
-        use semver::Version;
         let mpath = pwd.join("shipcat.conf");
         trace!("Using config in {}", mpath.display());
         if !mpath.exists() {
@@ -436,7 +453,15 @@ impl Config {
         let mut f = File::open(&mpath)?;
         let mut data = String::new();
         f.read_to_string(&mut data)?;
-        let res = serde_yaml::from_str(&data);
+        Config::from_yaml(&data)
+    }
+
+    /// Parse a raw config from its yaml
+    ///
+    /// Gives hints when the config fails to parse due to an outdated version pin.
+    pub fn from_yaml(data: &str) -> Result<Config> {
+        use semver::Version;
+        let res = serde_yaml::from_str(data);
         match res {
             Err(e) => {
                 // failed to parse the config common causes:
@@ -2,6 +2,9 @@ use std::fmt;
 use std::marker::PhantomData;
 use serde::de::{Visitor, Deserialize, Deserializer, Error, SeqAccess};
 use serde::de::value::{SeqAccessDeserializer};
+use schemars::JsonSchema;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{Schema, SchemaObject, SubschemaValidation};
 
 #[derive(Deserialize, Clone, Default)]
 pub struct CommaSeparatedString(
@@ -9,6 +12,23 @@ pub struct CommaSeparatedString(
     Vec<String>
 );
 
+/// Schema matching what `comma_separated_string` accepts
+impl JsonSchema for CommaSeparatedString {
+    fn schema_name() -> String {
+        "CommaSeparatedString".into()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![gen.subschema_for::<String>(), gen.subschema_for::<Vec<String>>()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }.into()
+    }
+}
+
 impl Into<Vec<String>> for CommaSeparatedString {
     fn into(self) -> Vec<String> {
         self.0
@@ -5,6 +5,7 @@
 
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate log;
+#[macro_use] extern crate schemars;
 
 /// The backing for manifests must come from the filesystem or the CRD
 /// This assert enforce that users of this library choses a feature.
@@ -78,9 +79,21 @@ pub use crate::region::{
     Region,
     VaultConfig,
     VersionScheme,
+    VersionPolicy,
+    DeployPolicy,
+    FreezeWindow,
+    ApprovalPolicy,
     KongConfig,
+    IngressConfig,
     Environment,
     ReconciliationMode,
+    HelmVersion,
+    NetworkPolicyConfig,
+    NetworkPolicyMode,
+    ServiceMeshConfig,
+    PodSecurityConfig,
+    ImageDigestConfig,
+    RegistryConfig,
 };
 /// Master config with cross-region data
 pub mod config;
@@ -3,7 +3,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use regex::Regex;
 
 use crate::config::{Config};
-use crate::region::{VaultConfig, Region};
+use crate::region::{VaultConfig, Region, PodSecurityConfig};
 use crate::states::ManifestType;
 use super::Result;
 
@@ -13,11 +13,16 @@ use super::structs::{
     Container, ResourceRequirements, HostAlias,
     volume::{Volume, VolumeMount},
     PersistentVolume,
+    {StatefulSet, Workload},
+    NetworkPolicy,
+    Ingress,
+    {Mesh, ServiceMesh},
+    {SecurityContext, PodSecurityContext, SecurityExemption, SecurityRule},
     {Metadata, VaultOpts, Dependency},
     security::DataHandling,
     Probe,
     CronJob, Job, EnvVars,
-    {Gate, Kafka, Kong, Rbac},
+    {Gate, Kafka, Kong, Rbac, RbacPolicy},
     RollingUpdate,
     autoscaling::AutoScaling,
     tolerations::Tolerations,
@@ -373,6 +378,21 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub dependencies: Vec<Dependency>,
 
+    /// Service mesh settings
+    ///
+    /// Timeouts, retries and mutual TLS for requests to this service.
+    /// Only used in regions with `serviceMesh` enabled.
+    ///
+    /// ```yaml
+    /// mesh:
+    ///   timeout: 10s
+    ///   retries:
+    ///     attempts: 3
+    ///     retryOn: 5xx
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<Mesh>,
+
     /// Worker `Deployment` objects to additinally include
     ///
     /// These are more flexible than `sidecars`, because they scale independently of
@@ -577,6 +597,33 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub persistentVolumes: Vec<PersistentVolume>,
 
+    /// Kubernetes workload kind for the main container
+    ///
+    /// Defaults to a `Deployment`. A `StatefulSet` gives pods stable identities,
+    /// per-pod volumes via `statefulSet`, and a headless `Service`.
+    ///
+    /// ```yaml
+    /// workload: StatefulSet
+    /// ```
+    #[serde(default, skip_serializing_if = "Workload::is_default")]
+    pub workload: Workload,
+
+    /// StatefulSet parameters
+    ///
+    /// Requires `workload: StatefulSet`.
+    ///
+    /// ```yaml
+    /// statefulSet:
+    ///   podManagementPolicy: Parallel
+    ///   volumeClaimTemplates:
+    ///   - name: data
+    ///     mountPath: /var/lib/data
+    ///     storageClass: gp2
+    ///     size: 10Gi
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statefulSet: Option<StatefulSet>,
+
     /// Cronjob images to run as kubernetes `CronJob` objects
     ///
     /// Limited usefulness abstraction, that should be avoided.
@@ -683,15 +730,57 @@ pub struct Manifest {
     /// A list of resources to allow the service access to use.
     /// This is a subset of kubernetes `Role::rules` parameters.
     ///
+    /// Rules are checked against the environment's `rbac` policy in `shipcat.conf`.
+    ///
     /// ```yaml
     /// rbac:
     /// - apiGroups: ["extensions"]
     ///   resources: ["deployments"]
     ///   verbs: ["get", "watch", "list"]
+    /// - apiGroups: [""]
+    ///   resources: ["nodes"]
+    ///   verbs: ["get"]
+    ///   clusterWide: true
     /// ```
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub rbac: Vec<Rbac>,
 
+    /// Security settings for the main container
+    ///
+    /// Defaults to the region's `podSecurity.securityContext`.
+    ///
+    /// ```yaml
+    /// securityContext:
+    ///   runAsNonRoot: true
+    ///   readOnlyRootFilesystem: true
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub securityContext: Option<SecurityContext>,
+
+    /// Security settings for all containers in the pods
+    ///
+    /// Defaults to the region's `podSecurity.podSecurityContext`.
+    ///
+    /// ```yaml
+    /// podSecurityContext:
+    ///   runAsUser: 1000
+    ///   fsGroup: 1000
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podSecurityContext: Option<PodSecurityContext>,
+
+    /// Exemptions from the region's pod security rules
+    ///
+    /// Every exemption needs a reason for reviewers.
+    ///
+    /// ```yaml
+    /// securityExemptions:
+    /// - rule: HostPath
+    ///   reason: "Node exporter reads /proc from the host"
+    /// ```
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub securityExemptions: Vec<SecurityExemption>,
+
     // ------------------------------------------------------------------------
     // Output variables
     //
@@ -731,6 +820,39 @@ pub struct Manifest {
     #[serde(default, skip_deserializing, skip_serializing_if = "BTreeMap::is_empty")]
     pub secrets: BTreeMap<String, String>,
 
+    /// Generated ingress network policy
+    ///
+    /// Computed from the dependency graph in regions with `networkPolicies` enabled.
+    /// This is an internal property that is exposed as an output only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "filesystem", serde(skip_deserializing))]
+    pub networkPolicy: Option<NetworkPolicy>,
+
+    /// Generated ingress
+    ///
+    /// Translated from `kong` in regions that use ingresses instead of kong.
+    /// This is an internal property that is exposed as an output only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "filesystem", serde(skip_deserializing))]
+    pub ingress: Option<Ingress>,
+
+    /// Generated istio objects
+    ///
+    /// Computed from `mesh`, dependencies and kong timeouts in regions with `serviceMesh` enabled.
+    /// This is an internal property that is exposed as an output only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "filesystem", serde(skip_deserializing))]
+    pub serviceMesh: Option<ServiceMesh>,
+
+    /// Resolved digest of `image:version`
+    ///
+    /// Looked up in the registry at deploy time in regions with `imageDigests` set,
+    /// and used instead of the tag in the pod spec.
+    /// This is an internal property that is exposed as an output only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "filesystem", serde(skip_deserializing))]
+    pub imageDigest: Option<String>,
+
     /// Internal kind of the manifest
     ///
     /// A manifest goes through different stages of serialization, templating,
@@ -793,6 +915,9 @@ impl Manifest {
 
         // TODO [DIP-499]: Separate gate/kong params + adjust the checks
         if let Some(g) = &self.gate {
+            if region.ingress.is_some() {
+                bail!("Can't have a `gate` configuration in {} which uses ingresses", region.name);
+            }
             if self.kong.is_none() {
                 bail!("Can't have a `gate` configuration without a `kong` one");
             }
@@ -813,6 +938,9 @@ impl Manifest {
         for d in &self.dependencies {
             d.verify()?;
         }
+        if let Some(m) = &self.mesh {
+            m.verify()?;
+        }
         for ha in &self.hostAliases {
             ha.verify()?;
         }
@@ -822,19 +950,54 @@ impl Manifest {
         for p in &self.ports {
             p.verify()?;
         }
-        for r in &self.rbac {
-            r.verify()?;
+        if !self.rbac.is_empty() {
+            let policy = conf.rbac.get(&region.environment).cloned()
+                .unwrap_or_else(RbacPolicy::legacy);
+            for r in &self.rbac {
+                r.verify(&policy)?;
+            }
         }
         for pv in &self.persistentVolumes {
             pv.verify()?;
         }
+        if let Some(ss) = &self.statefulSet {
+            if self.workload != Workload::StatefulSet {
+                bail!("`statefulSet` requires `workload: StatefulSet`");
+            }
+            ss.verify()?;
+            for vct in &ss.volumeClaimTemplates {
+                if self.volumes.iter().any(|v| v.name == vct.name) {
+                    bail!("Volume claim template {} clashes with a volume of the same name", vct.name);
+                }
+            }
+        }
+        if self.workload == Workload::StatefulSet {
+            if !self.persistentVolumes.is_empty() {
+                bail!("StatefulSets must use `statefulSet.volumeClaimTemplates` instead of `persistentVolumes`");
+            }
+            if self.rollingUpdate.is_some() {
+                bail!("StatefulSets roll out one pod at a time and do not support `rollingUpdate`");
+            }
+        }
         if let Some(ref cmap) = self.configs {
             cmap.verify()?;
         }
+        for e in &self.securityExemptions {
+            e.verify()?;
+        }
+        if let Some(ps) = &region.podSecurity {
+            self.verify_pod_security(ps)?;
+        }
         // misc minor properties
         if self.replicaCount.unwrap() == 0 {
             bail!("Need replicaCount to be at least 1");
         }
+        if let Some(ref hpa) = self.autoScaling {
+            hpa.verify()?;
+        }
+        for w in &self.workers {
+            w.verify()?;
+        }
         if let Some(ref ru) = &self.rollingUpdate {
             ru.verify(self.replicaCount.unwrap())?;
         }
@@ -886,6 +1049,43 @@ impl Manifest {
         Ok(())
     }
 
+    /// Enforce a region's pod security rules on every container
+    fn verify_pod_security(&self, ps: &PodSecurityConfig) -> Result<()> {
+        let exempt = |rule: SecurityRule| self.securityExemptions.iter().any(|e| e.rule == rule);
+        let pod = self.podSecurityContext.as_ref();
+
+        let mut containers = vec![(self.name.as_str(), self.securityContext.as_ref())];
+        let others = self.workers.iter().map(|w| &w.container)
+            .chain(self.sidecars.iter())
+            .chain(self.initContainers.iter())
+            .chain(self.cronJobs.iter().map(|c| &c.container))
+            .chain(self.jobs.iter().map(|j| &j.container));
+        for c in others {
+            containers.push((c.name.as_str(), c.security_context.as_ref()));
+        }
+
+        for (name, ctx) in containers {
+            if ps.requireRunAsNonRoot && !exempt(SecurityRule::RunAsNonRoot)
+                && !SecurityContext::runs_as_non_root(ctx, pod) {
+                bail!("Container {} must set runAsNonRoot or a non-root runAsUser in {}", name, self.region);
+            }
+            if ps.forbidPrivileged && !exempt(SecurityRule::Privileged)
+                && ctx.and_then(|c| c.privileged) == Some(true) {
+                bail!("Container {} cannot be privileged in {}", name, self.region);
+            }
+            if ps.requireReadOnlyRootFilesystem && !exempt(SecurityRule::ReadOnlyRootFilesystem)
+                && ctx.and_then(|c| c.readOnlyRootFilesystem) != Some(true) {
+                bail!("Container {} must set readOnlyRootFilesystem in {}", name, self.region);
+            }
+        }
+        if ps.forbidHostPath && !exempt(SecurityRule::HostPath) {
+            if let Some(v) = self.volumes.iter().find(|v| v.hostPath.is_some()) {
+                bail!("Volume {} cannot mount a host path in {}", v.name, self.region);
+            }
+        }
+        Ok(())
+    }
+
     fn get_vault_path(&self, vc: &VaultConfig) -> String {
         // some services use keys from other services
         let (svc, reg) = if let Some(ref vopts) = self.vault {
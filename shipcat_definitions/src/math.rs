@@ -1,5 +1,6 @@
 use super::structs::ResourceRequirements;
 use super::structs::rollingupdate::{RollingUpdate};
+use super::structs::Workload;
 use super::{Result, Manifest};
 
 /// Total resource usage for a Manifest
@@ -63,6 +64,10 @@ impl Manifest {
         } else {
             self.replicaCount.unwrap() // verify ensures we have one of these
         };
+        if self.workload == Workload::StatefulSet {
+            // statefulsets always replace their pods one at a time
+            return rcount;
+        }
         if let Some(ru) = self.rollingUpdate.clone() {
             ru.rollout_iterations(rcount)
         } else {
@@ -149,7 +154,7 @@ impl Manifest {
 
 #[cfg(test)]
 mod tests {
-    use crate::structs::HealthCheck;
+    use crate::structs::{HealthCheck, Workload};
     use super::{Manifest};
 
     #[test]
@@ -189,5 +194,9 @@ mod tests {
         mf.replicaCount = Some(2);
         assert_eq!(mf.estimate_wait_time(), 990); // lots of leeway here just in case
 
+        // statefulsets replace one pod at a time
+        mf.workload = Workload::StatefulSet;
+        mf.replicaCount = Some(3);
+        assert_eq!(mf.estimate_wait_time(), 2970); // (600*1.5 + 90s)*3
     }
 }
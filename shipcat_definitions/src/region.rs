@@ -10,13 +10,16 @@ use uuid::Uuid;
 #[allow(unused_imports)]
 use super::{Vault, Result, BaseManifest, ConfigType, Team};
 
-use super::structs::{Authorization};
+use super::structs::{Authorization, SlackChannel};
+use super::structs::networkpolicy::{LabelSelector, NetworkPolicyPeer};
+use super::structs::mesh::MtlsMode;
+use super::structs::{PodSecurityContext, SecurityContext};
 
 /// Versioning Scheme used in region
 ///
 /// This is valdiated strictly using `shipcat validate` when versions are found in manifests.
 /// Otherwise, it's validated on upgrade time (via `shipcat apply`) when it's passed.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub enum VersionScheme {
     /// Version must be valid semver (no leading v)
     ///
@@ -26,6 +29,19 @@ pub enum VersionScheme {
     ///
     /// This can be used for rolling environments that does not lock versions in manifests.
     GitShaOrSemver,
+    /// Version must be semver behind an alphabetic prefix, e.g. `v1.2.3` or `release-1.2.3`
+    PrefixedSemver,
+    /// Version must be a calendar version, e.g. `2019.05.1` or `19.05`
+    ///
+    /// Two digit years are taken to be in the 2000s when ordering versions.
+    Calver,
+    /// Version must match a custom regex
+    ///
+    /// ```yaml
+    /// versioningScheme:
+    ///   Regex: "^build-[0-9]+$"
+    /// ```
+    Regex(String),
 }
 
 impl Default for VersionScheme {
@@ -34,12 +50,29 @@ impl Default for VersionScheme {
     }
 }
 
+/// A version parsed far enough to be ordered
+#[derive(Debug, PartialEq, PartialOrd)]
+pub enum OrderedVersion {
+    Semver(Version),
+    Calver(Vec<u64>),
+}
+
+impl OrderedVersion {
+    /// Whether the version is a semver pre-release
+    pub fn is_prerelease(&self) -> bool {
+        match self {
+            OrderedVersion::Semver(v) => v.is_prerelease(),
+            OrderedVersion::Calver(_) => false,
+        }
+    }
+}
+
 /// Version validator
 impl VersionScheme {
     pub fn verify(&self, ver: &str) -> Result<()> {
         use regex::Regex;
         let gitre = Regex::new(r"^[0-9a-f\-]{40}$").unwrap();
-        match *self {
+        match self {
             VersionScheme::GitShaOrSemver => {
                 if !gitre.is_match(&ver) && Version::parse(&ver).is_err() {
                     bail!("Illegal tag {} (floating tags cannot be rolled back please use 40 char git sha or semver)", ver);
@@ -50,13 +83,191 @@ impl VersionScheme {
                     bail!("Version {} is not a semver version in a region using semver versions", ver);
                 }
             },
+            VersionScheme::PrefixedSemver | VersionScheme::Calver => {
+                if self.parse(ver).is_none() {
+                    bail!("Version {} does not follow the {:?} versioning scheme of the region", ver, self);
+                }
+            },
+            VersionScheme::Regex(re) => {
+                self.verify_scheme()?;
+                if !Regex::new(re).unwrap().is_match(&ver) {
+                    bail!("Version {} does not match the versioning regex {} of the region", ver, re);
+                }
+            },
+        };
+        Ok(())
+    }
+
+    /// Parse a version for ordering purposes
+    ///
+    /// Git shas and custom regexes have no order, and give `None`.
+    pub fn parse(&self, ver: &str) -> Option<OrderedVersion> {
+        use regex::Regex;
+        match self {
+            VersionScheme::Semver | VersionScheme::GitShaOrSemver => {
+                Version::parse(ver).ok().map(OrderedVersion::Semver)
+            },
+            VersionScheme::PrefixedSemver => {
+                let prefix = Regex::new(r"^[a-zA-Z][a-zA-Z_\-]*").unwrap();
+                let m = prefix.find(ver)?;
+                Version::parse(&ver[m.end()..]).ok().map(OrderedVersion::Semver)
+            },
+            VersionScheme::Calver => {
+                let calre = Regex::new(r"^([0-9]{2}|[0-9]{4})\.(0?[1-9]|1[0-2])(\.[0-9]+){0,2}$").unwrap();
+                if !calre.is_match(ver) {
+                    return None;
+                }
+                let mut parts = ver.split('.').map(|p| p.parse().ok()).collect::<Option<Vec<u64>>>()?;
+                // so that 19.05 and 2019.05 order the same
+                if parts[0] < 100 {
+                    parts[0] += 2000;
+                }
+                Some(OrderedVersion::Calver(parts))
+            },
+            VersionScheme::Regex(_) => None,
+        }
+    }
+
+    /// Sanity check the scheme itself
+    pub fn verify_scheme(&self) -> Result<()> {
+        if let VersionScheme::Regex(re) = self {
+            if let Err(e) = regex::Regex::new(re) {
+                bail!("Invalid versioning regex {}: {}", re, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rules for which versions can replace the running version
+///
+/// Checked on upgrades unless `--allow-downgrade` is passed.
+///
+/// ```yaml
+/// versionPolicy:
+///   forbidDowngrades: true
+///   forbidPrereleases: true
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct VersionPolicy {
+    /// Refuse versions older than the running version
+    #[serde(default)]
+    pub forbidDowngrades: bool,
+    /// Refuse pre-release versions like `1.2.0-rc.1`
+    #[serde(default)]
+    pub forbidPrereleases: bool,
+}
+
+impl VersionPolicy {
+    /// Check a new version against the running version (if any)
+    ///
+    /// Versions that the scheme cannot order (git shas, custom regexes) always pass.
+    pub fn verify(&self, scheme: &VersionScheme, running: Option<&str>, new: &str) -> Result<()> {
+        let newver = match scheme.parse(new) {
+            Some(v) => v,
+            None => return Ok(()),
         };
+        if self.forbidPrereleases && newver.is_prerelease() {
+            bail!("Pre-release version {} is not allowed in this region", new);
+        }
+        if self.forbidDowngrades {
+            if let Some(oldver) = running.and_then(|r| scheme.parse(r)) {
+                if newver < oldver {
+                    bail!("Refusing to downgrade from {} to {} (pass --allow-downgrade or set SHIPCAT_ALLOW_DOWNGRADE to override)", running.unwrap(), new);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deploy freeze windows and change approval rules
+///
+/// Checked by `shipcat apply`, `shipcat helm upgrade` and cluster reconciles.
+///
+/// ```yaml
+/// deployPolicy:
+///   freezes:
+///   - name: christmas
+///     start: "2019-12-20T17:00:00Z"
+///     end: "2020-01-02T09:00:00Z"
+///   - name: weekends
+///     cron: "* * * * 0,6"
+///   allowEmergency: true
+///   approval:
+///     pattern: "^CHG-[0-9]+$"
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct DeployPolicy {
+    /// Periods where upgrades are blocked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub freezes: Vec<FreezeWindow>,
+    /// Allow upgrades during freezes when an emergency reason is given
+    #[serde(default)]
+    pub allowEmergency: bool,
+    /// Change approval references required on upgrades
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<ApprovalPolicy>,
+}
+
+/// A dated or recurring period where upgrades are blocked
+///
+/// Either `start` and `end`, or `cron` must be set.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct FreezeWindow {
+    /// Name shown when upgrades are blocked
+    pub name: String,
+    /// Start of a dated freeze (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    /// End of a dated freeze (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+    /// Five field cron expression matching every minute (in UTC) of a recurring freeze
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+}
+
+/// Approval tokens or ticket references needed to upgrade
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ApprovalPolicy {
+    /// Regex the approval must match (eg `^CHG-[0-9]+$`)
+    pub pattern: String,
+    /// Require an approval on every upgrade rather than only on emergency overrides
+    #[serde(default)]
+    pub always: bool,
+}
+
+impl DeployPolicy {
+    /// Structural checks of the policy
+    ///
+    /// Times and cron expressions are parsed by the cli when verifying the config.
+    pub fn verify(&self, region: &str) -> Result<()> {
+        use regex::Regex;
+        for w in &self.freezes {
+            if w.name.is_empty() {
+                bail!("Freeze windows in {} need a name", region);
+            }
+            match (&w.start, &w.end, &w.cron) {
+                (Some(_), Some(_), None) | (None, None, Some(_)) => {}
+                _ => bail!("Freeze window {} in {} needs either start and end, or cron", w.name, region),
+            }
+        }
+        if let Some(a) = &self.approval {
+            if let Err(e) = Regex::new(&a.pattern) {
+                bail!("Invalid approval pattern in {}: {}", region, e);
+            }
+        }
         Ok(())
     }
 }
 
 /// Vault configuration for a region
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(test, derive(Default))]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct VaultConfig {
@@ -108,7 +319,7 @@ impl VaultConfig {
 //}
 
 /// Kafka configuration for a region
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KafkaConfig {
     /// Broker urls in "hostname:port" format.
@@ -122,26 +333,220 @@ pub struct KafkaConfig {
 }
 
 /// Webhook types that shipcat might trigger after actions
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[serde(tag = "name", deny_unknown_fields, rename_all = "snake_case")]
 pub enum Webhook {
     /// Audit webhook details
     Audit(AuditWebhook),
+    /// Slack notifications
+    Slack(SlackWebhook),
+    /// Grafana annotations
+    Grafana(GrafanaWebhook),
+    /// Generic signed http sink
+    Http(HttpWebhook),
+    /// Append-only local audit log
+    AuditLog(AuditLogWebhook),
+}
+
+/// The different states an upgrade can be in
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UpgradeState {
+    /// Before action
+    Pending,
+    /// No errors
+    Completed,
+    /// Errors
+    Failed,
+    // Before revert
+    RollingBack,
+    // After revert
+    RolledBack,
+    // Fail to revert
+    RollbackFailed,
 }
 
 /// Where / how to send audited events
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct AuditWebhook {
     /// Endpoint
     #[serde(with = "url_serde")]
+    #[schemars(with = "String")]
     pub url: Url,
     /// Credential
     pub token: String,
+    /// Retries before the event is spooled
+    #[serde(default = "http_retries")]
+    pub retries: u32,
+}
+
+/// Where / how to notify slack about upgrades
+///
+/// Uses an incoming webhook `url` by default. Setting an api `token` instead posts
+/// one message per upgrade through the Slack Web API, which is edited as the upgrade
+/// progresses, with diffs and failure mentions in its thread.
+///
+/// Messages go to the service's notifications channel, falling back to its team's,
+/// and then to `channel`. `routes` send some states to extra channels in this region.
+///
+/// ```yaml
+/// webhooks:
+/// - name: slack
+///   url: IN_VAULT
+///   channel: "#deploys"
+///   events: [COMPLETED, FAILED]
+///   routes:
+///   - channel: "#incidents"
+///     events: [FAILED, ROLLBACK_FAILED]
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct SlackWebhook {
+    /// Incoming webhook url
+    ///
+    /// Read from `{region}/shipcat/WEBHOOK_SLACK_URL` in vault when set to `IN_VAULT`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub url: String,
+    /// Bot token for the Slack Web API
+    ///
+    /// Read from `{region}/shipcat/WEBHOOK_SLACK_TOKEN` in vault when set to `IN_VAULT`.
+    /// The message that starts a thread is always posted on `PENDING`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Slack Web API base url
+    #[serde(default = "slack_api_url")]
+    pub apiUrl: String,
+    /// Channel to post in
+    pub channel: String,
+    /// Name to post as
+    #[serde(default = "slack_username")]
+    pub username: String,
+    /// Upgrade states to notify about
+    #[serde(default = "slack_events")]
+    pub events: Vec<UpgradeState>,
+    /// Extra channels for some upgrade states
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<SlackRoute>,
+}
+
+/// An extra slack channel for some upgrade states
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct SlackRoute {
+    /// Channel to also post in
+    pub channel: SlackChannel,
+    /// Upgrade states to post there
+    pub events: Vec<UpgradeState>,
+}
+
+pub fn slack_api_url() -> String {
+    "https://slack.com/api".into()
+}
+fn slack_username() -> String {
+    "shipcat".into()
+}
+fn slack_events() -> Vec<UpgradeState> {
+    vec![UpgradeState::Completed, UpgradeState::Failed, UpgradeState::RolledBack, UpgradeState::RollbackFailed]
+}
+
+/// Where / how to annotate grafana dashboards with upgrades
+///
+/// ```yaml
+/// webhooks:
+/// - name: grafana
+///   url: https://grafana.example.com
+///   token: IN_VAULT
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct GrafanaWebhook {
+    /// Grafana base url
+    #[serde(with = "url_serde")]
+    #[schemars(with = "String")]
+    pub url: Url,
+    /// API token
+    ///
+    /// Read from `{region}/shipcat/WEBHOOK_GRAFANA_TOKEN` in vault when set to `IN_VAULT`.
+    pub token: String,
+    /// Upgrade states to annotate
+    #[serde(default = "grafana_events")]
+    pub events: Vec<UpgradeState>,
+}
+
+fn grafana_events() -> Vec<UpgradeState> {
+    vec![UpgradeState::Completed, UpgradeState::Failed, UpgradeState::RolledBack]
+}
+
+/// Where / how to post events to a generic http endpoint
+///
+/// Events are posted as the same json as the audit webhook, unless a `template` is given.
+/// Failed deliveries are retried with backoff, then spooled to disk for `shipcat webhooks flush`.
+///
+/// ```yaml
+/// webhooks:
+/// - name: http
+///   url: https://deploys.example.com/events
+///   secret: IN_VAULT
+///   template: |
+///     {"text": "{{ event.payload.service }} {{ event.status }} in {{ event.payload.region }}"}
+///   retries: 5
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct HttpWebhook {
+    /// Endpoint
+    #[serde(with = "url_serde")]
+    #[schemars(with = "String")]
+    pub url: Url,
+    /// Tera template for the request body, rendered with the `event`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Secret for the `X-Shipcat-Signature` HMAC-SHA256 header
+    ///
+    /// Read from `{region}/shipcat/WEBHOOK_HTTP_SECRET` in vault when set to `IN_VAULT`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// Extra headers to send
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Retries before the event is spooled
+    #[serde(default = "http_retries")]
+    pub retries: u32,
+    /// Upgrade states to post (all when empty)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<UpgradeState>,
+}
+
+fn http_retries() -> u32 {
+    3
+}
+
+/// Where to append audit events as json lines
+///
+/// Every upgrade state transition is written as one line, in the same format as the audit webhook.
+/// This is what `shipcat history` reads from. Exactly one of `path` or `configMap` must be set.
+///
+/// ```yaml
+/// webhooks:
+/// - name: audit_log
+///   configMap: shipcat-audit-log
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct AuditLogWebhook {
+    /// Local file to append to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// ConfigMap in the region's namespace to append to
+    ///
+    /// Note that ConfigMaps are limited to 1MB, so this is best for short retention.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub configMap: Option<String>,
 }
 
 /// Configure how CRs will be deployed on a region
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct CRSettings {
     #[serde(rename = "config")]
@@ -150,8 +555,136 @@ pub struct CRSettings {
 
 // ----------------------------------------------------------------------------------
 
+/// How generated network policies are deployed in a region
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub enum NetworkPolicyMode {
+    /// Deploy policies with an extra allow-all rule so nothing is blocked
+    ///
+    /// Useful to review generated policies in the cluster before enforcing them.
+    Audit,
+    /// Deploy policies that only allow the generated rules
+    Enforce,
+}
+
+/// Network policy generation for a region
+///
+/// Services get an ingress `NetworkPolicy` allowing traffic from services that
+/// declare them as an http/grpc dependency, from kong when exposed through kong,
+/// and from their `sourceRanges`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NetworkPolicyConfig {
+    /// Whether policies are enforced or deployed for auditing only
+    pub mode: NetworkPolicyMode,
+    /// Selector for the namespace kong/gate proxies run in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxyNamespace: Option<LabelSelector>,
+    /// Selector for kong/gate proxy pods
+    pub proxyPods: LabelSelector,
+    /// Sources always allowed to reach every service, e.g. monitoring
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alwaysAllow: Vec<NetworkPolicyPeer>,
+}
+
+// ----------------------------------------------------------------------------------
+
+/// Pod security defaults and rules for a region
+///
+/// Rules are enforced on every container in a service when validating,
+/// unless the service lists a `securityExemptions` entry for the rule.
+///
+/// ```yaml
+/// podSecurity:
+///   requireRunAsNonRoot: true
+///   forbidPrivileged: true
+///   forbidHostPath: true
+///   requireReadOnlyRootFilesystem: true
+///   podSecurityContext:
+///     runAsNonRoot: true
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PodSecurityConfig {
+    /// Containers must not run as root
+    #[serde(default)]
+    pub requireRunAsNonRoot: bool,
+    /// Containers must not be privileged
+    #[serde(default)]
+    pub forbidPrivileged: bool,
+    /// Pods must not mount host paths
+    #[serde(default)]
+    pub forbidHostPath: bool,
+    /// Containers must have a read only root filesystem
+    #[serde(default)]
+    pub requireReadOnlyRootFilesystem: bool,
+    /// Default `podSecurityContext` for services that do not set one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podSecurityContext: Option<PodSecurityContext>,
+    /// Default `securityContext` for containers that do not set one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub securityContext: Option<SecurityContext>,
+}
+
+// ----------------------------------------------------------------------------------
+
+/// Service mesh generation for a region
+///
+/// Services get an istio `VirtualService`, `DestinationRule` and `PeerAuthentication`
+/// built from their `mesh` settings, dependencies, `rollingUpdate` and kong timeouts.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ServiceMeshConfig {
+    /// Default mutual TLS mode for services
+    #[serde(default)]
+    pub mtls: MtlsMode,
+}
+
+// ----------------------------------------------------------------------------------
+
+/// Docker registry access for a region
+///
+/// Registries are reached through the v2 api, authenticating with the
+/// credentials in the docker config (`~/.docker/config.json` or `$DOCKER_CONFIG`).
+///
+/// ```yaml
+/// registry:
+///   checkImages: true
+///   insecureRegistries: ["localhost:5000"]
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct RegistryConfig {
+    /// Check that all images exist before upgrading and in `shipcat validate`
+    #[serde(default)]
+    pub checkImages: bool,
+    /// Registries that are only reachable over plain http
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub insecureRegistries: Vec<String>,
+}
+
+/// Image digest pinning for a region
+///
+/// Image tags are resolved to a `sha256` digest through the registry v2 api at deploy time,
+/// so a tag that is moved after `shipcat validate` cannot change what gets deployed.
+///
+/// ```yaml
+/// imageDigests:
+///   required: true
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ImageDigestConfig {
+    /// Fail deploys when the digest cannot be resolved
+    ///
+    /// Without this, deploys fall back to the tag with a warning.
+    #[serde(default)]
+    pub required: bool,
+}
+
+// ----------------------------------------------------------------------------------
+
 /// Kong configuration for a region
-#[derive(Serialize, Deserialize, Clone, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongConfig {
     /// Base URL to use (e.g. uk.dev.babylontech.co.uk)
@@ -175,8 +708,26 @@ pub struct KongConfig {
     pub extra_apis: BTreeMap<String, Kong>,
 }
 
+/// Ingress configuration for a region
+///
+/// Annotations generated from kong settings assume the nginx ingress controller.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct IngressConfig {
+    /// Ingress class handling the generated ingresses (e.g. nginx)
+    pub ingressClass: String,
+    /// Extra annotations for every ingress, e.g. for cert-manager
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Secret with the TLS certificate for the ingress hosts
+    ///
+    /// Ingresses are plain http if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tlsSecretName: Option<String>,
+}
+
 /// StatusCake configuration for a region
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct StatuscakeConfig {
     /// Contact Group that will be used if tests go down
@@ -188,7 +739,7 @@ pub struct StatuscakeConfig {
 }
 
 /// Logz.io configuration for a region
-#[derive(Serialize, Deserialize, Clone, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct LogzIoConfig {
     /// Base URL to use (e.g. https://app-eu.logz.io/#/dashboard/kibana/dashboard)
@@ -198,7 +749,7 @@ pub struct LogzIoConfig {
 }
 
 /// Grafana details for a region
-#[derive(Serialize, Deserialize, Clone, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct GrafanaConfig {
     /// Base URL to use (e.g. https://dev-grafana.ops.babylontech.co.uk)
@@ -208,20 +759,20 @@ pub struct GrafanaConfig {
 }
 
 /// Sentry details for a region
-#[derive(Serialize, Deserialize, Clone, Default)] // TODO: better Default impl
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct SentryConfig {
     /// Base URL to use (e.g. https://dev-uk-sentry.ops.babylontech.co.uk)
     pub url: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongAnonymousConsumers {
     pub anonymous: BTreeMap<String, String>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongOauthConsumer {
     pub oauth_client_id: String,
@@ -229,7 +780,7 @@ pub struct KongOauthConsumer {
     pub username: String
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongJwtConsumer {
     pub kid: String,
@@ -279,7 +830,7 @@ impl KongConfig {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongTcpLogConfig {
     pub enabled: bool,
@@ -295,14 +846,14 @@ impl KongConfig {
 
 /// Defaults for services in this region
 // TODO: This should be ManifestDefaults from shipcat_filebacked
-#[derive(Deserialize, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Clone, Default)]
 #[serde(default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DefaultConfig {
     pub kong: DefaultKongConfig,
 }
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Clone, Default)]
 #[serde(default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct DefaultKongConfig {
@@ -320,6 +871,29 @@ impl Webhook {
                     h.token = vault.read(&vkey)?;
                 }
             }
+            Webhook::Slack(h) => {
+                if h.url == "IN_VAULT" {
+                    let vkey = format!("{}/shipcat/WEBHOOK_SLACK_URL", region);
+                    h.url = vault.read(&vkey)?;
+                }
+                if h.token.as_ref().map(String::as_str) == Some("IN_VAULT") {
+                    let vkey = format!("{}/shipcat/WEBHOOK_SLACK_TOKEN", region);
+                    h.token = Some(vault.read(&vkey)?);
+                }
+            }
+            Webhook::Grafana(h) => {
+                if h.token == "IN_VAULT" {
+                    let vkey = format!("{}/shipcat/WEBHOOK_GRAFANA_TOKEN", region);
+                    h.token = vault.read(&vkey)?;
+                }
+            }
+            Webhook::Http(h) => {
+                if h.secret.as_ref().map(String::as_str) == Some("IN_VAULT") {
+                    let vkey = format!("{}/shipcat/WEBHOOK_HTTP_SECRET", region);
+                    h.secret = Some(vault.read(&vkey)?);
+                }
+            }
+            Webhook::AuditLog(_) => {}
         }
         Ok(())
     }
@@ -330,6 +904,25 @@ impl Webhook {
                 let vkey = format!("{}/shipcat/WEBHOOK_AUDIT_TOKEN", region);
                 vault.read(&vkey)?;
             }
+            Webhook::Slack(h) => {
+                if h.url == "IN_VAULT" {
+                    vault.read(&format!("{}/shipcat/WEBHOOK_SLACK_URL", region))?;
+                }
+                if h.token.as_ref().map(String::as_str) == Some("IN_VAULT") {
+                    vault.read(&format!("{}/shipcat/WEBHOOK_SLACK_TOKEN", region))?;
+                }
+            }
+            Webhook::Grafana(h) => {
+                if h.token == "IN_VAULT" {
+                    vault.read(&format!("{}/shipcat/WEBHOOK_GRAFANA_TOKEN", region))?;
+                }
+            }
+            Webhook::Http(h) => {
+                if h.secret.as_ref().map(String::as_str) == Some("IN_VAULT") {
+                    vault.read(&format!("{}/shipcat/WEBHOOK_HTTP_SECRET", region))?;
+                }
+            }
+            Webhook::AuditLog(_) => {}
         }
         // TODO: when more secrets, build up a list and do a LIST on shipcat folder
         Ok(())
@@ -338,7 +931,8 @@ impl Webhook {
     pub fn get_configuration(&self) -> Result<BTreeMap<String, String>> {
         let mut whc = BTreeMap::default();
         match self {
-            Webhook::Audit(_h) => {
+            // http sinks and audit logs record the same events as the audit webhook
+            Webhook::Audit(_) | Webhook::Http(_) | Webhook::AuditLog(_) => {
                 whc.insert("SHIPCAT_AUDIT_CONTEXT_ID".into(),
                                 env::var("SHIPCAT_AUDIT_CONTEXT_ID")
                                 .unwrap_or_else(|_| Uuid::new_v4().to_string()));
@@ -360,19 +954,50 @@ impl Webhook {
                 }
 
                 // strict requirements
+                if let Webhook::AuditLog(h) = self {
+                    // local logs should also record local upgrades
+                    if !whc.contains_key("SHIPCAT_AUDIT_REVISION") {
+                        whc.insert("SHIPCAT_AUDIT_REVISION".into(), "unknown".into());
+                    }
+                    if h.path.is_some() == h.configMap.is_some() {
+                        return Err("audit_log webhook needs exactly one of path or configMap".into())
+                    }
+                }
                 if !whc.contains_key("SHIPCAT_AUDIT_REVISION") {
                     return Err("SHIPCAT_AUDIT_REVISION not specified".into())
                 }
 
                 debug!("Audit webhook config {:?}", whc);
             }
+            Webhook::Slack(h) => {
+                if h.url.is_empty() && h.token.is_none() {
+                    return Err(format!("slack webhook for {} needs a url or a token", h.channel).into())
+                }
+                for r in &h.routes {
+                    r.channel.verify()?;
+                }
+            }
+            // fully configured in the region
+            Webhook::Grafana(_) => {}
         }
 
-        // TODO: when slack webhook is cfged, require this:
-        // slack::have_credentials()?;
-
         Ok(whc)
     }
+
+    /// Whether the webhook wants to hear about an upgrade state
+    ///
+    /// Audit webhooks and logs get everything.
+    pub fn wants(&self, us: &UpgradeState) -> bool {
+        match self {
+            Webhook::Audit(_) | Webhook::AuditLog(_) => true,
+            // threads are started on pending
+            Webhook::Slack(h) => h.events.contains(us)
+                || h.routes.iter().any(|r| r.events.contains(us))
+                || (h.token.is_some() && *us == UpgradeState::Pending),
+            Webhook::Grafana(h) => h.events.contains(us),
+            Webhook::Http(h) => h.events.is_empty() || h.events.contains(us),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -388,6 +1013,7 @@ mod test_webhooks {
         let wha = Webhook::Audit(AuditWebhook{
             url: Url::parse("http://testnoop").unwrap(),
             token: "noop".into(),
+            retries: 0,
         });
         let reuuid = Regex::new(r"^[0-9a-f-]{36}$").unwrap();
 
@@ -426,7 +1052,7 @@ mod test_webhooks {
 // ----------------------------------------------------------------------------------
 
 /// Environments are well defined strings
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "lowercase")]
 pub enum Environment {
     /// Production environment
@@ -460,8 +1086,28 @@ impl ToString for Environment {
 
 // ----------------------------------------------------------------------------------
 
+/// Helm major version used to manage releases in a region
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HelmVersion {
+    /// Helm 2 with a tiller in the region's namespace
+    V2,
+    /// Helm 3 with releases stored in the region's namespace
+    ///
+    /// Existing helm 2 releases can be adopted with `shipcat cluster helm migrate`.
+    V3,
+}
+
+impl Default for HelmVersion {
+    fn default() -> Self {
+        HelmVersion::V2
+    }
+}
+
+// ----------------------------------------------------------------------------------
+
 /// Environments are well defined strings
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
 pub enum ReconciliationMode {
     /// Tiller owned, apply every time
     ///
@@ -491,7 +1137,7 @@ impl Default for ReconciliationMode {
 ///
 /// Either it's a pure kubernetes context with a namespace and a cluster,
 /// or it's an abstract concept with many associated real kubernetes contexts.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(test, derive(Default))]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Region {
@@ -506,6 +1152,9 @@ pub struct Region {
     /// This affects how `cluster crd reconcile` behaves in the region.
     #[serde(default)]
     pub reconciliationMode: ReconciliationMode,
+    /// Helm version managing the region's releases
+    #[serde(default)]
+    pub helmVersion: HelmVersion,
 
     /// Primary cluster serving this region
     ///
@@ -519,6 +1168,12 @@ pub struct Region {
     pub cluster: String,
     /// Versioning scheme
     pub versioningScheme: VersionScheme,
+    /// Downgrade and pre-release protection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versionPolicy: Option<VersionPolicy>,
+    /// Deploy freeze windows and change approvals
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployPolicy: Option<DeployPolicy>,
 
     /// Important base urls that can be templated in evars
     #[serde(default)]
@@ -530,6 +1185,12 @@ pub struct Region {
     /// Kong configuration for the region
     #[serde(default)]
     pub kong: KongConfig,
+    /// Ingress configuration for regions without kong
+    ///
+    /// When set, the `kong` block of manifests is translated into `Ingress` objects
+    /// instead of kong apis, and `shipcat kong` refuses to generate config for the region.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingress: Option<IngressConfig>,
     /// Statuscake configuration for the region
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub statuscake: Option<StatuscakeConfig>,
@@ -554,6 +1215,21 @@ pub struct Region {
     pub webhooks: Option<Vec<Webhook>>,
     /// CRD tuning
     pub customResources: Option<CRSettings>,
+    /// Network policy generation (opt-in)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub networkPolicies: Option<NetworkPolicyConfig>,
+    /// Istio service mesh generation (opt-in)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serviceMesh: Option<ServiceMeshConfig>,
+    /// Pod security defaults and rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podSecurity: Option<PodSecurityConfig>,
+    /// Docker registry access and image checks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<RegistryConfig>,
+    /// Image digest pinning at deploy time (opt-in)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imageDigests: Option<ImageDigestConfig>,
     /// Default values for services
     #[serde(skip_serializing, default)]
     pub defaults: DefaultConfig,
@@ -632,3 +1308,62 @@ impl Region {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ApprovalPolicy, DeployPolicy, FreezeWindow, VersionPolicy, VersionScheme};
+
+    #[test]
+    fn version_schemes() {
+        assert!(VersionScheme::PrefixedSemver.verify("v1.2.3").is_ok());
+        assert!(VersionScheme::PrefixedSemver.verify("release-1.2.3").is_ok());
+        assert!(VersionScheme::PrefixedSemver.verify("1.2.3").is_err());
+        assert!(VersionScheme::Calver.verify("2019.05.1").is_ok());
+        assert!(VersionScheme::Calver.verify("19.05").is_ok());
+        assert!(VersionScheme::Calver.verify("2019.13.1").is_err());
+        let custom = VersionScheme::Regex("^build-[0-9]+$".into());
+        assert!(custom.verify("build-42").is_ok());
+        assert!(custom.verify("42").is_err());
+        assert!(VersionScheme::Regex("(".into()).verify_scheme().is_err());
+    }
+
+    #[test]
+    fn version_policy() {
+        let policy = VersionPolicy { forbidDowngrades: true, forbidPrereleases: true };
+        let semver = VersionScheme::Semver;
+        assert!(policy.verify(&semver, Some("1.2.0"), "1.3.0").is_ok());
+        assert!(policy.verify(&semver, Some("1.2.0"), "1.1.9").is_err());
+        assert!(policy.verify(&semver, None, "1.3.0-rc.1").is_err());
+        assert!(policy.verify(&VersionScheme::Calver, Some("2019.05.2"), "2019.05.10").is_ok());
+        assert!(policy.verify(&VersionScheme::Calver, Some("2019.05.2"), "2019.4.1").is_err());
+        // two digit years order with four digit ones
+        assert!(policy.verify(&VersionScheme::Calver, Some("2019.05.2"), "19.06").is_ok());
+        assert!(policy.verify(&VersionScheme::Calver, Some("19.06"), "2019.05.2").is_err());
+        // unordered versions cannot be checked
+        let sha = "d4f01f5143643e75d9cc2d5e3221e82a9e1c12e5";
+        assert!(policy.verify(&VersionScheme::GitShaOrSemver, Some("1.2.0"), sha).is_ok());
+    }
+
+    #[test]
+    fn deploy_policy_verify() {
+        let window = |start: Option<&str>, end: Option<&str>, cron: Option<&str>| FreezeWindow {
+            name: "xmas".into(),
+            start: start.map(String::from),
+            end: end.map(String::from),
+            cron: cron.map(String::from),
+        };
+        let mut policy = DeployPolicy {
+            freezes: vec![
+                window(Some("2019-12-20T17:00:00Z"), Some("2020-01-02T09:00:00Z"), None),
+                window(None, None, Some("* * * * 0,6")),
+            ],
+            ..Default::default()
+        };
+        assert!(policy.verify("prod-uk").is_ok());
+        policy.freezes.push(window(Some("2019-12-20T17:00:00Z"), None, None));
+        assert!(policy.verify("prod-uk").is_err());
+        policy.freezes.pop();
+        policy.approval = Some(ApprovalPolicy { pattern: "^CHG-[0-9+$".into(), always: false });
+        assert!(policy.verify("prod-uk").is_err());
+    }
+}
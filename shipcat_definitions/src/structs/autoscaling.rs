@@ -1,38 +1,61 @@
 // AutoScaling types roughly as defined in kubernetes source
 // https://github.com/kubernetes/kubernetes/blob/master/pkg/apis/autoscaling/types.go
 
+use std::collections::BTreeMap;
+
 use super::{Result};
 
 /// Configuration parameters for HorizontalPodAutoScaler
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct AutoScaling {
     pub minReplicas: u32,
     pub maxReplicas: u32,
 
     /// Metrics to scale on
     pub metrics: Vec<ScalingMetric>,
+
+    /// Scaling behaviour in each direction
+    ///
+    /// Straight from the `autoscaling/v2beta2` HPA spec.
+    ///
+    /// ```yaml
+    /// behavior:
+    ///   scaleDown:
+    ///     stabilizationWindowSeconds: 300
+    ///     policies:
+    ///     - type: Pods
+    ///       value: 1
+    ///       periodSeconds: 60
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub behavior: Option<ScalingBehavior>,
 }
 
 /// Kubernetes Scaling Metrics (adjacently tagged enums)
 ///
 /// The content name (for adjacency) is dynamic - so need wrapper structs..
 /// The name of the wrapper is tagged correctly via serde under a `type` key
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 #[serde(tag = "type")]
 pub enum ScalingMetric {
     Resource(ScalingMetricResourceWrapper),
     Pods(ScalingMetricPodWrapper),
-    // no Object support yet
+    Object(ScalingMetricObjectWrapper),
+    External(ScalingMetricExternalWrapper),
 }
 
 // dumb adjacency wrappers to get the adjacency content
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct ScalingMetricResourceWrapper { resource: ScalingMetricResource }
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct ScalingMetricPodWrapper { pods: ScalingMetricPod }
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ScalingMetricObjectWrapper { object: ScalingMetricObject }
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ScalingMetricExternalWrapper { external: ScalingMetricExternal }
 
 /// Native resource scaling via kube
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct ScalingMetricResource {
     name: ScalingMetricResourceType,
     /// The target value of the average of the resource metric across relevant pods,
@@ -44,7 +67,7 @@ pub struct ScalingMetricResource {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     targetAverageValue: Option<String>,
 }
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub enum ScalingMetricResourceType {
     #[serde(rename = "cpu")]
     CPU,
@@ -53,13 +76,140 @@ pub enum ScalingMetricResourceType {
 }
 
 /// Scaling Metrics from prometheus
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct ScalingMetricPod {
     /// Promethus metric name
     pub metricName: String,
     pub targetAverageValue: String,
 }
 
+/// Kubernetes object a metric describes
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CrossVersionObjectReference {
+    /// Kind of the referent, e.g. `Ingress`
+    pub kind: String,
+    /// Name of the referent
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apiVersion: Option<String>,
+}
+
+/// Scaling on a metric describing a single kubernetes object
+///
+/// E.g. requests per second on an `Ingress`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ScalingMetricObject {
+    /// The object the metric describes
+    pub target: CrossVersionObjectReference,
+    /// Custom metric name
+    pub metricName: String,
+    /// Target value of the metric
+    pub targetValue: String,
+    /// Target value of the metric divided by the number of pods
+    ///
+    /// Takes precedence over `targetValue` when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub averageValue: Option<String>,
+}
+
+/// Scaling on a metric not associated with any kubernetes object
+///
+/// E.g. the length of a queue in a cloud messaging service.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ScalingMetricExternal {
+    /// External metric name
+    pub metricName: String,
+    /// Labels to narrow down the metric
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metricSelector: Option<ScalingMetricSelector>,
+    /// Target value of the metric
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub targetValue: Option<String>,
+    /// Target value of the metric divided by the number of pods
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub targetAverageValue: Option<String>,
+}
+
+/// Label selector for external metrics
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ScalingMetricSelector {
+    pub matchLabels: BTreeMap<String, String>,
+}
+
+/// HPA scaling behaviour for scaling up and down
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct ScalingBehavior {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scaleUp: Option<ScalingRules>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scaleDown: Option<ScalingRules>,
+}
+
+/// Rules for scaling in one direction
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct ScalingRules {
+    /// Seconds of past recommendations to consider before scaling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stabilizationWindowSeconds: Option<u32>,
+    /// Which policy to use when several apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selectPolicy: Option<ScalingPolicySelect>,
+    /// Limits on how fast to scale
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<ScalingPolicy>,
+}
+
+/// How to pick between several scaling policies
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub enum ScalingPolicySelect {
+    /// The policy allowing the largest change
+    Max,
+    /// The policy allowing the smallest change
+    Min,
+    /// Never scale in this direction
+    Disabled,
+}
+
+/// Unit of a scaling policy
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub enum ScalingPolicyType {
+    Pods,
+    Percent,
+}
+
+/// Limit on how much to scale in a period
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ScalingPolicy {
+    #[serde(rename = "type")]
+    pub policyType: ScalingPolicyType,
+    /// Number of pods or percentage of pods
+    pub value: u32,
+    /// How long the policy applies for
+    pub periodSeconds: u32,
+}
+
+impl ScalingRules {
+    fn verify(&self, direction: &str) -> Result<()> {
+        if let Some(w) = self.stabilizationWindowSeconds {
+            if w > 3600 {
+                bail!("{} stabilizationWindowSeconds cannot exceed 3600", direction);
+            }
+        }
+        if self.selectPolicy == Some(ScalingPolicySelect::Disabled) && !self.policies.is_empty() {
+            bail!("{} policies are pointless when selectPolicy is Disabled", direction);
+        }
+        for p in &self.policies {
+            if p.value == 0 {
+                bail!("{} policy values must be at least 1", direction);
+            }
+            if p.periodSeconds == 0 || p.periodSeconds > 1800 {
+                bail!("{} policy periodSeconds must be between 1 and 1800", direction);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl AutoScaling {
      pub fn verify(&self) -> Result<()> {
         if self.minReplicas == 0 {
@@ -76,17 +226,109 @@ impl AutoScaling {
                     // if this is the case should disallow both to be set..
                     match r.resource.name {
                         ScalingMetricResourceType::CPU => {
-                            assert!(r.resource.targetAverageUtilization.is_some());
+                            if r.resource.targetAverageUtilization.is_none() {
+                                bail!("cpu scaling requires targetAverageUtilization");
+                            }
                         },
                         ScalingMetricResourceType::Memory => {
-                            assert!(r.resource.targetAverageValue.is_some());
+                            if r.resource.targetAverageValue.is_none() {
+                                bail!("memory scaling requires targetAverageValue");
+                            }
                         }
                     }
                 },
                 ScalingMetric::Pods(_p) => {} // no validation here
+                ScalingMetric::Object(o) => {
+                    if o.object.metricName.is_empty() {
+                        bail!("Object scaling metric needs a metricName");
+                    }
+                    if o.object.target.kind.is_empty() || o.object.target.name.is_empty() {
+                        bail!("Object scaling metric {} needs a target kind and name", o.object.metricName);
+                    }
+                },
+                ScalingMetric::External(e) => {
+                    let ext = &e.external;
+                    if ext.metricName.is_empty() {
+                        bail!("External scaling metric needs a metricName");
+                    }
+                    if ext.targetValue.is_some() == ext.targetAverageValue.is_some() {
+                        bail!("External scaling metric {} needs exactly one of targetValue or targetAverageValue", ext.metricName);
+                    }
+                },
+            }
+        }
+        if let Some(b) = &self.behavior {
+            if let Some(up) = &b.scaleUp {
+                up.verify("scaleUp")?;
+            }
+            if let Some(down) = &b.scaleDown {
+                down.verify("scaleDown")?;
             }
         }
 
         Ok(())
      }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AutoScaling;
+
+    #[test]
+    fn verify_autoscaling() {
+        let hpa: AutoScaling = serde_yaml::from_str("
+minReplicas: 2
+maxReplicas: 6
+metrics:
+- type: Resource
+  resource:
+    name: cpu
+    targetAverageUtilization: 60
+- type: External
+  external:
+    metricName: queue_length
+    targetAverageValue: \"30\"
+- type: Object
+  object:
+    target:
+      kind: Ingress
+      name: main-route
+    metricName: requests-per-second
+    targetValue: 2k
+behavior:
+  scaleDown:
+    stabilizationWindowSeconds: 300
+    policies:
+    - type: Pods
+      value: 1
+      periodSeconds: 60").unwrap();
+        assert!(hpa.verify().is_ok());
+
+        // a bad manifest is an error rather than a panic
+        let nocpu: AutoScaling = serde_yaml::from_str("
+minReplicas: 2
+maxReplicas: 6
+metrics:
+- type: Resource
+  resource:
+    name: cpu
+    targetAverageValue: 500m").unwrap();
+        assert!(nocpu.verify().is_err());
+
+        let badperiod: AutoScaling = serde_yaml::from_str("
+minReplicas: 2
+maxReplicas: 6
+metrics:
+- type: Pods
+  pods:
+    metricName: inflight
+    targetAverageValue: \"10\"
+behavior:
+  scaleUp:
+    policies:
+    - type: Percent
+      value: 100
+      periodSeconds: 0").unwrap();
+        assert!(badperiod.verify().is_err());
+    }
+}
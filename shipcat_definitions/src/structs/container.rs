@@ -1,4 +1,4 @@
-use super::{ResourceRequirements, EnvVars, Probe, Port, VolumeMount};
+use super::{ResourceRequirements, EnvVars, Probe, Port, VolumeMount, SecurityContext};
 
 #[derive(Serialize, Deserialize, Default, Clone, Debug)]
 #[serde(default, rename_all = "camelCase")]
@@ -37,4 +37,8 @@ pub struct Container {
     /// Volume mounts
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub volume_mounts: Vec<VolumeMount>,
+
+    /// Security settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_context: Option<SecurityContext>,
 }
@@ -1,10 +1,11 @@
 use std::path::Path;
 use super::Result;
+use super::mesh::{verify_duration, Retries};
 
 /// Supported dependency protocols
 ///
 /// Forces lowercase values of this enum to be used
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum DependencyProtocol {
     /// HTTP REST dependency
@@ -25,7 +26,7 @@ impl Default for DependencyProtocol {
 }
 
 /// Dependency of a service
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Dependency {
     /// Name of service relied upon (used to goto dependent manifest)
@@ -40,6 +41,16 @@ pub struct Dependency {
     pub protocol: DependencyProtocol,
     /// Intent behind dependency - for manifest level descriptiveness
     pub intent: Option<String>,
+    /// Timeout for requests to the dependency in service mesh regions
+    ///
+    /// Overrides the dependency's own `mesh.timeout` for this service only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    /// Retries for requests to the dependency in service mesh regions
+    ///
+    /// Overrides the dependency's own `mesh.retries` for this service only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<Retries>,
 }
 
 fn default_api_version() -> String { "v1".into() }
@@ -57,6 +68,18 @@ impl Dependency {
             let ver : usize = vstr.parse()?;
             trace!("Parsed api version of dependency {} as {}", self.name.clone(), ver);
         }
+        if self.timeout.is_some() || self.retries.is_some() {
+            match self.protocol {
+                DependencyProtocol::Http | DependencyProtocol::Grpc => {},
+                _ => bail!("Dependency {} can only set timeout or retries for http or grpc", self.name),
+            }
+        }
+        if let Some(t) = &self.timeout {
+            verify_duration("timeout", t)?;
+        }
+        if let Some(r) = &self.retries {
+            r.verify()?;
+        }
         Ok(())
     }
 }
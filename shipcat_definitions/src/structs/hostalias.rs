@@ -3,7 +3,7 @@ use super::Result;
 
 // HostAlias support for all pods regardless of network configuration.
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
 pub struct HostAlias {
     /// ip address string
     pub ip: String,
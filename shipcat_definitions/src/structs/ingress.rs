@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+
+use super::{Kong, Region, Result};
+use super::Authentication;
+
+const NGINX: &str = "nginx.ingress.kubernetes.io";
+
+/// Generated `Ingress` for a service exposed through kong settings
+///
+/// Built from the `kong` block in regions that configure `ingress` instead of kong.
+/// Never set in manifests.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct Ingress {
+    /// Ingress class from the region
+    pub ingressClass: String,
+    /// Annotations translated from kong settings and the region
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Hosts to route, all paths are routed on every host
+    ///
+    /// Empty means any host.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    /// Paths routed to the service
+    pub paths: Vec<String>,
+    /// Secret with the TLS certificate for the hosts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tlsSecretName: Option<String>,
+}
+
+/// Kong takes milliseconds, nginx takes whole seconds
+fn seconds(ms: u32) -> String {
+    ((ms + 999) / 1000).to_string()
+}
+
+impl Ingress {
+    /// Translate kong settings into an ingress for a region using ingresses
+    ///
+    /// Fails on any kong setting that has no ingress equivalent.
+    pub fn from_kong(kong: &Kong, region: &Region) -> Result<Ingress> {
+        let cfg = if let Some(cfg) = &region.ingress {
+            cfg
+        } else {
+            bail!("Region {} does not use ingresses", region.name)
+        };
+        kong.verify_ingress_compatible(region)?;
+
+        let mut annotations = cfg.annotations.clone();
+        annotations.insert("kubernetes.io/ingress.class".into(), cfg.ingressClass.clone());
+
+        let uris = kong.uris.clone().unwrap_or_else(|| "/".into());
+        let mut paths : Vec<String> = uris.split(',').map(|u| u.trim().to_string()).collect();
+        if kong.strip_uri && paths.iter().all(|p| p == "/") {
+            // nothing to strip from the root, but keep the path absolute
+            paths = vec!["/(.*)".into()];
+            annotations.insert(format!("{}/rewrite-target", NGINX), "/$1".into());
+        } else if kong.strip_uri {
+            // capture everything after the prefix and send only that upstream
+            paths = paths.into_iter()
+                .map(|p| format!("{}(/|$)(.*)", p.trim_end_matches('/')))
+                .collect();
+            annotations.insert(format!("{}/rewrite-target", NGINX), "/$2".into());
+        }
+        if !kong.preserve_host {
+            let upstream = kong.upstream_url.splitn(2, "://").last().unwrap_or_default();
+            let host = upstream.split('/').next().unwrap_or_default();
+            annotations.insert(format!("{}/upstream-vhost", NGINX), host.into());
+        }
+
+        if let Some(t) = kong.upstream_connect_timeout {
+            annotations.insert(format!("{}/proxy-connect-timeout", NGINX), seconds(t));
+        }
+        if let Some(t) = kong.upstream_send_timeout {
+            annotations.insert(format!("{}/proxy-send-timeout", NGINX), seconds(t));
+        }
+        if let Some(t) = kong.upstream_read_timeout {
+            annotations.insert(format!("{}/proxy-read-timeout", NGINX), seconds(t));
+        }
+
+        if let Some(cors) = kong.cors.as_ref().filter(|c| c.enabled) {
+            annotations.insert(format!("{}/enable-cors", NGINX), "true".into());
+            annotations.insert(format!("{}/cors-allow-origin", NGINX), cors.origin.clone());
+            annotations.insert(format!("{}/cors-allow-methods", NGINX), cors.methods.clone());
+            annotations.insert(format!("{}/cors-allow-headers", NGINX), cors.headers.clone());
+            annotations.insert(format!("{}/cors-allow-credentials", NGINX), cors.credentials.to_string());
+            annotations.insert(format!("{}/cors-max-age", NGINX), cors.max_age.clone());
+            if !cors.exposed_headers.is_empty() {
+                annotations.insert(format!("{}/cors-expose-headers", NGINX), cors.exposed_headers.clone());
+            }
+        }
+
+        if kong.internal {
+            let mut ips = region.kong.internal_ips_whitelist.clone();
+            ips.extend(kong.additional_internal_ips.clone());
+            annotations.insert(format!("{}/whitelist-source-range", NGINX), ips.join(","));
+        }
+
+        Ok(Ingress {
+            ingressClass: cfg.ingressClass.clone(),
+            annotations,
+            hosts: kong.hosts.clone(),
+            paths,
+            tlsSecretName: cfg.tlsSecretName.clone(),
+        })
+    }
+}
+
+impl Kong {
+    /// Check that the kong settings can be expressed as an ingress
+    fn verify_ingress_compatible(&self, region: &Region) -> Result<()> {
+        let default_upstream = format!("http://{}.{}.svc.cluster.local", self.name, region.namespace);
+        if self.upstream_url != default_upstream {
+            bail!("kong.upstream_url cannot be used with ingresses, they always route to the service itself")
+        }
+        if self.auth != Authentication::None {
+            bail!("Ingresses do not authenticate requests, set `kong.unauthenticated: true` in regions using ingresses")
+        }
+        if self.cookie_auth || self.cookie_auth_csrf {
+            bail!("kong.cookie_auth and kong.cookie_auth_csrf are not supported by ingresses")
+        }
+        if self.oauth2_anonymous.is_some() || self.oauth2_extension_plugin.is_some() {
+            bail!("kong oauth2 plugins are not supported by ingresses")
+        }
+        if self.babylon_auth_header.is_some() {
+            bail!("kong.babylon_auth_header is not supported by ingresses")
+        }
+        if self.publiclyAccessible {
+            bail!("kong.publiclyAccessible is a gate setting and has no meaning for ingresses")
+        }
+        if !self.add_headers.is_empty() {
+            bail!("kong.add_headers is not supported by ingresses")
+        }
+        if let Some(cors) = self.cors.as_ref().filter(|c| c.enabled) {
+            if cors.preflight_continue {
+                bail!("kong.cors.preflight_continue is not supported by ingresses")
+            }
+        }
+        if !self.additional_internal_ips.is_empty() && !self.internal {
+            bail!("kong.additional_internal_ips requires kong.internal")
+        }
+        if let Some(uris) = &self.uris {
+            let uris = uris.split(',').map(str::trim).collect::<Vec<_>>();
+            for u in &uris {
+                if !u.starts_with('/') {
+                    bail!("kong.uris must be absolute paths for ingresses, got {}", u)
+                }
+            }
+            // the root and prefixes need different rewrites, an ingress only has one
+            if self.strip_uri && uris.len() > 1 && uris.contains(&"/") {
+                bail!("kong.strip_uri cannot combine / with other kong.uris for ingresses")
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ingress;
+    use crate::region::IngressConfig;
+    use crate::structs::Kong;
+    use crate::structs::kong::{Authentication, Cors};
+    use crate::Region;
+
+    fn setup() -> (Kong, Region) {
+        let mut reg = Region::default();
+        reg.name = "dev-ingress".into();
+        reg.namespace = "dev".into();
+        reg.ingress = Some(IngressConfig {
+            ingressClass: "nginx".into(),
+            annotations: Default::default(),
+            tlsSecretName: None,
+        });
+        let mut kong = Kong::default();
+        kong.name = "webapp".into();
+        kong.upstream_url = "http://webapp.dev.svc.cluster.local".into();
+        kong.uris = Some("/webapp".into());
+        kong.preserve_host = true;
+        kong.auth = Authentication::None;
+        (kong, reg)
+    }
+
+    #[test]
+    fn ingress_from_kong() {
+        let (mut kong, reg) = setup();
+        kong.strip_uri = true;
+        kong.upstream_read_timeout = Some(90500);
+        kong.cors = Some(Cors { enabled: true, origin: "*".into(), ..Default::default() });
+        let ing = Ingress::from_kong(&kong, &reg).unwrap();
+        assert_eq!(ing.ingressClass, "nginx");
+        assert_eq!(ing.paths, vec!["/webapp(/|$)(.*)".to_string()]);
+        let ann = &ing.annotations;
+        assert_eq!(ann["nginx.ingress.kubernetes.io/rewrite-target"], "/$2");
+        assert_eq!(ann["nginx.ingress.kubernetes.io/proxy-read-timeout"], "91");
+        assert_eq!(ann["nginx.ingress.kubernetes.io/cors-allow-origin"], "*");
+    }
+
+    #[test]
+    fn ingress_strip_root() {
+        let (mut kong, reg) = setup();
+        kong.strip_uri = true;
+        for uris in &[None, Some("/".to_string())] {
+            kong.uris = uris.clone();
+            let ing = Ingress::from_kong(&kong, &reg).unwrap();
+            assert_eq!(ing.paths, vec!["/(.*)".to_string()]);
+            assert_eq!(ing.annotations["nginx.ingress.kubernetes.io/rewrite-target"], "/$1");
+        }
+        kong.uris = Some("/, /webapp".into());
+        assert!(Ingress::from_kong(&kong, &reg).is_err());
+    }
+
+    #[test]
+    fn ingress_unsupported() {
+        let (mut kong, reg) = setup();
+        kong.auth = Authentication::OAuth2;
+        assert!(Ingress::from_kong(&kong, &reg).is_err());
+        let (mut kong, reg) = setup();
+        kong.add_headers.insert("X-Frame-Options".into(), "SAMEORIGIN".into());
+        assert!(Ingress::from_kong(&kong, &reg).is_err());
+        let (mut kong, reg) = setup();
+        kong.upstream_url = "http://elsewhere.com".into();
+        assert!(Ingress::from_kong(&kong, &reg).is_err());
+    }
+}
@@ -3,7 +3,7 @@ use super::Container;
 /// Restart policy
 ///
 /// Used to decide if a job should be restarted when it fails or not.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub enum RestartPolicy {
     Never,
     OnFailure,
@@ -13,7 +13,7 @@ impl Default for RestartPolicy {
     fn default() -> Self { RestartPolicy::Never }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct JobVolumeClaim {
     /// The cron job name
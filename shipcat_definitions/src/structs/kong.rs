@@ -1,11 +1,11 @@
 use std::ops::Not;
 use std::collections::BTreeMap;
 
-use crate::deserializers::{comma_separated_string};
+use crate::deserializers::{comma_separated_string, CommaSeparatedString};
 use super::{Authorization};
 
 /// Kong setup for a service
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Kong {
     /// Auto-populated name of service
@@ -54,6 +54,7 @@ pub struct Kong {
     ///
     /// For example: example.com. At least one of hosts, uris, or methods should be specified
     #[serde(default, skip_serializing_if = "Vec::is_empty", deserialize_with = "comma_separated_string")]
+    #[schemars(with = "CommaSeparatedString")]
     pub hosts: Vec<String>,
 
     /// Authentication type
@@ -138,7 +139,7 @@ pub struct Kong {
 fn preserve_host_default() -> bool { true }
 
 /// Cors plugin data
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Cors {
     pub credentials: bool,
@@ -152,7 +153,7 @@ pub struct Cors {
 }
 
 /// Babylon Auth Header plugin data
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct BabylonAuthHeader {
     pub auth_service: String,
@@ -161,7 +162,7 @@ pub struct BabylonAuthHeader {
     pub http_timeout_msec: u32,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub enum Authentication {
     None,
@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+use regex::Regex;
+
+use super::Result;
+use super::networkpolicy::LabelSelector;
+
+/// Mutual TLS mode between sidecars
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MtlsMode {
+    /// Accept both plain text and mutual TLS traffic
+    Permissive,
+    /// Only accept mutual TLS traffic
+    Strict,
+    /// Only accept plain text traffic
+    Disable,
+}
+impl Default for MtlsMode {
+    fn default() -> Self { MtlsMode::Permissive }
+}
+
+/// Check a duration in the format istio understands, e.g. `500ms` or `10s`
+pub fn verify_duration(name: &str, duration: &str) -> Result<()> {
+    let re = Regex::new(r"^[0-9]+(ms|s|m|h)$").unwrap();
+    if !re.is_match(duration) {
+        bail!("{} must be a duration like 500ms or 10s, got {}", name, duration);
+    }
+    Ok(())
+}
+
+/// Retry policy for http and grpc requests
+///
+/// Straight from [istio's HTTPRetry](https://istio.io/docs/reference/config/networking/virtual-service/#HTTPRetry)
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Retries {
+    /// Number of retries for a request
+    pub attempts: u32,
+    /// Timeout per attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perTryTimeout: Option<String>,
+    /// Comma separated conditions to retry on, e.g. `5xx,connect-failure`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retryOn: Option<String>,
+}
+
+impl Retries {
+    pub fn verify(&self) -> Result<()> {
+        if self.attempts == 0 || self.attempts > 10 {
+            bail!("Retry attempts must be between 1 and 10, got {}", self.attempts);
+        }
+        if let Some(t) = &self.perTryTimeout {
+            verify_duration("retries.perTryTimeout", t)?;
+        }
+        Ok(())
+    }
+}
+
+/// Service mesh settings for a service
+///
+/// Only used in regions with `serviceMesh` enabled.
+///
+/// ```yaml
+/// mesh:
+///   timeout: 10s
+///   retries:
+///     attempts: 3
+///     perTryTimeout: 2s
+///     retryOn: 5xx,connect-failure
+///   mtls: STRICT
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Mesh {
+    /// Timeout for requests to the service
+    ///
+    /// Defaults to `kong.upstream_read_timeout` when exposed through kong.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    /// Retries for requests to the service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<Retries>,
+    /// Mutual TLS mode for traffic to the service (region default if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtls: Option<MtlsMode>,
+}
+
+impl Mesh {
+    pub fn verify(&self) -> Result<()> {
+        if let Some(t) = &self.timeout {
+            verify_duration("mesh.timeout", t)?;
+        }
+        if let Some(r) = &self.retries {
+            r.verify()?;
+        }
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------------
+// Generated istio specs
+
+/// Destination of a route
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Destination {
+    pub host: String,
+    pub port: PortSelector,
+}
+
+/// Port on a destination
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PortSelector {
+    pub number: u32,
+}
+
+/// Weighted destination of a route
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct HttpRouteDestination {
+    pub destination: Destination,
+}
+
+/// Conditions for a route to apply
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct HttpMatchRequest {
+    pub sourceLabels: BTreeMap<String, String>,
+}
+
+/// Http route in a `VirtualService`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct HttpRoute {
+    /// Any of these must match, empty matches everything
+    #[serde(rename = "match", default, skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<HttpMatchRequest>,
+    pub route: Vec<HttpRouteDestination>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<Retries>,
+}
+
+/// Spec of an istio `VirtualService`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct VirtualService {
+    pub hosts: Vec<String>,
+    pub http: Vec<HttpRoute>,
+}
+
+/// TLS settings for traffic to a destination
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct TlsSettings {
+    pub mode: String,
+}
+
+/// TCP connection settings for a destination
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct TcpSettings {
+    pub connectTimeout: String,
+}
+
+/// Connection pool settings for a destination
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ConnectionPool {
+    pub tcp: TcpSettings,
+}
+
+/// Ejection of failing pods from the load balancing pool
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct OutlierDetection {
+    pub maxEjectionPercent: u32,
+}
+
+/// Traffic policy for a destination
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct TrafficPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connectionPool: Option<ConnectionPool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outlierDetection: Option<OutlierDetection>,
+}
+
+/// Spec of an istio `DestinationRule`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct DestinationRule {
+    pub host: String,
+    pub trafficPolicy: TrafficPolicy,
+}
+
+/// Mutual TLS setting of a `PeerAuthentication`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PeerMtls {
+    pub mode: MtlsMode,
+}
+
+/// Spec of an istio `PeerAuthentication`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PeerAuthentication {
+    pub selector: LabelSelector,
+    pub mtls: PeerMtls,
+}
+
+/// Generated istio objects for a service
+///
+/// Built by shipcat from `mesh`, dependencies, `rollingUpdate` and kong timeouts
+/// in regions that enable `serviceMesh`. Never set in manifests.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ServiceMesh {
+    pub virtualService: VirtualService,
+    pub destinationRule: DestinationRule,
+    pub peerAuthentication: PeerAuthentication,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mesh, Retries};
+
+    #[test]
+    fn verify_mesh() {
+        let mut mesh = Mesh::default();
+        mesh.timeout = Some("10s".into());
+        mesh.retries = Some(Retries { attempts: 3, perTryTimeout: Some("500ms".into()), retryOn: None });
+        assert!(mesh.verify().is_ok());
+        mesh.timeout = Some("10".into());
+        assert!(mesh.verify().is_err());
+        mesh.timeout = None;
+        mesh.retries = Some(Retries { attempts: 0, perTryTimeout: None, retryOn: None });
+        assert!(mesh.verify().is_err());
+    }
+}
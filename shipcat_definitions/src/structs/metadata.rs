@@ -6,7 +6,7 @@ use super::Result;
 use crate::config::{Team, SlackParameters};
 
 /// Contact data
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Contact {
     /// Free text name
     pub name: String,
@@ -41,7 +41,7 @@ impl Contact {
 }
 
 /// Slack channel verifier
-#[derive(Serialize, Deserialize, PartialEq, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Default, Debug)]
 pub struct SlackChannel(String);
 impl SlackChannel {
     pub fn new(chan: &str) -> Self {
@@ -77,7 +77,7 @@ impl DerefMut for SlackChannel {
 }
 
 /// Metadata for a service
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 #[cfg_attr(test, derive(Default))]
 pub struct Metadata {
     /// Git repository
@@ -99,8 +99,28 @@ pub use self::port::Port;
 
 /// Rbac
 pub mod rbac;
-pub use self::rbac::Rbac;
+pub use self::rbac::{Rbac, RbacPolicy};
 
 // PersistentVolume
 mod persistentvolume;
 pub use self::persistentvolume::PersistentVolume;
+
+/// StatefulSet workloads
+pub mod statefulset;
+pub use self::statefulset::{StatefulSet, Workload};
+
+/// Kubernetes network policies generated from dependencies
+pub mod networkpolicy;
+pub use self::networkpolicy::NetworkPolicy;
+
+/// Kubernetes ingresses generated from kong settings
+pub mod ingress;
+pub use self::ingress::Ingress;
+
+/// Istio objects generated from dependencies and mesh settings
+pub mod mesh;
+pub use self::mesh::{Mesh, ServiceMesh};
+
+/// Pod and container security settings
+pub mod podsecurity;
+pub use self::podsecurity::{SecurityContext, PodSecurityContext, SecurityExemption, SecurityRule};
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+
+/// Kubernetes label selector
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct LabelSelector {
+    pub matchLabels: BTreeMap<String, String>,
+}
+
+impl LabelSelector {
+    pub fn app(name: &str) -> Self {
+        let mut matchLabels = BTreeMap::new();
+        matchLabels.insert("app".into(), name.into());
+        LabelSelector { matchLabels }
+    }
+}
+
+/// CIDR range allowed by a network policy
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct IpBlock {
+    pub cidr: String,
+}
+
+/// Source of traffic in a network policy rule
+///
+/// Straight from [kubernetes network policies](https://kubernetes.io/docs/concepts/services-networking/network-policies/)
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct NetworkPolicyPeer {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podSelector: Option<LabelSelector>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespaceSelector: Option<LabelSelector>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipBlock: Option<IpBlock>,
+}
+
+/// Port allowed by a network policy rule
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct NetworkPolicyPort {
+    pub port: u32,
+    pub protocol: String,
+}
+
+/// Ingress rule in a network policy
+///
+/// An empty rule allows all traffic.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct NetworkPolicyIngressRule {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub from: Vec<NetworkPolicyPeer>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<NetworkPolicyPort>,
+}
+
+/// Generated ingress `NetworkPolicy` for a service
+///
+/// Built by shipcat from the dependency graph, kong exposure and `sourceRanges`
+/// in regions that enable `networkPolicies`. Never set in manifests.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct NetworkPolicy {
+    /// Whether the policy is deployed in audit mode only
+    ///
+    /// Audit policies carry an extra allow-all rule, so they never block traffic.
+    pub audit: bool,
+    /// Ingress rules for the service's pods
+    pub ingress: Vec<NetworkPolicyIngressRule>,
+}
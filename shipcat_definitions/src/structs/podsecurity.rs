@@ -0,0 +1,128 @@
+use super::Result;
+
+/// Linux capabilities to add or drop
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct Capabilities {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub add: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drop: Vec<String>,
+}
+
+/// Container level security settings
+///
+/// A subset of kubernetes' `SecurityContext`.
+///
+/// ```yaml
+/// securityContext:
+///   runAsNonRoot: true
+///   readOnlyRootFilesystem: true
+///   allowPrivilegeEscalation: false
+///   capabilities:
+///     drop: ["ALL"]
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct SecurityContext {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runAsUser: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runAsGroup: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runAsNonRoot: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privileged: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowPrivilegeEscalation: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readOnlyRootFilesystem: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Capabilities>,
+}
+
+/// Pod level security settings, shared by all containers in the pod
+///
+/// A subset of kubernetes' `PodSecurityContext`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PodSecurityContext {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runAsUser: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runAsGroup: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runAsNonRoot: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fsGroup: Option<i64>,
+}
+
+/// Pod security rules a region can enforce
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub enum SecurityRule {
+    /// Containers must not run as root
+    RunAsNonRoot,
+    /// Containers must not be privileged
+    Privileged,
+    /// Pods must not mount host paths
+    HostPath,
+    /// Containers must have a read only root filesystem
+    ReadOnlyRootFilesystem,
+}
+
+/// A justified exemption from one of the region's pod security rules
+///
+/// ```yaml
+/// securityExemptions:
+/// - rule: ReadOnlyRootFilesystem
+///   reason: "Legacy app writes its pid file to /var/run, tracked in PLAT-123"
+/// ```
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct SecurityExemption {
+    /// Rule the service is exempt from
+    pub rule: SecurityRule,
+    /// Why the service needs the exemption
+    pub reason: String,
+}
+
+impl SecurityExemption {
+    pub fn verify(&self) -> Result<()> {
+        if self.reason.trim().len() < 10 {
+            bail!("Security exemption from {:?} needs a proper reason", self.rule);
+        }
+        Ok(())
+    }
+}
+
+impl SecurityContext {
+    /// Whether the container is guaranteed to run as a non-root user
+    pub fn runs_as_non_root(ctx: Option<&SecurityContext>, pod: Option<&PodSecurityContext>) -> bool {
+        let user = ctx.and_then(|c| c.runAsUser).or_else(|| pod.and_then(|p| p.runAsUser));
+        if user == Some(0) {
+            return false;
+        }
+        let nonroot = ctx.and_then(|c| c.runAsNonRoot).or_else(|| pod.and_then(|p| p.runAsNonRoot));
+        nonroot == Some(true) || user.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PodSecurityContext, SecurityContext};
+
+    #[test]
+    fn non_root_inheritance() {
+        let mut pod = PodSecurityContext::default();
+        pod.runAsNonRoot = Some(true);
+        assert!(SecurityContext::runs_as_non_root(None, Some(&pod)));
+        assert!(!SecurityContext::runs_as_non_root(None, None));
+
+        // containers can override the pod
+        let mut ctx = SecurityContext::default();
+        ctx.runAsUser = Some(0);
+        assert!(!SecurityContext::runs_as_non_root(Some(&ctx), Some(&pod)));
+        ctx.runAsUser = Some(1000);
+        assert!(SecurityContext::runs_as_non_root(Some(&ctx), None));
+    }
+}
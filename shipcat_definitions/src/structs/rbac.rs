@@ -1,67 +1,103 @@
+use std::collections::BTreeMap;
+use std::ops::Not;
+
 use super::Result;
 
 /// RBAC (Role-Based Access Control)
 ///
 /// Designed for services which requires escalated privileges
 /// Used to generate roles and role bindings in kubernetes
-#[derive(Serialize, Deserialize, Clone, Debug)]
+///
+/// Every api group, resource and verb must be allowed by the `rbac` policy
+/// for the region's environment in `shipcat.conf`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Rbac {
     /// API groups containing resources (defined below)
-    pub apiGroups: Vec<AllowedApiGroups>,
+    pub apiGroups: Vec<String>,
     /// Resources on which to apply verbs / actions
-    pub resources: Vec<AllowedResources>,
+    pub resources: Vec<String>,
     /// Actions to be allowed
-    pub verbs: Vec<AllowedVerbs>
+    pub verbs: Vec<String>,
+    /// Grant the rule across all namespaces through a `ClusterRole`
+    ///
+    /// Only possible where the environment's policy sets `allowClusterRoles`.
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub clusterWide: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum AllowedApiGroups {
-    #[serde(rename = "")]
-    Empty,
-    Extensions,
-    Batch,
-    #[serde(rename = "babylontech.co.uk")]
-    Babylontech,
+/// RBAC allowlist for an environment
+///
+/// Defined in `shipcat.conf` so platform admins control what services can request.
+/// Resources are allowed per api group, so allowing `jobs` in `batch` does not
+/// allow `jobs` in other groups. A `*` entry allows anything, including a `*` request.
+///
+/// ```yaml
+/// rbac:
+///   dev:
+///     apiGroups:
+///       "": ["pods", "pods/log"]
+///       extensions: ["deployments"]
+///       batch: ["jobs"]
+///     verbs: ["get", "list", "watch", "create"]
+///     allowClusterRoles: true
+/// ```
+///
+/// Environments without a policy get the `legacy` allowlist.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct RbacPolicy {
+    /// Resources services can request access to in each api group
+    #[serde(default)]
+    pub apiGroups: BTreeMap<String, Vec<String>>,
+    /// Verbs services can request
+    #[serde(default)]
+    pub verbs: Vec<String>,
+    /// Whether services can request cluster wide access
+    #[serde(default)]
+    pub allowClusterRoles: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum AllowedResources {
-    Deployments,
-    ReplicaSets,
-    Jobs,
-    CronJobs,
-    Pods,
-    #[serde(rename = "pods/log")]
-    PodsSlashLog,
-    ConfigMaps,
-    Namespaces,
-    HorizontalPodAutoscaler,
-    Events,
-    Nodes,
-    RoleBindings,
-    Roles,
-    Secrets,
-    ServiceAccounts,
-    Services,
-    ShipcatManifests,
-    ShipcatConfigs,
+impl RbacPolicy {
+    /// The allowlist used before policies were configurable
+    ///
+    /// Read only access to a fixed set of resources in a fixed set of api groups.
+    pub fn legacy() -> RbacPolicy {
+        let resources = [
+            "deployments", "replicasets", "jobs", "cronjobs", "pods", "pods/log", "configmaps",
+            "namespaces", "horizontalpodautoscaler", "events", "nodes", "rolebindings", "roles",
+            "secrets", "serviceaccounts", "services", "shipcatmanifests", "shipcatconfigs",
+        ].iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        let apiGroups = ["", "extensions", "batch", "babylontech.co.uk"].iter()
+            .map(|g| (g.to_string(), resources.clone()))
+            .collect();
+        RbacPolicy {
+            apiGroups,
+            verbs: vec!["list".into(), "get".into(), "watch".into()],
+            allowClusterRoles: false,
+        }
+    }
+
+    /// Resources allowed in an api group
+    fn resources(&self, group: &str) -> Option<&Vec<String>> {
+        self.apiGroups.get(group).or_else(|| self.apiGroups.get("*"))
+    }
 }
 
-/// We don't allow eg Delete or other operations for security reasons (least privilege).
-/// More operations can be added if required but due diligence would be sane.
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum AllowedVerbs {
-    List,
-    Get,
-    Watch,
+fn check_allowed(kind: &str, requested: &[String], allowed: &[String]) -> Result<()> {
+    if allowed.iter().any(|a| a == "*") {
+        return Ok(());
+    }
+    for r in requested {
+        if !allowed.contains(r) {
+            bail!("RBAC {} '{}' is not allowed by shipcat.conf (allowed: {:?})", kind, r, allowed);
+        }
+    }
+    Ok(())
 }
 
 impl Rbac {
-    pub fn verify(&self) -> Result<()> {
+    pub fn verify(&self, policy: &RbacPolicy) -> Result<()> {
         if self.apiGroups.is_empty() {
             bail!("RBAC needs to have at least one item in apiGroups");
         }
@@ -71,7 +107,83 @@ impl Rbac {
         if self.verbs.is_empty() {
             bail!("RBAC needs to have at least one item in verbs");
         }
+        for g in &self.apiGroups {
+            // a `*` group needs resources allowed in every group
+            let allowed = if g == "*" { policy.apiGroups.get("*") } else { policy.resources(g) };
+            if let Some(resources) = allowed {
+                let kind = format!("resource in apiGroup '{}'", g);
+                check_allowed(&kind, &self.resources, resources)?;
+            } else {
+                let groups = policy.apiGroups.keys().collect::<Vec<_>>();
+                bail!("RBAC apiGroup '{}' is not allowed by shipcat.conf (allowed: {:?})", g, groups);
+            }
+        }
+        check_allowed("verb", &self.verbs, &policy.verbs)?;
+        if self.clusterWide && !policy.allowClusterRoles {
+            bail!("RBAC clusterWide rules are not allowed by shipcat.conf");
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Rbac, RbacPolicy};
+
+    fn strs(xs: &[&str]) -> Vec<String> {
+        xs.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn rule(groups: &[&str], resources: &[&str], verbs: &[&str]) -> Rbac {
+        Rbac {
+            apiGroups: strs(groups),
+            resources: strs(resources),
+            verbs: strs(verbs),
+            clusterWide: false,
+        }
+    }
+
+    #[test]
+    fn verify_against_policy() {
+        let mut policy = RbacPolicy::default();
+        policy.apiGroups.insert("".into(), strs(&["pods"]));
+        policy.apiGroups.insert("batch".into(), strs(&["jobs"]));
+        policy.verbs = strs(&["get", "list", "create"]);
+
+        let mut rbac = rule(&["batch"], &["jobs"], &["create"]);
+        assert!(rbac.verify(&policy).is_ok());
+        rbac.verbs.push("delete".into());
+        assert!(rbac.verify(&policy).is_err());
+        rbac.verbs.pop();
+        rbac.clusterWide = true;
+        assert!(rbac.verify(&policy).is_err());
+
+        // resources are only allowed in their own group
+        assert!(rule(&[""], &["jobs"], &["get"]).verify(&policy).is_err());
+        assert!(rule(&["", "batch"], &["pods"], &["get"]).verify(&policy).is_err());
+        assert!(rule(&["apps"], &["pods"], &["get"]).verify(&policy).is_err());
+        assert!(rule(&["*"], &["pods"], &["get"]).verify(&policy).is_err());
+
+        let mut anything = RbacPolicy::default();
+        anything.apiGroups.insert("*".into(), strs(&["*"]));
+        anything.verbs = strs(&["*"]);
+        anything.allowClusterRoles = true;
+        assert!(rbac.verify(&anything).is_ok());
+        assert!(rule(&["*"], &["*"], &["*"]).verify(&anything).is_ok());
+    }
+
+    #[test]
+    fn verify_against_legacy_policy() {
+        // what manifests could request before policies were configurable
+        let legacy = RbacPolicy::legacy();
+        let old = rule(&["", "extensions"], &["pods", "pods/log", "deployments"], &["get", "watch", "list"]);
+        assert!(old.verify(&legacy).is_ok());
+        assert!(rule(&["babylontech.co.uk"], &["shipcatmanifests"], &["get"]).verify(&legacy).is_ok());
+        assert!(rule(&["batch"], &["jobs"], &["create"]).verify(&legacy).is_err());
+        assert!(rule(&["apps"], &["deployments"], &["get"]).verify(&legacy).is_err());
+        let mut wide = old.clone();
+        wide.clusterWide = true;
+        assert!(wide.verify(&legacy).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use super::{Result};
 
 // Untagged enum to get around the weird validation
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[serde(untagged)]
 pub enum AvailabilityPolicy {
     Percentage(String),
@@ -49,7 +49,7 @@ impl AvailabilityPolicy {
 }
 
 /// Configuration parameters for Deployment.spec.strategy.rollingUpdate
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct RollingUpdate {
     /// How many replicas or percentage of replicas that can be down during rolling-update
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -133,6 +133,33 @@ impl RollingUpdate {
         trace!("rollout iters={}", iters);
         iters
     }
+    /// Rolling update the chart uses when a manifest sets none
+    ///
+    /// Kubernetes defaults, except that a single replica is never taken down.
+    pub fn chart_default(replicas: u32) -> RollingUpdate {
+        let mut ru = RollingUpdate::default();
+        if replicas == 1 {
+            ru.maxUnavailable = Some(AvailabilityPolicy::Unsigned(0));
+        }
+        ru
+    }
+
+    /// Percentage of replicas that can be down during a rollout
+    pub fn max_unavailable_percentage(&self, replicas: u32) -> Result<u32> {
+        Ok(match &self.maxUnavailable {
+            Some(AvailabilityPolicy::Percentage(percstr)) => {
+                let digits = percstr.chars().take_while(|ch| *ch != '%').collect::<String>();
+                let perc : u32 = digits.parse()
+                    .map_err(|_| format!("invalid maxUnavailable percentage '{}'", percstr))?;
+                perc.min(100)
+            },
+            Some(AvailabilityPolicy::Unsigned(u)) if replicas > 0 => {
+                ((*u as f64 * 100.0) / replicas as f64).ceil().min(100.0) as u32
+            },
+            Some(AvailabilityPolicy::Unsigned(_)) => 100,
+            None => 25, // kubernetes default
+        })
+    }
     pub fn rollout_iterations_default(replicas: u32) -> u32 {
         // default surge percentage is 25
         ((replicas as f64 * 25 as f64)/ 100.0).ceil() as u32
@@ -176,4 +203,21 @@ mod tests {
         };
         assert_eq!(rusurge.rollout_iterations(8), 4);  // 2 dn 2 up (x4)
     }
+
+    #[test]
+    fn max_unavailable_percentages() {
+        assert_eq!(RollingUpdate::default().max_unavailable_percentage(4).unwrap(), 25);
+        assert_eq!(RollingUpdate::chart_default(1).max_unavailable_percentage(1).unwrap(), 0);
+        assert_eq!(RollingUpdate::chart_default(4).max_unavailable_percentage(4).unwrap(), 25);
+        let ru = RollingUpdate {
+            maxUnavailable: Some(AvailabilityPolicy::Unsigned(1)),
+            maxSurge: None,
+        };
+        assert_eq!(ru.max_unavailable_percentage(3).unwrap(), 34);
+        let bad = RollingUpdate {
+            maxUnavailable: Some(AvailabilityPolicy::Percentage("lots%".into())),
+            maxSurge: None,
+        };
+        assert!(bad.max_unavailable_percentage(3).is_err());
+    }
 }
@@ -0,0 +1,132 @@
+use super::Result;
+use super::resources::parse_memory;
+
+/// Kubernetes workload kind for the main container
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub enum Workload {
+    /// A `Deployment` with interchangeable pods
+    Deployment,
+    /// A `StatefulSet` with stable pod identities and per-pod volumes
+    StatefulSet,
+}
+impl Default for Workload {
+    fn default() -> Self { Workload::Deployment }
+}
+
+impl Workload {
+    /// Resource name as understood by `kubectl`
+    pub fn kube_kind(&self) -> &'static str {
+        match self {
+            Workload::Deployment => "deployment",
+            Workload::StatefulSet => "statefulset",
+        }
+    }
+
+    /// Whether this is the default `Deployment` (left out when serializing)
+    pub fn is_default(&self) -> bool {
+        *self == Workload::default()
+    }
+}
+
+/// How pods are created and deleted when scaling a `StatefulSet`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub enum PodManagementPolicy {
+    /// One at a time, waiting for the previous pod to be ready
+    OrderedReady,
+    /// All at once
+    Parallel,
+}
+impl Default for PodManagementPolicy {
+    fn default() -> Self { PodManagementPolicy::OrderedReady }
+}
+
+/// Persistent volume claim created for every pod in a `StatefulSet`
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct VolumeClaimTemplate {
+    /// Name of the claim, also used as the volume name
+    pub name: String,
+    /// Where to mount the volume in the main container
+    pub mountPath: String,
+    /// Storage class to provision from (cluster default if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storageClass: Option<String>,
+    /// Access mode of the claim
+    #[serde(default = "default_access_mode")]
+    pub accessMode: String,
+    /// Requested size of the volume
+    pub size: String,
+}
+fn default_access_mode() -> String { "ReadWriteOnce".into() }
+
+impl VolumeClaimTemplate {
+    fn verify(&self) -> Result<()> {
+        let size = parse_memory(&self.size)?;
+        if size > 100.0*1024.0*1024.0*1024.0 {
+            bail!("Volume claim {} requests more than 100 GB of persistent memory", self.name)
+        }
+        let modes = ["ReadWriteOnce", "ReadOnlyMany", "ReadWriteMany"];
+        if !modes.contains(&self.accessMode.as_str()) {
+            bail!("Volume claim {} has invalid accessMode {}", self.name, self.accessMode)
+        }
+        if !self.mountPath.starts_with('/') {
+            bail!("Volume claim {} must have an absolute mountPath", self.name)
+        }
+        Ok(())
+    }
+}
+
+/// `StatefulSet` parameters
+///
+/// Only used when the manifest's `workload` is `StatefulSet`.
+/// The chart creates a headless `Service` named `{name}-headless` to govern the set.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct StatefulSet {
+    /// Ordering guarantees when scaling
+    #[serde(default)]
+    pub podManagementPolicy: PodManagementPolicy,
+    /// Volumes claimed per pod
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumeClaimTemplates: Vec<VolumeClaimTemplate>,
+}
+
+impl StatefulSet {
+    pub fn verify(&self) -> Result<()> {
+        let mut names = vec![];
+        for vct in &self.volumeClaimTemplates {
+            vct.verify()?;
+            if names.contains(&vct.name) {
+                bail!("Duplicate volume claim template {}", vct.name)
+            }
+            names.push(vct.name.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StatefulSet, VolumeClaimTemplate};
+
+    fn claim(name: &str, size: &str) -> VolumeClaimTemplate {
+        VolumeClaimTemplate {
+            name: name.into(),
+            mountPath: "/data".into(),
+            storageClass: None,
+            accessMode: "ReadWriteOnce".into(),
+            size: size.into(),
+        }
+    }
+
+    #[test]
+    fn verify_claims() {
+        let mut ss = StatefulSet::default();
+        ss.volumeClaimTemplates.push(claim("data", "10Gi"));
+        assert!(ss.verify().is_ok());
+        ss.volumeClaimTemplates.push(claim("data", "1Gi"));
+        assert!(ss.verify().is_err()); // duplicate name
+        ss.volumeClaimTemplates = vec![claim("data", "200Gi")];
+        assert!(ss.verify().is_err()); // too big
+    }
+}
@@ -5,7 +5,7 @@ use std::collections::BTreeMap;
 // TODO: cross reference better with
 // https://kubernetes.io/docs/concepts/storage/volumes/
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct VolumeSecretItem {
     #[serde(default = "volume_key")]
     pub key: String,
@@ -16,35 +16,35 @@ pub struct VolumeSecretItem {
 fn volume_key() -> String { "value".into() }
 fn volume_default_mode() -> u32 { 420 } // 0644
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 pub struct VolumeSecretDetail {
     pub secretName: String,
     pub items: Vec<VolumeSecretItem>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 pub struct ProjectedVolumeSecretSourceDetail {
     pub name: String,
     pub items: Vec<VolumeSecretItem>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 pub struct ProjectedVolumeSecretSource {
     pub secret: ProjectedVolumeSecretSourceDetail,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 pub struct ProjectedVolumeSecret {
     pub sources: Vec<ProjectedVolumeSecretSource>,
     // pub default_mode: u32,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 pub struct DownwardApiWrapper {
     pub items: Vec<DownwardApiItem>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct DownwardApiItem {
     /// Kube path to string
     pub path: String,
@@ -52,7 +52,7 @@ pub struct DownwardApiItem {
     pub resourceFieldRef: DownWardApiResource,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct DownWardApiResource {
     /// Name of container TODO: default to service name
     pub containerName: String,
@@ -63,7 +63,15 @@ pub struct DownWardApiResource {
     pub divisor: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+/// Directory on the node mounted into the pod
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct HostPathVolume {
+    pub path: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub hostPathType: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
 pub struct Volume {
     pub name: String,
     /// A projection combines multiple volume items
@@ -79,6 +87,11 @@ pub struct Volume {
     /// Items from the Downward API
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub downwardAPI: Option<DownwardApiWrapper>,
+    /// Directory on the node
+    ///
+    /// Forbidden in regions with `podSecurity.forbidHostPath` unless exempted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostPath: Option<HostPathVolume>,
 }
 
 impl Volume {
@@ -88,7 +101,7 @@ impl Volume {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, Debug)]
 pub struct VolumeMount {
     pub name: String,
     pub mountPath: String,
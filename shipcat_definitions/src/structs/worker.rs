@@ -1,4 +1,5 @@
-use super::{Container};
+use super::{Container, Result};
+use crate::ResultExt;
 use super::autoscaling::AutoScaling;
 
 /// Worker for a service
@@ -24,3 +25,15 @@ pub struct Worker {
     #[serde(flatten)]
     pub container: Container,
 }
+
+impl Worker {
+    pub fn verify(&self) -> Result<()> {
+        if self.replicaCount == 0 {
+            bail!("Worker {} needs replicaCount to be at least 1", self.container.name);
+        }
+        if let Some(ref hpa) = self.autoScaling {
+            hpa.verify().chain_err(|| format!("Worker {} has invalid autoScaling", self.container.name))?;
+        }
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use shipcat_definitions::structs::Authorization;
 use super::Result;
 use super::util::{Build};
 
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Deserialize, JsonSchema, Default, Merge, Clone)]
 pub struct AuthorizationSource {
     pub allowed_audiences: Option<Vec<String>>,
     pub allow_anonymous: Option<bool>,
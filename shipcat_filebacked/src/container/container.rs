@@ -2,7 +2,7 @@ use merge::Merge;
 use regex::Regex;
 
 use shipcat_definitions::Result;
-use shipcat_definitions::structs::{Container, Probe, Port, VolumeMount};
+use shipcat_definitions::structs::{Container, Probe, Port, SecurityContext, VolumeMount};
 
 use crate::util::{Build, Require};
 
@@ -10,7 +10,7 @@ use super::EnvVarsSource;
 use super::image::{ImageTagSource, ImageNameSource};
 use super::resources::ResourceRequirementsSource;
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Clone, Default)]
 pub struct ContainerName(String);
 
 impl Build<String, ()> for ContainerName {
@@ -25,7 +25,7 @@ impl Build<String, ()> for ContainerName {
 }
 
 /// Source configuration for a K8s container, deserialized from a service manifest.
-#[derive(Deserialize, Merge, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Merge, Clone, Default)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 pub struct ContainerSource {
     pub name: Option<ContainerName>,
@@ -44,10 +44,14 @@ pub struct ContainerSource {
     pub ports: Option<Vec<Port>>,
 
     pub volume_mounts: Option<Vec<VolumeMount>>,
+
+    pub security_context: Option<SecurityContext>,
 }
 
 pub struct ContainerBuildParams {
     pub main_envs: EnvVarsSource,
+    /// Region default for containers without a `securityContext`
+    pub security_context: Option<SecurityContext>,
 }
 
 impl Build<Container, ContainerBuildParams> for ContainerSource {
@@ -86,6 +90,8 @@ impl Build<Container, ContainerBuildParams> for ContainerSource {
             ports,
 
             volume_mounts: self.volume_mounts.unwrap_or_default(),
+
+            security_context: self.security_context.or_else(|| params.security_context.clone()),
         })
     }
 }
@@ -8,7 +8,7 @@ use crate::util::{Build, Require};
 
 use super::container::{ContainerSource, ContainerBuildParams};
 
-#[derive(Deserialize, Merge, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Merge, Clone, Default)]
 #[serde(default, rename_all = "camelCase")]
 pub struct CronJobSource {
     pub schedule: Option<String>,
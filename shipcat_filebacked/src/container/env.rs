@@ -6,7 +6,7 @@ use shipcat_definitions::structs::EnvVars;
 
 use crate::util::{Build, RelaxedString};
 
-#[derive(Deserialize, Clone, Default, Debug, PartialEq)]
+#[derive(Deserialize, JsonSchema, Clone, Default, Debug, PartialEq)]
 pub struct EnvVarsSource(BTreeMap<String, RelaxedString>);
 
 impl Build<EnvVars, ()> for EnvVarsSource {
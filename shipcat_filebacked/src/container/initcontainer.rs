@@ -4,7 +4,7 @@ use shipcat_definitions::structs::Container;
 use crate::util::{Build, Require};
 use super::container::{ContainerSource, ContainerBuildParams};
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Clone, Default)]
 pub struct InitContainerSource(ContainerSource);
 
 impl Build<Container, ContainerBuildParams> for InitContainerSource {
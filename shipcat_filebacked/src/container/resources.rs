@@ -3,7 +3,7 @@ use shipcat_definitions::structs::resources::{ResourceRequirements, Resources};
 
 use crate::util::{Build, RelaxedString, Require};
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Clone, Default)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 pub struct ResourceRequirementsSource {
     pub requests: ResourcesSource,
@@ -21,7 +21,7 @@ impl Build<ResourceRequirements<String>, ()> for ResourceRequirementsSource {
     }
 }
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Clone, Default)]
 #[serde(default, rename_all = "camelCase", deny_unknown_fields)]
 pub struct ResourcesSource {
     pub cpu: Option<RelaxedString>,
@@ -4,7 +4,7 @@ use shipcat_definitions::structs::Container;
 use crate::util::Build;
 use super::container::{ContainerSource, ContainerBuildParams};
 
-#[derive(Deserialize, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Clone, Default)]
 pub struct SidecarSource(ContainerSource);
 
 impl Build<Container, ContainerBuildParams> for SidecarSource {
@@ -7,7 +7,7 @@ use shipcat_definitions::structs::autoscaling::AutoScaling;
 use crate::util::{Build, Require};
 use super::container::{ContainerSource, ContainerBuildParams};
 
-#[derive(Deserialize, Merge, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Merge, Clone, Default)]
 #[serde(default, rename_all = "camelCase")]
 pub struct WorkerSource {
     pub replica_count: Option<u32>,
@@ -9,7 +9,7 @@ use super::authorization::AuthorizationSource;
 use super::util::{Build, Enabled};
 
 /// Main manifest, deserialized from `shipcat.yml`.
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Deserialize, JsonSchema, Default, Merge, Clone)]
 #[serde(default, deny_unknown_fields)]
 pub struct KongSource {
     pub upstream_url: Option<String>,
@@ -6,6 +6,8 @@ extern crate merge_derive;
 extern crate log;
 #[macro_use]
 extern crate error_chain;
+#[macro_use]
+extern crate schemars;
 
 // Structs
 mod authorization;
@@ -18,13 +20,29 @@ mod kong;
 mod load;
 mod util;
 
-use manifest::ManifestSource;
+/// Pluggable file sources for the working tree and git revisions
+mod source;
+pub use crate::source::{FileSource, DiskSource, GitSource};
+
+use std::path::Path;
+use manifest::{ManifestSource, ManifestFragment, ManifestOverrides};
+use schemars::schema::RootSchema;
 use shipcat_definitions::{Config, Manifest, Region, Result, BaseManifest};
 
 pub fn load_manifest(service: &str, conf: &Config, reg: &Region) -> Result<Manifest> {
     ManifestSource::load_manifest(service, conf, reg)
 }
 
+pub fn load_manifest_from(src: &dyn FileSource, service: &str, conf: &Config, reg: &Region) -> Result<Manifest> {
+    ManifestSource::load_manifest_from(src, service, conf, reg)
+}
+
+/// Read the raw `shipcat.conf` from a file source
+pub fn load_config_from(src: &dyn FileSource) -> Result<Config> {
+    let data = src.read(&Path::new(".").join("shipcat.conf"))?;
+    Config::from_yaml(&data)
+}
+
 pub fn load_metadata(service: &str, conf: &Config, reg: &Region) -> Result<SimpleManifest> {
     ManifestSource::load_metadata(service, conf, reg)
 }
@@ -33,6 +51,29 @@ pub fn all(conf: &Config) -> Result<Vec<BaseManifest>> {
     ManifestSource::all(conf)
 }
 
+pub fn all_from(src: &dyn FileSource, conf: &Config) -> Result<Vec<BaseManifest>> {
+    ManifestSource::all_from(src, conf)
+}
+
 pub fn available(conf: &Config, reg: &Region) -> Result<Vec<SimpleManifest>> {
     ManifestSource::available(conf, reg)
 }
+
+pub fn available_from(src: &dyn FileSource, conf: &Config, reg: &Region) -> Result<Vec<SimpleManifest>> {
+    ManifestSource::available_from(src, conf, reg)
+}
+
+/// JSON Schema for `shipcat.yml`
+pub fn manifest_schema() -> RootSchema {
+    schema_for!(ManifestSource)
+}
+
+/// JSON Schema for region and environment override files
+pub fn overrides_schema() -> RootSchema {
+    schema_for!(ManifestOverrides)
+}
+
+/// JSON Schema for shared fragments listed in `extends`
+pub fn fragment_schema() -> RootSchema {
+    schema_for!(ManifestFragment)
+}
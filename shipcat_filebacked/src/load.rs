@@ -1,32 +1,39 @@
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use merge::Merge;
 use serde::de::DeserializeOwned;
-use shipcat_definitions::{Config, Manifest, Region, Result};
-use walkdir::WalkDir;
+use shipcat_definitions::{Config, Manifest, Region, Result, ResultExt};
 
-use crate::manifest::{ManifestDefaults, ManifestOverrides, ManifestSource};
+use crate::manifest::{ManifestDefaults, ManifestFragment, ManifestOverrides, ManifestSource};
+use crate::source::{DiskSource, FileSource};
 use super::{SimpleManifest, BaseManifest};
 use super::authorization::{AuthorizationSource};
 use super::util::{Build, Enabled};
 
 impl ManifestSource {
     pub fn load_manifest(service: &str, conf: &Config, reg: &Region) -> Result<Manifest> {
-        let manifest = ManifestSource::load_merged(service, conf, reg)?;
+        Self::load_manifest_from(&DiskSource, service, conf, reg)
+    }
+
+    pub fn load_manifest_from(src: &dyn FileSource, service: &str, conf: &Config, reg: &Region) -> Result<Manifest> {
+        let manifest = ManifestSource::load_merged(src, service, conf, reg)?;
+        let manifest = manifest.load_templates(src, service)?;
         manifest.build(&(conf.clone(), reg.clone()))
     }
 
     pub fn load_metadata(service: &str, conf: &Config, reg: &Region) -> Result<SimpleManifest> {
-        let manifest = ManifestSource::load_merged(service, conf, reg)?;
+        Self::load_metadata_from(&DiskSource, service, conf, reg)
+    }
+
+    fn load_metadata_from(src: &dyn FileSource, service: &str, conf: &Config, reg: &Region) -> Result<SimpleManifest> {
+        let manifest = ManifestSource::load_merged(src, service, conf, reg)?;
         manifest.build_simple(&conf, &reg)
     }
 
-    fn load_merged(service: &str, conf: &Config, reg: &Region) -> Result<Self> {
+    fn load_merged(src: &dyn FileSource, service: &str, conf: &Config, reg: &Region) -> Result<Self> {
         let dir = Self::services_dir().join(service);
 
-        if !dir.exists() {
+        if !src.is_dir(&dir) {
             bail!("Service folder {} does not exist", dir.display())
         }
 
@@ -36,52 +43,41 @@ impl ManifestSource {
 
         let source_path = Self::services_dir().join(service).join("shipcat.yml");
         debug!("Loading service manifest from {:?}", source_path);
-        let source = ManifestSource::read_from(&source_path)?;
+        let source = ManifestSource::read_from(src, &source_path)?;
+        let source = source.merge_fragments(src, &source_path)?;
         let mut manifest = defaults.merge_source(source);
 
         let env_path = dir.join(format!("{}.yml", reg.environment.to_string()));
-        if env_path.is_file() {
+        if src.is_file(&env_path) {
             debug!("Loading service overrides from {:?}", env_path);
-            let env = ManifestOverrides::read_from(&env_path)?;
+            let env = ManifestOverrides::read_from(src, &env_path)?;
             manifest = manifest.merge_overrides(env);
         }
 
         let region_path = dir.join(format!("{}.yml", reg.name));
-        if region_path.is_file() {
+        if src.is_file(&region_path) {
             debug!("Loading service overrides from {:?}", region_path);
-            let region = ManifestOverrides::read_from(&region_path)?;
+            let region = ManifestOverrides::read_from(src, &region_path)?;
             manifest = manifest.merge_overrides(region);
         }
 
         Ok(manifest)
     }
 
-    fn all_names() -> Vec<String> {
-        let mut res : Vec<_> = WalkDir::new(&ManifestSource::services_dir())
-            .min_depth(1)
-            .max_depth(1)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_dir())
-            .map(|e| {
-                let mut cmps = e.path().components();
-                cmps.next(); // .
-                cmps.next(); // services
-                let svccomp = cmps.next().unwrap();
-                let svcname = svccomp.as_os_str().to_str().unwrap();
-                svcname.to_string()
-            })
-            .collect();
-        res.sort();
-        res
+    fn all_names(src: &dyn FileSource) -> Result<Vec<String>> {
+        src.subdirs(&Self::services_dir())
     }
 
     pub fn all(conf: &Config) -> Result<Vec<BaseManifest>> {
+        Self::all_from(&DiskSource, conf)
+    }
+
+    pub fn all_from(src: &dyn FileSource, conf: &Config) -> Result<Vec<BaseManifest>> {
         let mut all = vec![];
-        for service in Self::all_names() {
+        for service in Self::all_names(src)? {
             let source_path = Self::services_dir().join(service).join("shipcat.yml");
             debug!("Loading service manifest from {:?}", source_path);
-            let source = ManifestSource::read_from(&source_path)?;
+            let source = ManifestSource::read_from(src, &source_path)?;
             let manifest = source.build_base(conf)?;
             all.push(manifest);
         }
@@ -89,9 +85,13 @@ impl ManifestSource {
     }
 
     pub fn available(conf: &Config, reg: &Region) -> Result<Vec<SimpleManifest>> {
+        Self::available_from(&DiskSource, conf, reg)
+    }
+
+    pub fn available_from(src: &dyn FileSource, conf: &Config, reg: &Region) -> Result<Vec<SimpleManifest>> {
         let mut available = vec![];
-        for service in Self::all_names() {
-            let manifest = Self::load_metadata(&service, conf, reg)?;
+        for service in Self::all_names(src)? {
+            let manifest = Self::load_metadata_from(src, &service, conf, reg)?;
             if manifest.enabled && !manifest.external {
                 available.push(manifest);
             }
@@ -102,6 +102,106 @@ impl ManifestSource {
     fn services_dir() -> PathBuf {
         Path::new(".").join("services")
     }
+
+    /// Inline the config templates listed in `configs`
+    ///
+    /// Templates are read from the service folder, falling back to `templates/`.
+    fn load_templates(mut self, src: &dyn FileSource, service: &str) -> Result<Self> {
+        if let Some(configs) = &mut self.overrides.configs {
+            for f in &mut configs.files {
+                f.value = Some(read_template_file(src, service, &f.name)?);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Merge the fragments listed in `extends` below the service's own file
+    fn merge_fragments(mut self, src: &dyn FileSource, source_path: &PathBuf) -> Result<Self> {
+        let mut chain = vec![normalize(source_path)?];
+        let fragments = ManifestFragment::load_all(src, &self.extends, &mut chain)?;
+        self.overrides = fragments.merge(self.overrides);
+        Ok(self)
+    }
+}
+
+impl ManifestFragment {
+    /// Load and merge a list of fragments, later fragments taking precedence
+    ///
+    /// The `chain` holds the files currently being extended, and is used to
+    /// detect cycles and to report where a broken fragment was pulled in from.
+    fn load_all(src: &dyn FileSource, extends: &[String], chain: &mut Vec<PathBuf>) -> Result<ManifestOverrides> {
+        let mut merged = ManifestOverrides::default();
+        for name in extends {
+            merged = merged.merge(Self::load(src, name, chain)?);
+        }
+        Ok(merged)
+    }
+
+    fn load(src: &dyn FileSource, name: &str, chain: &mut Vec<PathBuf>) -> Result<ManifestOverrides> {
+        let path = normalize(&Path::new(".").join(name))?;
+        let parent = match chain.last() {
+            Some(p) => p.clone(),
+            None => bail!("Manifest fragment {} is not extended from a file", name),
+        };
+        if chain.contains(&path) {
+            let cycle = chain.iter()
+                .chain(std::iter::once(&path))
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>();
+            bail!("Cyclic manifest fragments: {}", cycle.join(" -> "));
+        }
+        debug!("Loading manifest fragment from {:?}", path);
+        let fragment = ManifestFragment::read_from(src, &path)
+            .chain_err(|| format!("Invalid fragment {} extended from {}", path.display(), parent.display()))?;
+
+        chain.push(path);
+        let base = Self::load_all(src, &fragment.extends, chain)?;
+        chain.pop();
+        Ok(base.merge(fragment.overrides))
+    }
+}
+
+/// Resolve `.` and `..` in a path relative to the manifest root
+///
+/// Done lexically as fragments may be read from git rather than from disk.
+fn normalize(path: &Path) -> Result<PathBuf> {
+    let mut clean = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !clean.pop() {
+                    bail!("{} points outside of the manifest directory", path.display());
+                }
+            }
+            Component::Normal(p) => clean.push(p),
+            Component::RootDir | Component::Prefix(_) => bail!("{} must be a relative path", path.display()),
+        }
+    }
+    Ok(Path::new(".").join(clean))
+}
+
+fn read_template_file(src: &dyn FileSource, svc: &str, tmpl: &str) -> Result<String> {
+    // try to read file from ./services/{svc}/{tmpl} into `tpl` sting
+    let pth = Path::new(".").join("services").join(svc).join(tmpl);
+    let gpth = Path::new(".").join("templates").join(tmpl);
+    let found_pth = if src.is_file(&pth) {
+        debug!("Reading template in {}", pth.display());
+        pth
+    } else {
+        if !src.is_file(&gpth) {
+            bail!(
+                "Template {} does not exist in neither {} nor {}",
+                tmpl,
+                pth.display(),
+                gpth.display()
+            );
+        }
+        debug!("Reading template in {}", gpth.display());
+        gpth
+    };
+    // read the template - should work now
+    src.read(&found_pth)
 }
 
 impl ManifestDefaults {
@@ -138,21 +238,19 @@ trait ManifestFile
 where
     Self: Sized,
 {
-    fn read_from(path: &PathBuf) -> Result<Self>;
+    fn read_from(src: &dyn FileSource, path: &PathBuf) -> Result<Self>;
 }
 
 impl<T> ManifestFile for T
 where
     T: DeserializeOwned,
 {
-    fn read_from(path: &PathBuf) -> Result<Self> {
+    fn read_from(src: &dyn FileSource, path: &PathBuf) -> Result<Self> {
         trace!("Reading manifest in {}", path.display());
-        if !path.exists() {
+        if !src.is_file(path) {
             bail!("Manifest file {} does not exist", path.display())
         }
-        let mut f = File::open(&path)?;
-        let mut data = String::new();
-        f.read_to_string(&mut data)?;
+        let data = src.read(path)?;
         if data.is_empty() {
             bail!("Manifest file {} is empty", path.display());
         }
@@ -168,7 +266,8 @@ mod tests {
     use std::path::{Path};
 
     use shipcat_definitions::{Config};
-    use super::{ManifestSource};
+    use crate::source::{DiskSource, GitSource};
+    use super::{normalize, ManifestFragment, ManifestSource};
 
     fn setup() {
         let pwd = env::current_dir().unwrap();
@@ -200,6 +299,72 @@ mod tests {
         assert_eq!(manifest.image, Some("quay.io/babylonhealth/fake-ask".into()));
     }
 
+    #[test]
+    fn load_fake_storage_fragments() {
+        setup();
+
+        let conf = Config::read().unwrap();
+        let region = conf.get_region("dev-uk").unwrap();
+
+        let manifest = ManifestSource::load_manifest("fake-storage", &conf, &region).unwrap();
+        // livenessProbe and labels only exist in the fragment
+        assert!(manifest.livenessProbe.is_some());
+        assert_eq!(manifest.labels["fragment"], "web".to_string());
+        // resources in shipcat.yml take precedence over the fragment
+        let resources = manifest.resources.unwrap();
+        assert_eq!(resources.requests.cpu, "100m".to_string());
+        assert_eq!(resources.limits.memory, "1Gi".to_string());
+    }
+
+    #[test]
+    fn cyclic_fragments() {
+        setup();
+
+        let mut chain = vec![Path::new(".").join("services").join("fake-storage").join("shipcat.yml")];
+        let res = ManifestFragment::load_all(&DiskSource, &["templates/fragments/cycle-a.yml".into()], &mut chain);
+        let err = res.err().unwrap().to_string();
+        assert!(err.contains("Cyclic manifest fragments"));
+        assert!(err.contains("cycle-b.yml"));
+    }
+
+    #[test]
+    fn cyclic_fragments_through_parent_dirs() {
+        setup();
+
+        let mut chain = vec![Path::new(".").join("templates").join("fragments").join("web.yml")];
+        let res = ManifestFragment::load_all(&DiskSource, &["templates/fragments/../fragments/web.yml".into()], &mut chain);
+        assert!(res.err().unwrap().to_string().contains("Cyclic manifest fragments"));
+    }
+
+    #[test]
+    fn normalize_paths() {
+        let expected = Path::new(".").join("templates").join("b.yml");
+        assert_eq!(normalize(Path::new("./templates/a/../b.yml")).unwrap(), expected);
+        assert_eq!(normalize(Path::new("templates/./b.yml")).unwrap(), expected);
+        assert!(normalize(Path::new("../b.yml")).is_err());
+        assert!(normalize(Path::new("/etc/b.yml")).is_err());
+    }
+
+    #[test]
+    fn load_fake_ask_from_git() {
+        setup();
+
+        let src = GitSource::new("HEAD");
+        let conf = crate::load_config_from(&src).unwrap();
+        let region = conf.get_region("dev-uk").unwrap();
+
+        // same manifest as the working tree, without touching it
+        let manifest = ManifestSource::load_manifest_from(&src, "fake-ask", &conf, &region).unwrap();
+        let local = ManifestSource::load_manifest_from(&DiskSource, "fake-ask", &conf, &region).unwrap();
+        assert_eq!(manifest.name, "fake-ask".to_string());
+        assert_eq!(manifest.version, local.version);
+        assert!(manifest.configs.unwrap().files[0].value.is_some()); // templates inlined
+
+        let all = ManifestSource::all_from(&src, &conf).unwrap();
+        assert!(all.iter().any(|mf| mf.name == "fake-ask"));
+        assert!(ManifestSource::load_manifest_from(&src, "not-a-service", &conf, &region).is_err());
+    }
+
     #[test]
     fn all() {
         setup();
@@ -3,9 +3,10 @@ use std::collections::BTreeMap;
 
 use shipcat_definitions::structs::{
     autoscaling::AutoScaling, security::DataHandling, tolerations::Tolerations, volume::Volume,
-    ConfigMap, Dependency, Gate, HealthCheck, HostAlias,
-    Kafka, LifeCycle, Metadata, PersistentVolume, Port, Probe, Rbac,
-    RollingUpdate, VaultOpts, VolumeMount,
+    ConfigMap, Dependency, Gate, HealthCheck, HostAlias, Ingress,
+    Kafka, LifeCycle, Mesh, Metadata, PersistentVolume, Port, Probe, Rbac,
+    RollingUpdate, StatefulSet, VaultOpts, VolumeMount, Workload,
+    PodSecurityContext, SecurityContext, SecurityExemption,
 };
 use shipcat_definitions::{Config, Manifest, BaseManifest, Region, Result};
 
@@ -15,74 +16,153 @@ use super::kong::{KongSource, KongBuildParams};
 use super::util::{Build, Enabled, RelaxedString, Require};
 
 /// Main manifest, deserialized from `shipcat.yml`.
-#[derive(Deserialize, Default)]
+#[derive(Deserialize, JsonSchema, Default)]
 #[serde(default, rename_all = "camelCase")]
 pub struct ManifestSource {
+    /// Name of the service, matching its folder in `services/`
     pub name: Option<String>,
+    /// Service is external, and only a non-kube reference
     pub external: bool,
+    /// Service is disabled in all regions
     pub disabled: bool,
+    /// Regions to deploy this service to
     pub regions: Vec<String>,
+    /// Important contacts and other metadata for the service
     pub metadata: Option<Metadata>,
+    /// Shared fragments to merge below this file, e.g. `templates/fragments/jvm.yml`
+    pub extends: Vec<String>,
+
+    #[serde(flatten)]
+    pub overrides: ManifestOverrides,
+}
+
+/// Shared manifest fragment, deserialized from files listed in `extends`.
+///
+/// Fragments are merged below the file extending them, in the order they are listed.
+#[derive(Deserialize, JsonSchema, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ManifestFragment {
+    /// Other fragments to merge below this one
+    pub extends: Vec<String>,
 
     #[serde(flatten)]
     pub overrides: ManifestOverrides,
 }
 
 /// Manifest overrides, deserialized from `dev-uk.yml`/`prod.yml` etc.
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Deserialize, JsonSchema, Default, Merge, Clone)]
 #[serde(default, deny_unknown_fields, rename_all = "camelCase")]
 pub struct ManifestOverrides {
+    /// Whether the service should be public
     pub publicly_accessible: Option<bool>,
+    /// Image name of the docker image to run
     pub image: Option<ImageNameSource>,
+    /// Optional uncompressed image size, used to estimate rollout wait times
     pub image_size: Option<u32>,
+    /// Version aka. tag of docker image to run
     pub version: Option<ImageTagSource>,
+    /// Command to use for the docker image
     pub command: Option<Vec<String>>,
+    /// Data sources and handling strategies
     pub data_handling: Option<DataHandling>,
+    /// Language the service is written in
     pub language: Option<String>,
+    /// Kubernetes resource limits and requests
     pub resources: Option<ResourceRequirementsSource>,
+    /// Kubernetes Secret Files to inject
     pub secret_files: BTreeMap<String, String>,
+    /// Config files to inline in a kubernetes `ConfigMap`
     pub configs: Option<ConfigMap>,
+    /// Vault options
+    ///
+    /// DEPRECATED. Should only be set in rare cases.
     pub vault: Option<VaultOpts>,
+    /// Http Port to expose in the kubernetes `Service`
     pub http_port: Option<u32>,
+    /// Ports to open
     pub ports: Option<Vec<Port>>,
+    /// Externally exposed port
     pub external_port: Option<u32>,
+    /// Health check parameters
+    ///
+    /// DEPRECATED. Should use `readinessProbe`.
     pub health: Option<HealthCheck>,
+    /// Service dependencies
     pub dependencies: Option<Vec<Dependency>>,
+    /// Service mesh settings
+    pub mesh: Option<Mesh>,
+    /// Worker `Deployment` objects to additionally include
     pub workers: Option<Vec<WorkerSource>>,
+    /// Sidecars to inject into every kubernetes `Deployment`
     pub sidecars: Option<Vec<SidecarSource>>,
+    /// `readinessProbe` for kubernetes
     pub readiness_probe: Option<Probe>,
+    /// `livenessProbe` for kubernetes
     pub liveness_probe: Option<Probe>,
+    /// Container lifecycle events for kubernetes
     pub lifecycle: Option<LifeCycle>,
+    /// Rolling update Deployment parameters
     pub rolling_update: Option<RollingUpdate>,
+    /// `HorizontalPodAutoScaler` parameters for kubernetes
     pub auto_scaling: Option<AutoScaling>,
+    /// Toleration parameters for kubernetes
     pub tolerations: Option<Vec<Tolerations>>,
+    /// Host aliases to inject in /etc/hosts in every kubernetes `Pod`
     pub host_aliases: Option<Vec<HostAlias>>,
+    /// `initContainer` list for every kubernetes `Pod`
     pub init_containers: Option<Vec<InitContainerSource>>,
+    /// Volumes that can be mounted in every kubernetes `Pod`
     pub volumes: Option<Vec<Volume>>,
+    /// Volumes to mount to every kubernetes `Pod`
     pub volume_mounts: Option<Vec<VolumeMount>>,
+    /// PersistentVolume injected in helm chart
     pub persistent_volumes: Option<Vec<PersistentVolume>>,
+    /// Kubernetes workload kind, `Deployment` or `StatefulSet`
+    pub workload: Option<Workload>,
+    /// StatefulSet parameters
+    pub stateful_set: Option<StatefulSet>,
+    /// Cronjob images to run as kubernetes `CronJob` objects
     pub cron_jobs: Option<Vec<CronJobSource>>,
+    /// Job images to run as kubernetes `Job` objects
     pub jobs: Option<Vec<JobSource>>,
+    /// Annotations to set on `Service` objects
     pub service_annotations: BTreeMap<String, String>,
+    /// Labels for every kubernetes object
     pub labels: BTreeMap<String, RelaxedString>,
+    /// Gate config
     pub gate: Option<Gate>,
+    /// Hosts to override kong hosts
     pub hosts: Option<Vec<String>>,
+    /// Kafka config
     pub kafka: Option<Kafka>,
+    /// Load balancer source ranges
     pub source_ranges: Option<Vec<String>>,
+    /// Role-Based Access Control
     pub rbac: Option<Vec<Rbac>>,
+    /// Security settings for the main container
+    pub security_context: Option<SecurityContext>,
+    /// Security settings for all containers in the pods
+    pub pod_security_context: Option<PodSecurityContext>,
+    /// Exemptions from the region's pod security rules
+    pub security_exemptions: Option<Vec<SecurityExemption>>,
 
     #[serde(flatten)]
     pub defaults: ManifestDefaults,
 }
 
 /// Global/regional manifest defaults, deserialized from `shipcat.conf` etc.
-#[derive(Deserialize, Default, Merge, Clone)]
+#[derive(Deserialize, JsonSchema, Default, Merge, Clone)]
 #[serde(default, deny_unknown_fields, rename_all = "camelCase")]
 pub struct ManifestDefaults {
+    /// Image prefix used when `image` is not set
     pub image_prefix: Option<String>,
+    /// Chart to use for the service
     pub chart: Option<String>,
+    /// Kubernetes replication count
     pub replica_count: Option<u32>,
+    /// Environment variables to inject
     pub env: EnvVarsSource,
+    /// Kong config
     pub kong: Enabled<KongSource>,
 }
 
@@ -93,13 +173,19 @@ impl Build<Manifest, (Config, Region)> for ManifestSource {
         let name = simple.base.name;
         let data_handling = self.build_data_handling();
         let kafka = self.build_kafka(&name, region);
-        let configs = self.build_configs(&name)?;
+        let configs = self.build_configs();
+        let ingress = match (&simple.kong, &region.ingress) {
+            (Some(k), Some(_)) => Some(Ingress::from_kong(k, region)?),
+            _ => None,
+        };
 
         let overrides = self.overrides;
         let defaults = overrides.defaults;
 
+        let pod_security = region.podSecurity.clone().unwrap_or_default();
         let container_build_params = ContainerBuildParams {
             main_envs: defaults.env.clone(),
+            security_context: pod_security.securityContext.clone(),
         };
 
         Ok(Manifest {
@@ -132,6 +218,7 @@ impl Build<Manifest, (Config, Region)> for ManifestSource {
             externalPort: overrides.external_port,
             health: overrides.health,
             dependencies: overrides.dependencies.unwrap_or_default(),
+            mesh: overrides.mesh,
             workers: overrides.workers.unwrap_or_default().build(&container_build_params)?,
             sidecars: overrides.sidecars.unwrap_or_default().build(&container_build_params)?,
             readinessProbe: overrides.readiness_probe,
@@ -145,6 +232,8 @@ impl Build<Manifest, (Config, Region)> for ManifestSource {
             volumes: overrides.volumes.unwrap_or_default(),
             volumeMounts: overrides.volume_mounts.unwrap_or_default(),
             persistentVolumes: overrides.persistent_volumes.unwrap_or_default(),
+            workload: overrides.workload.unwrap_or_default(),
+            statefulSet: overrides.stateful_set,
             cronJobs: overrides.cron_jobs.unwrap_or_default().build(&container_build_params)?,
             jobs: overrides.jobs.unwrap_or_default().build(&container_build_params)?,
             serviceAnnotations: overrides.service_annotations,
@@ -155,11 +244,18 @@ impl Build<Manifest, (Config, Region)> for ManifestSource {
             kafka: kafka,
             sourceRanges: overrides.source_ranges.unwrap_or_default(),
             rbac: overrides.rbac.unwrap_or_default(),
+            securityContext: overrides.security_context.or_else(|| pod_security.securityContext.clone()),
+            podSecurityContext: overrides.pod_security_context.or_else(|| pod_security.podSecurityContext.clone()),
+            securityExemptions: overrides.security_exemptions.unwrap_or_default(),
 
             region: region.name.clone(),
             environment: region.environment.to_string(),
             namespace: region.namespace.clone(),
             secrets: Default::default(),
+            networkPolicy: None,
+            ingress,
+            serviceMesh: None,
+            imageDigest: None,
             kind: Default::default(),
         })
     }
@@ -252,16 +348,9 @@ impl ManifestSource {
     }
 
     // TODO: Extract ConfigsSource
-    fn build_configs(&self, service: &String) -> Result<Option<ConfigMap>> {
-        let original = &self.overrides.configs;
-        if original.is_none() {
-            return Ok(None);
-        }
-        let mut configs = original.clone().unwrap();
-        for f in &mut configs.files {
-            f.value = Some(read_template_file(service, &f.name)?);
-        }
-        Ok(Some(configs))
+    fn build_configs(&self) -> Option<ConfigMap> {
+        // values are inlined by `load_templates` when loading
+        self.overrides.configs.clone()
     }
 
     pub(crate) fn merge_overrides(mut self, other: ManifestOverrides) -> Self {
@@ -270,35 +359,6 @@ impl ManifestSource {
     }
 }
 
-fn read_template_file(svc: &str, tmpl: &str) -> Result<String> {
-    use std::fs::File;
-    use std::io::prelude::*;
-    use std::path::Path;
-    // try to read file from ./services/{svc}/{tmpl} into `tpl` sting
-    let pth = Path::new(".").join("services").join(svc).join(tmpl);
-    let gpth = Path::new(".").join("templates").join(tmpl);
-    let found_pth = if pth.exists() {
-        debug!("Reading template in {}", pth.display());
-        pth
-    } else {
-        if !gpth.exists() {
-            bail!(
-                "Template {} does not exist in neither {} nor {}",
-                tmpl,
-                pth.display(),
-                gpth.display()
-            );
-        }
-        debug!("Reading template in {}", gpth.display());
-        gpth
-    };
-    // read the template - should work now
-    let mut f = File::open(&found_pth)?;
-    let mut data = String::new();
-    f.read_to_string(&mut data)?;
-    Ok(data)
-}
-
 impl ManifestDefaults {
     pub(crate) fn merge_source(self, mut other: ManifestSource) -> ManifestSource {
         other.overrides.defaults = self.merge(other.overrides.defaults);
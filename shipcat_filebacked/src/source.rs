@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use shipcat_definitions::Result;
+use walkdir::WalkDir;
+
+/// Where manifests, fragments and the config are read from
+///
+/// Paths are always relative to the root of the manifests repository.
+pub trait FileSource: Send + Sync {
+    /// Read a file to a string
+    fn read(&self, path: &Path) -> Result<String>;
+    /// Whether a file exists
+    fn is_file(&self, path: &Path) -> bool;
+    /// Whether a directory exists
+    fn is_dir(&self, path: &Path) -> bool;
+    /// Sorted names of the directories directly inside a directory
+    fn subdirs(&self, path: &Path) -> Result<Vec<String>>;
+}
+
+/// Files in the working tree
+pub struct DiskSource;
+
+impl FileSource for DiskSource {
+    fn read(&self, path: &Path) -> Result<String> {
+        Ok(fs::read_to_string(path)?)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn subdirs(&self, path: &Path) -> Result<Vec<String>> {
+        let mut res : Vec<_> = WalkDir::new(path)
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_dir())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect();
+        res.sort();
+        Ok(res)
+    }
+}
+
+/// Files at a git revision, read from the object database
+///
+/// Never touches the working tree or the index, so it is safe to use
+/// while there are local changes, and from several threads at once.
+pub struct GitSource {
+    /// Any revision understood by git, e.g. `master` or a sha
+    pub rev: String,
+}
+
+impl GitSource {
+    pub fn new(rev: &str) -> Self {
+        GitSource { rev: rev.into() }
+    }
+
+    /// Object name of a path at the revision, relative to the current directory
+    fn object(&self, path: &Path) -> String {
+        let pth = path.to_string_lossy();
+        let pth = pth.trim_start_matches("./");
+        format!("{}:./{}", self.rev, pth)
+    }
+
+    fn git(&self, args: &[&str]) -> Result<(String, bool)> {
+        debug!("git {}", args.join(" "));
+        let s = Command::new("git").args(args).output()?;
+        let out : String = String::from_utf8_lossy(&s.stdout).into();
+        Ok((out, s.status.success()))
+    }
+
+    fn object_type(&self, path: &Path) -> Option<String> {
+        match self.git(&["cat-file", "-t", &self.object(path)]) {
+            Ok((out, true)) => Some(out.trim().to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl FileSource for GitSource {
+    fn read(&self, path: &Path) -> Result<String> {
+        let (out, success) = self.git(&["cat-file", "blob", &self.object(path)])?;
+        if !success {
+            bail!("{} does not exist in git revision {}", path.display(), self.rev);
+        }
+        Ok(out)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.object_type(path).map_or(false, |t| t == "blob")
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.object_type(path).map_or(false, |t| t == "tree")
+    }
+
+    fn subdirs(&self, path: &Path) -> Result<Vec<String>> {
+        let (out, success) = self.git(&["ls-tree", &self.object(path)])?;
+        if !success {
+            bail!("{} does not exist in git revision {}", path.display(), self.rev);
+        }
+        // lines are of the form: <mode> SP <type> SP <object> TAB <name>
+        let mut res = out.lines()
+            .filter_map(|l| {
+                let mut parts = l.splitn(2, '\t');
+                let meta = parts.next()?;
+                let name = parts.next()?;
+                if meta.split_whitespace().nth(1) == Some("tree") {
+                    Some(name.to_string())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        res.sort();
+        Ok(res)
+    }
+}
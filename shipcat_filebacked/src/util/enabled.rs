@@ -4,7 +4,7 @@ use shipcat_definitions::Result;
 use super::Build;
 
 /// Enabled wraps a struct and adds an `enabled` field.
-#[derive(Deserialize, Default, Clone, PartialEq)]
+#[derive(Deserialize, JsonSchema, Default, Clone, PartialEq)]
 #[cfg_attr(test, derive(Debug, Copy))]
 #[serde(default)]
 pub struct Enabled<T> {
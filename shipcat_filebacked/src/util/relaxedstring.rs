@@ -1,5 +1,8 @@
 use std::fmt;
 use serde::de::{Visitor, Deserialize, Deserializer, Error};
+use schemars::JsonSchema;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject, SingleOrVec};
 
 use super::Build;
 
@@ -30,6 +33,25 @@ impl<'de> Deserialize<'de> for RelaxedString {
     }
 }
 
+/// Schema matching the types accepted by `RelaxedStringVisitor`
+impl JsonSchema for RelaxedString {
+    fn schema_name() -> String {
+        "RelaxedString".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(SingleOrVec::Vec(vec![
+                InstanceType::String,
+                InstanceType::Number,
+                InstanceType::Boolean,
+                InstanceType::Null,
+            ])),
+            ..Default::default()
+        }.into()
+    }
+}
+
 struct RelaxedStringVisitor;
 
 macro_rules! visit_tostring {